@@ -0,0 +1,12 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use runner::bench::Window;
+
+fn build_msg(c: &mut Criterion) {
+    let mut window = Window::new(1_000_000, 120);
+    c.bench_function("build_msg 1M lines", |b| {
+        b.iter(|| black_box(window.build_msg()))
+    });
+}
+
+criterion_group!(benches, build_msg);
+criterion_main!(benches);
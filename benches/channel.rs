@@ -0,0 +1,158 @@
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use runner::{Line, Stream};
+
+/// Lines the command writes, half on each stream.
+const LINES: usize = 1_000_000;
+
+/// The channel between the reader threads and the main thread.
+trait Channel {
+    type Sender: Clone + Send + 'static;
+    type Receiver;
+
+    fn new() -> (Self::Sender, Self::Receiver);
+    fn send(sender: &Self::Sender, line: Line);
+    fn recv(receiver: &Self::Receiver) -> Option<Line>;
+}
+
+/// `std::sync::mpsc`, which the runs use.
+struct Std;
+
+impl Channel for Std {
+    type Sender = mpsc::Sender<Line>;
+    type Receiver = mpsc::Receiver<Line>;
+
+    fn new() -> (Self::Sender, Self::Receiver) {
+        mpsc::channel()
+    }
+
+    fn send(sender: &Self::Sender, line: Line) {
+        sender.send(line).expect("receiving");
+    }
+
+    fn recv(receiver: &Self::Receiver) -> Option<Line> {
+        receiver.recv().ok()
+    }
+}
+
+/// `crossbeam_channel::unbounded`.
+struct Crossbeam;
+
+impl Channel for Crossbeam {
+    type Sender = crossbeam_channel::Sender<Line>;
+    type Receiver = crossbeam_channel::Receiver<Line>;
+
+    fn new() -> (Self::Sender, Self::Receiver) {
+        crossbeam_channel::unbounded()
+    }
+
+    fn send(sender: &Self::Sender, line: Line) {
+        sender.send(line).expect("receiving");
+    }
+
+    fn recv(receiver: &Self::Receiver) -> Option<Line> {
+        receiver.recv().ok()
+    }
+}
+
+/// Sends the lines of `pipe` as a reader thread does.
+fn _read<C: Channel>(
+    pipe: impl Read + Send + 'static,
+    stream: Stream,
+    sender: C::Sender,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut pipe = BufReader::with_capacity(64 * 1024, pipe);
+        let mut buf = Vec::new();
+        while pipe.read_until(b'\n', &mut buf).expect("reading") > 0 {
+            let line = String::from_utf8_lossy(&buf);
+            let line = Line {
+                line: line.trim_end_matches('\n').into(),
+                stream: stream.clone(),
+                crlf: false,
+            };
+            C::send(&sender, line);
+            buf.clear();
+        }
+    })
+}
+
+/// Runs a command writing [`LINES`] lines as fast as it can, on stdout and
+/// stderr at once, and returns how many lines came through the channel.
+fn run_command<C: Channel>() -> usize {
+    let script = format!("seq {0} & seq {0} >&2; wait", LINES / 2);
+    let mut child = Command::new("sh")
+        .args(["-c", &script])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawning sh");
+    let (sender, receiver) = C::new();
+    let stdout = child.stdout.take().expect("piped");
+    let stderr = child.stderr.take().expect("piped");
+    let readers = [
+        _read::<C>(stdout, Stream::Stdout, sender.clone()),
+        _read::<C>(stderr, Stream::Stderr, sender),
+    ];
+    let mut count = 0;
+    while C::recv(&receiver).is_some() {
+        count += 1;
+    }
+    for reader in readers {
+        reader.join().expect("no panic");
+    }
+    child.wait().expect("waiting");
+    count
+}
+
+/// Sends [`LINES`] lines from two threads, without a command, to time the
+/// channel alone.
+fn send_lines<C: Channel>(line: &Line) -> usize {
+    let (sender, receiver) = C::new();
+    let senders = [sender.clone(), sender].map(|sender| {
+        let line = line.clone();
+        thread::spawn(move || {
+            for _ in 0..LINES / 2 {
+                C::send(&sender, line.clone());
+            }
+        })
+    });
+    let mut count = 0;
+    while C::recv(&receiver).is_some() {
+        count += 1;
+    }
+    for sender in senders {
+        sender.join().expect("no panic");
+    }
+    count
+}
+
+fn channel(c: &mut Criterion) {
+    assert_eq!(run_command::<Std>(), LINES);
+    assert_eq!(run_command::<Crossbeam>(), LINES);
+    let line = Line {
+        line: "   Compiling some-crate v0.1.0 (/path/to/some-crate)".into(),
+        stream: Stream::Stdout,
+        crlf: false,
+    };
+
+    let mut group = c.benchmark_group("channel");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(LINES as u64));
+    group.bench_function("command/mpsc", |b| b.iter(run_command::<Std>));
+    group.bench_function("command/crossbeam", |b| {
+        b.iter(run_command::<Crossbeam>)
+    });
+    group.bench_function("send/mpsc", |b| b.iter(|| send_lines::<Std>(&line)));
+    group.bench_function("send/crossbeam", |b| {
+        b.iter(|| send_lines::<Crossbeam>(&line))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, channel);
+criterion_main!(benches);
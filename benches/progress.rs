@@ -0,0 +1,72 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use runner::bench::Window;
+use runner::{Line, RunOptions, Stream};
+
+/// Counts allocations, to report what a run costs the allocator besides
+/// time.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn progress(c: &mut Criterion) {
+    let lines = (0..100_000)
+        .map(|i| Line {
+            line: format!("line {i}: compiling some-crate v0.{i}.0").into(),
+            stream: Stream::Stdout,
+            crlf: false,
+        })
+        .collect::<Vec<_>>();
+
+    let expected = RunOptions {
+        expected_lines: Some(lines.len() as u64),
+        ..Default::default()
+    };
+    for (name, options) in [
+        ("100K lines", RunOptions::default()),
+        ("expected", expected),
+    ] {
+        let (count, size) = (
+            ALLOCATIONS.load(Ordering::Relaxed),
+            ALLOCATED.load(Ordering::Relaxed),
+        );
+        Window::with_options(&options, 0, 120).feed(&lines);
+        println!(
+            "progress/{name}: {} allocations, {} bytes",
+            ALLOCATIONS.load(Ordering::Relaxed) - count,
+            ALLOCATED.load(Ordering::Relaxed) - size
+        );
+    }
+
+    let mut group = c.benchmark_group("progress");
+    group.sample_size(10);
+    group.bench_function("100K lines", |b| {
+        b.iter_batched(
+            || Window::new(0, 120),
+            |mut window| window.feed(&lines),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, progress);
+criterion_main!(benches);
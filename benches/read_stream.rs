@@ -0,0 +1,18 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use runner::bench::read_lines;
+
+fn read_stream(c: &mut Criterion) {
+    // About 1 MB, in lines of a typical build log's length.
+    let data = (0..16_000)
+        .map(|i| {
+            format!("   Compiling some-crate v0.{i}.0 (/path/to/some-crate)\n")
+        })
+        .collect::<String>();
+    let mut group = c.benchmark_group("read_stream");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("1 MB", |b| b.iter(|| read_lines(data.as_bytes())));
+    group.finish();
+}
+
+criterion_group!(benches, read_stream);
+criterion_main!(benches);
@@ -0,0 +1,15 @@
+//! Feeds arbitrary output to the line reader and the window: nothing may
+//! panic, whatever the bytes. Run with `cargo +nightly fuzz run ingest`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the width of the terminal.
+    let Some((&columns, output)) = data.split_first() else {
+        return;
+    };
+    let window = runner::bench::ingest(output, columns.into());
+    assert!(window.lines().all(|row| row.starts_with('│')));
+});
@@ -0,0 +1,246 @@
+//! Runs under a tokio runtime, with the `async-runtime` feature.
+//!
+//! The command is spawned with `tokio::process` and waited for by a task;
+//! its output is read by blocking tasks, through the same line readers as
+//! the threads of [`run_with_progress`](crate::run_with_progress). Drawing
+//! the box stays synchronous, on a blocking thread of the runtime.
+
+use std::ffi::OsStr;
+use std::io::Read;
+use std::mem;
+use std::process::{Command, ExitStatus};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use console::Term;
+use nonempty::NonEmpty;
+use tokio::process::Child;
+use tokio::runtime::Handle;
+use tokio_util::io::SyncIoBridge;
+
+use crate::decode::Decoding;
+use crate::hash::Hashers;
+use crate::stdin::Input;
+use crate::{
+    _read_bytes, _read_stream, _run, AthensError, Collector, Event, Exited,
+    Reader, Result, RunError, RunOptions, RunResult, Spawner, Stream,
+};
+
+/// Like [`spawn_with_progress`](crate::spawn_with_progress), without
+/// blocking the runtime it's awaited on, which needs its IO driver enabled.
+///
+/// The run goes on when this future is dropped: stop it with
+/// [`RunOptions::cancel`]. The peak RSS of the command isn't known, so a
+/// command killed by `SIGKILL` is only told to be out of memory from its
+/// cgroup.
+pub async fn spawn_with_progress_async<S>(
+    command: NonEmpty<S>,
+    options: RunOptions,
+    out: Term,
+) -> Result<RunResult>
+where
+    S: AsRef<OsStr> + Send + 'static,
+{
+    let runtime = Handle::current();
+    tokio::task::spawn_blocking(move || {
+        _run(command, options, &out, None, Spawner::Tokio(runtime))
+            .or_else(RunError::into_result)
+    })
+    .await
+    .map_err(|_| AthensError::CollectorPanicked)?
+}
+
+/// Spawns `cmd` on `runtime` and starts collecting its output in a task,
+/// as [`_start`](crate::_start) does in a thread.
+pub(crate) fn start(
+    runtime: &Handle,
+    cmd: &mut Command,
+    raw: bool,
+    decoding: Decoding,
+    input: Input,
+    hashers: Option<Hashers>,
+    sender: Sender<Event>,
+) -> Result<(u32, Receiver<Option<ExitStatus>>, Collector)> {
+    // Spawning registers the child with the runtime's driver.
+    let _entered = runtime.enter();
+    input.configure(cmd);
+    let program = Command::new(cmd.get_program());
+    let mut cmd = tokio::process::Command::from(mem::replace(cmd, program));
+    let mut child = cmd.spawn().map_err(AthensError::SpawnFailed)?;
+    if let Some(to) = child.stdin.take() {
+        input.write_to(SyncIoBridge::new_with_handle(to, runtime.clone()));
+    }
+    let pid = child
+        .id()
+        .ok_or(AthensError::WaitFailed(std::io::ErrorKind::NotFound.into()))?;
+    let _ = sender.send(Event::Started(pid));
+    let (exited_sender, exited) = channel();
+    let task = runtime.spawn({
+        let runtime = runtime.clone();
+        let done = sender.clone();
+        async move {
+            let status = collect(
+                &runtime,
+                child,
+                sender,
+                raw,
+                decoding,
+                exited_sender,
+                hashers,
+            )
+            .await;
+            let _ = done.send(Event::Done);
+            status
+        }
+    });
+    Ok((pid, exited, Collector::Task(runtime.clone(), task)))
+}
+
+/// Reads the output of `child` until it exits, as
+/// [`collect`](crate::collect) does.
+async fn collect(
+    runtime: &Handle,
+    mut child: Child,
+    sender: Sender<Event>,
+    raw: bool,
+    decoding: Decoding,
+    exited: Sender<Option<ExitStatus>>,
+    hashers: Option<Hashers>,
+) -> Result<Exited> {
+    let err = child
+        .stderr
+        .take()
+        .ok_or(AthensError::StreamTakeFailed(Stream::Stderr))?;
+    let out = child
+        .stdout
+        .take()
+        .ok_or(AthensError::StreamTakeFailed(Stream::Stdout))?;
+    let err = SyncIoBridge::new_with_handle(err, runtime.clone());
+    let out = SyncIoBridge::new_with_handle(out, runtime.clone());
+    let read: Reader = if raw { _read_bytes } else { _read_stream };
+    let (err, out): (Box<dyn Read + Send>, Box<dyn Read + Send>) =
+        match &hashers {
+            Some(hashers) => (
+                Box::new(hashers.wrap(&Stream::Stderr, err)),
+                Box::new(hashers.wrap(&Stream::Stdout, out)),
+            ),
+            None => (Box::new(err), Box::new(out)),
+        };
+    let t1 = tokio::task::spawn_blocking({
+        let sender = sender.clone();
+        move || read(err, &sender, Stream::Stderr, decoding)
+    });
+    let t2 = tokio::task::spawn_blocking({
+        let sender = sender.clone();
+        move || read(out, &sender, Stream::Stdout, decoding)
+    });
+    let status = child.wait().await;
+    let _ = exited.send(status.as_ref().ok().copied());
+    // As in `collect`, both readers finish before the sender is dropped.
+    let err_read = t1.await;
+    let out_read = t2.await;
+    drop(sender);
+    err_read.map_err(|_| AthensError::ReaderPanicked(Stream::Stderr))??;
+    out_read.map_err(|_| AthensError::ReaderPanicked(Stream::Stdout))??;
+    let status = status.map_err(AthensError::WaitFailed)?;
+    Ok(Exited {
+        status,
+        peak_rss: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{File, OpenOptions};
+    use std::thread;
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use console::Term;
+    use nonempty::nonempty;
+
+    use crate::{
+        spawn_with_progress_async, AthensError, CancelToken, RunOptions,
+        StdinInput,
+    };
+
+    fn quiet() -> Result<Term> {
+        let null = OpenOptions::new().write(true).open("/dev/null")?;
+        Ok(Term::read_write_pair(File::open("/dev/null")?, null))
+    }
+
+    #[tokio::test]
+    async fn test_spawn_with_progress_async() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let result = spawn_with_progress_async(
+            nonempty!["sh", "-c", "echo one; echo two >&2; exit 3"],
+            RunOptions {
+                dump_dir: Some(dir.path().into()),
+                ..Default::default()
+            },
+            quiet()?,
+        )
+        .await?;
+        assert_eq!(result.status.code(), Some(3));
+        assert_eq!(result.stats.lines, 2);
+        let dump = std::fs::read_to_string(result.dump)?;
+        assert!(dump.contains("one") && dump.contains("two"), "{dump}");
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_async_stdin() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let result = spawn_with_progress_async(
+            nonempty!["cat"],
+            RunOptions {
+                dump_dir: Some(dir.path().into()),
+                stdin: Some(StdinInput::Data(b"a\nb\nc\n".to_vec())),
+                ..Default::default()
+            },
+            quiet()?,
+        )
+        .await?;
+        assert!(result.success);
+        assert_eq!(result.stats.lines, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_cancel() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cancel = CancelToken::new();
+        let run = tokio::spawn(spawn_with_progress_async(
+            nonempty!["sh", "-c", "while true; do echo busy; sleep 0.1; done"],
+            RunOptions {
+                dump_dir: Some(dir.path().into()),
+                cancel: Some(cancel.clone()),
+                ..Default::default()
+            },
+            quiet()?,
+        ));
+        tokio::task::spawn_blocking(|| {
+            thread::sleep(Duration::from_millis(200))
+        })
+        .await?;
+        cancel.cancel();
+        let result = run.await??;
+        assert!(result.cancelled && result.stats.lines > 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_not_found() -> Result<()> {
+        let err = spawn_with_progress_async(
+            nonempty!["/nonexistent/command"],
+            RunOptions::default(),
+            quiet()?,
+        )
+        .await
+        .expect_err("nothing to run");
+        let AthensError::SpawnFailed(err) = err else {
+            panic!("{err}");
+        };
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        Ok(())
+    }
+}
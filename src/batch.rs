@@ -0,0 +1,218 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Runs sharing a batch id, registered in a file that `--batch-watch`
+/// follows.
+///
+/// The file is a log with one tab-separated record per line: `start`, run
+/// id, time and name when a run starts, and `end`, run id, time and status
+/// when it finishes. Records are appended under an exclusive lock, so runs
+/// started in parallel don't interleave.
+pub struct Batch {
+    path: PathBuf,
+}
+
+/// A run of a batch, as read back from its file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchRun {
+    pub id: String,
+    pub name: String,
+    pub started: SystemTime,
+    /// The status and time, once the run is over.
+    pub finished: Option<(String, SystemTime)>,
+}
+
+impl Batch {
+    /// Opens the batch `id`, keeping its file under `dir`.
+    pub fn open(dir: &Path, id: &str) -> io::Result<Self> {
+        let valid = !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+            && !id.starts_with('.');
+        if !valid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid batch id: {id}"),
+            ));
+        }
+        let dir = dir.join("batches");
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            path: dir.join(format!("{id}.log")),
+        })
+    }
+
+    /// Registers a run named `name`, returning its id.
+    pub fn start(&self, name: &str) -> io::Result<String> {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        let now = SystemTime::now();
+        let id = format!(
+            "{}-{}-{}",
+            process::id(),
+            _millis(now),
+            RUNS.fetch_add(1, Ordering::Relaxed)
+        );
+        self.append(&["start", &id, &_millis(now).to_string(), name])?;
+        Ok(id)
+    }
+
+    /// Records that the run `id` finished with `status`.
+    pub fn finish(&self, id: &str, status: &str) -> io::Result<()> {
+        let now = _millis(SystemTime::now()).to_string();
+        self.append(&["end", id, &now, status])
+    }
+
+    /// The runs registered so far, in the order they started. A run whose
+    /// process is gone without recording its end is finished as `lost`.
+    pub fn runs(&self) -> io::Result<Vec<BatchRun>> {
+        let log = match fs::read_to_string(&self.path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            log => log?,
+        };
+        let mut runs = Vec::<BatchRun>::new();
+        // A record being written may be seen half done; it is skipped.
+        for record in log.lines() {
+            match record.splitn(4, '\t').collect::<Vec<_>>()[..] {
+                ["start", id, at, name] => runs.push(BatchRun {
+                    id: id.into(),
+                    name: name.into(),
+                    started: _time(at),
+                    finished: None,
+                }),
+                ["end", id, at, status] => {
+                    if let Some(run) = runs.iter_mut().find(|r| r.id == id) {
+                        run.finished = Some((status.into(), _time(at)));
+                    }
+                }
+                _ => (),
+            }
+        }
+        let now = SystemTime::now();
+        for run in runs.iter_mut().filter(|run| run.finished.is_none()) {
+            let pid = run.id.split('-').next().and_then(|p| p.parse().ok());
+            if pid.is_some_and(|pid| !_alive(pid)) {
+                run.finished = Some(("lost".into(), now));
+            }
+        }
+        Ok(runs)
+    }
+
+    fn append(&self, fields: &[&str]) -> io::Result<()> {
+        let fields = fields
+            .iter()
+            .map(|field| field.replace(['\t', '\n', '\r'], " "))
+            .collect::<Vec<_>>();
+        let record = format!("{}\n", fields.join("\t"));
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let _lock = Lock::new(&file)?;
+        (&file).write_all(record.as_bytes())
+    }
+}
+
+fn _millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+fn _time(millis: &str) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis.parse().unwrap_or_default())
+}
+
+/// Whether the process `pid` is still running.
+#[cfg(unix)]
+fn _alive(pid: i32) -> bool {
+    // SAFETY: signal 0 only checks that the process can be signalled.
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn _alive(_pid: i32) -> bool {
+    true
+}
+
+/// Holds an exclusive `flock` on a file until dropped.
+#[cfg(unix)]
+struct Lock<'a>(&'a File);
+
+#[cfg(unix)]
+impl<'a> Lock<'a> {
+    fn new(file: &'a File) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        // SAFETY: `flock` has no memory safety requirements, and the file
+        // descriptor is valid for as long as `file` is borrowed.
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self(file))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Lock<'_> {
+    fn drop(&mut self) {
+        use std::os::unix::io::AsRawFd;
+
+        // SAFETY: as in `Lock::new`.
+        unsafe {
+            libc::flock(self.0.as_raw_fd(), libc::LOCK_UN);
+        }
+    }
+}
+
+/// Appends are small enough to be written at once elsewhere.
+#[cfg(not(unix))]
+struct Lock;
+
+#[cfg(not(unix))]
+impl Lock {
+    fn new(_file: &File) -> io::Result<Self> {
+        Ok(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+
+    use anyhow::Result;
+
+    use crate::batch::Batch;
+
+    #[test]
+    fn test_batch_records_runs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let batch = Batch::open(dir.path(), "nightly")?;
+        let build = batch.start("cargo\tbuild")?;
+        let test = batch.start("cargo test")?;
+        batch.finish(&build, "exit 1")?;
+        let runs = batch.runs()?;
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].name, "cargo build");
+        assert_eq!(runs[0].finished.as_ref().map(|f| &f.0[..]), Some("exit 1"));
+        assert_eq!((&runs[1].id, &runs[1].finished), (&test, &None));
+        assert!(Batch::open(dir.path(), "../etc").is_err());
+
+        // Started by a process that is gone, and never finished.
+        let mut child = Command::new("true").spawn()?;
+        child.wait()?;
+        fs::write(
+            dir.path().join("batches/killed.log"),
+            format!("start\t{}-0-0\t0\tbuild\n", child.id()),
+        )?;
+        let runs = Batch::open(dir.path(), "killed")?.runs()?;
+        assert_eq!(runs[0].finished.as_ref().map(|f| &f.0[..]), Some("lost"));
+        Ok(())
+    }
+}
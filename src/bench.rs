@@ -0,0 +1,84 @@
+//! Hooks for the benchmarks in `benches/` and the fuzz targets in `fuzz/`,
+//! which can only reach the public API. Not meant to be used otherwise.
+
+use std::sync::mpsc::channel;
+
+use console::Term;
+use encoding_rs::UTF_8;
+use indicatif::ProgressBar;
+
+use crate::decode::Decoding;
+use crate::{
+    _build_msg, _read_stream, progress, Event, Line, RunOptions, State, Stream,
+};
+
+/// A run's state holding a given number of lines.
+pub struct Window(State);
+
+impl Window {
+    pub fn new(lines: usize, columns: u16) -> Self {
+        Self::with_options(&RunOptions::default(), lines, columns)
+    }
+
+    pub fn with_options(
+        options: &RunOptions,
+        lines: usize,
+        columns: u16,
+    ) -> Self {
+        let mut state = State::new(options, &Term::stdout());
+        state.pb = ProgressBar::hidden();
+        state.term_columns = columns;
+        for i in 0..lines {
+            let stream = if i % 10 == 0 {
+                Stream::Stderr
+            } else {
+                Stream::Stdout
+            };
+            state.buf.push(Line {
+                line: format!("line {i}: compiling some-crate v0.{i}.0").into(),
+                stream,
+                crlf: false,
+            });
+        }
+        Self(state)
+    }
+
+    /// Records lines and redraws after each, as a run does.
+    pub fn feed(&mut self, lines: &[Line]) {
+        for line in lines {
+            progress(&mut self.0, line).expect("nothing is printed");
+        }
+    }
+
+    /// Renders the window, as done after every line.
+    pub fn build_msg(&mut self) -> String {
+        _build_msg(&mut self.0)
+    }
+}
+
+/// Splits `data` into lines as a reader thread does, returning how many
+/// were sent.
+pub fn read_lines(data: &[u8]) -> usize {
+    let (sender, receiver) = channel();
+    _read_stream(data, &sender, Stream::Stdout, Decoding::Fixed(UTF_8))
+        .expect("reading");
+    drop(sender);
+    receiver.into_iter().count()
+}
+
+/// Reads `data` as the output of a command, rendering the window after each
+/// line, and returns the window as drawn in a terminal `columns` wide.
+pub fn ingest(data: &[u8], columns: u16) -> String {
+    let (sender, receiver) = channel();
+    _read_stream(data, &sender, Stream::Stdout, Decoding::Fixed(UTF_8))
+        .expect("reading");
+    drop(sender);
+    let mut window = Window::new(0, columns);
+    for event in receiver {
+        if let Event::Line(line) = event {
+            window.feed(&[line]);
+        }
+    }
+    window.0.term_columns = columns;
+    window.build_msg()
+}
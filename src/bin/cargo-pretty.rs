@@ -0,0 +1,87 @@
+//! `cargo pretty <command>...` runs `cargo <command>...` with the output in
+//! a box, as `runner -- cargo <command>...` would.
+
+use std::env;
+use std::ffi::OsString;
+use std::process::exit;
+
+use anyhow::Result;
+use console::Term;
+use nonempty::NonEmpty;
+use runner::exit::{report, EXIT_USAGE};
+use runner::{run_with_progress, CancelToken, RunOptions, NESTED_ENV};
+
+/// Options of cargo itself that take a value, as in `--color always`.
+const VALUE_OPTIONS: &[&str] = &["--color", "--config", "-C", "-Z"];
+
+/// The cargo subcommand in `args`, skipping `+toolchain` and cargo's own
+/// options like `--offline`.
+fn _subcommand(args: &[OsString]) -> Option<&OsString> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let text = arg.to_string_lossy();
+        if VALUE_OPTIONS.contains(&&*text) {
+            args.next();
+        } else if !text.starts_with(['-', '+']) {
+            return Some(arg);
+        }
+    }
+    None
+}
+
+fn main() -> Result<()> {
+    let mut args = env::args_os().skip(1).peekable();
+    // Cargo passes the name of the subcommand first.
+    if args.peek().is_some_and(|arg| arg == "pretty") {
+        args.next();
+    }
+    let args = args.collect::<Vec<_>>();
+    if args.is_empty() {
+        eprintln!("usage: cargo pretty <cargo command> [args]...");
+        exit(EXIT_USAGE);
+    }
+    let subcommand = _subcommand(&args).unwrap_or(&args[0]);
+    // Cargo tells subcommands which cargo is running them.
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo"));
+    let name = format!("cargo {}", subcommand.to_string_lossy());
+    let out = Term::stderr();
+    out.write_line(&format!("Command: {name}"))?;
+    let options = RunOptions {
+        plain: env::var_os(NESTED_ENV).is_some_and(|value| value == "1"),
+        cancel: Some(CancelToken::on_interrupt()),
+        output: Some(Term::stdout()),
+        ..Default::default()
+    };
+    let command = NonEmpty::from((cargo.clone(), args));
+    let code = match run_with_progress(command, options, &out) {
+        Ok(_) => 0,
+        Err(err) => report(&err, &cargo),
+    };
+    exit(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+
+    use crate::_subcommand;
+
+    #[test]
+    fn test_subcommand() {
+        for (args, expected) in [
+            (&["build", "--release"][..], Some("build")),
+            (&["--offline", "test"], Some("test")),
+            (&["+nightly", "fmt"], Some("fmt")),
+            (&["--color", "always", "test"], Some("test")),
+            (
+                &["--color=always", "-Z", "unstable-options", "doc"],
+                Some("doc"),
+            ),
+            (&["--version"], None),
+        ] {
+            let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+            let found = _subcommand(&args).map(|arg| arg.to_string_lossy());
+            assert_eq!(found.as_deref(), expected, "{args:?}");
+        }
+    }
+}
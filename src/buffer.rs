@@ -0,0 +1,194 @@
+use std::collections::{vec_deque, VecDeque};
+use std::slice;
+
+/// Keeps the last `capacity` items, overwriting the oldest one on overflow.
+pub(crate) struct RingBuffer<T> {
+    items: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<T> RingBuffer<T> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            items: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Adds `item`, returning the one it overwrote, if any.
+    pub(crate) fn push(&mut self, item: T) -> Option<T> {
+        if self.capacity == 0 {
+            return Some(item);
+        }
+        let oldest = if self.items.len() == self.capacity {
+            self.items.pop_front()
+        } else {
+            None
+        };
+        self.items.push_back(item);
+        oldest
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// The items, oldest first.
+    pub(crate) fn iter(&self) -> vec_deque::Iter<'_, T> {
+        self.items.iter()
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Option<&T> {
+        self.items.get(index)
+    }
+}
+
+/// The lines kept by a run: all of them, or only the last ones when a
+/// maximum is set.
+pub(crate) enum Buffer<T> {
+    All(Vec<T>),
+    Last { ring: RingBuffer<T>, dropped: usize },
+}
+
+impl<T> Default for Buffer<T> {
+    fn default() -> Self {
+        Self::All(Vec::new())
+    }
+}
+
+impl<T> Buffer<T> {
+    pub(crate) fn new(max: Option<usize>) -> Self {
+        match max {
+            Some(max) => Self::Last {
+                ring: RingBuffer::new(max),
+                dropped: 0,
+            },
+            None => Self::default(),
+        }
+    }
+
+    /// Makes room for `additional` more items. Only needed when keeping
+    /// everything: the ring is allocated upfront.
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        if let Self::All(items) = self {
+            items.reserve(additional);
+        }
+    }
+
+    /// Stores `item` and returns its index. Indices count every item ever
+    /// pushed, so they stay valid as old items are dropped.
+    pub(crate) fn push(&mut self, item: T) -> usize {
+        let index = self.total();
+        match self {
+            Self::All(items) => items.push(item),
+            Self::Last { ring, dropped } => {
+                if ring.push(item).is_some() {
+                    *dropped += 1;
+                }
+            }
+        }
+        index
+    }
+
+    /// Number of items ever pushed, including dropped ones.
+    pub(crate) fn total(&self) -> usize {
+        self.first() + self.len()
+    }
+
+    /// Index of the oldest item still kept.
+    pub(crate) fn first(&self) -> usize {
+        match self {
+            Self::All(_) => 0,
+            Self::Last { dropped, .. } => *dropped,
+        }
+    }
+
+    /// Number of items kept.
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Self::All(items) => items.len(),
+            Self::Last { ring, .. } => ring.len(),
+        }
+    }
+
+    /// The item at `index`, as returned by `push`, if it is still kept.
+    pub(crate) fn get(&self, index: usize) -> Option<&T> {
+        let index = index.checked_sub(self.first())?;
+        match self {
+            Self::All(items) => items.get(index),
+            Self::Last { ring, .. } => ring.get(index),
+        }
+    }
+
+    /// The items kept, oldest first.
+    pub(crate) fn iter(&self) -> Iter<'_, T> {
+        match self {
+            Self::All(items) => Iter::All(items.iter()),
+            Self::Last { ring, .. } => Iter::Last(ring.iter()),
+        }
+    }
+}
+
+pub(crate) enum Iter<'a, T> {
+    All(slice::Iter<'a, T>),
+    Last(vec_deque::Iter<'a, T>),
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::All(iter) => iter.next(),
+            Self::Last(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Self::All(iter) => iter.size_hint(),
+            Self::Last(iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl<T> DoubleEndedIterator for Iter<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::All(iter) => iter.next_back(),
+            Self::Last(iter) => iter.next_back(),
+        }
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer::{Buffer, RingBuffer};
+
+    #[test]
+    fn test_ring_buffer_overwrites_oldest() {
+        let mut ring = RingBuffer::new(3);
+        assert_eq!(ring.push(1), None);
+        ring.push(2);
+        ring.push(3);
+        assert_eq!(ring.push(4), Some(1));
+        assert_eq!(ring.iter().copied().collect::<Vec<_>>(), [2, 3, 4]);
+        assert_eq!(RingBuffer::new(0).push(1), Some(1));
+    }
+
+    #[test]
+    fn test_buffer_indices() {
+        let mut buffer = Buffer::new(Some(2));
+        assert_eq!((0..5).map(|i| buffer.push(i)).last(), Some(4));
+        assert_eq!((buffer.first(), buffer.len(), buffer.total()), (3, 2, 5));
+        assert_eq!(buffer.iter().rev().copied().collect::<Vec<_>>(), [4, 3]);
+
+        let mut buffer = Buffer::new(None);
+        (0..5).for_each(|i| {
+            buffer.push(i);
+        });
+        assert_eq!((buffer.first(), buffer.len()), (0, 5));
+    }
+}
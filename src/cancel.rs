@@ -0,0 +1,77 @@
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use crate::Event;
+
+/// Stops a run from another thread.
+///
+/// Cancelling terminates the command; the run then finishes as usual, saving
+/// the output captured so far, and reports [`RunError::Cancelled`].
+///
+/// [`RunError::Cancelled`]: crate::RunError::Cancelled
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: bool,
+    run: Option<Sender<Event>>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        let mut inner = self.inner.lock().expect("cancel token poisoned");
+        inner.cancelled = true;
+        if let Some(run) = &inner.run {
+            let _ = run.send(Event::Cancel);
+        }
+    }
+
+    /// A token cancelled on `SIGINT`/`SIGTERM`, so the output captured so
+    /// far is still saved and reported when the user interrupts the run.
+    #[cfg(unix)]
+    pub fn on_interrupt() -> Self {
+        use signal_hook::consts::{SIGINT, SIGTERM};
+        use signal_hook::iterator::Signals;
+
+        let token = Self::new();
+        if let Ok(mut signals) = Signals::new([SIGINT, SIGTERM]) {
+            let token = token.clone();
+            std::thread::spawn(move || {
+                for _ in signals.forever() {
+                    token.cancel();
+                }
+            });
+        }
+        token
+    }
+
+    #[cfg(not(unix))]
+    pub fn on_interrupt() -> Self {
+        Self::new()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.lock().expect("cancel token poisoned").cancelled
+    }
+
+    /// Connects the token to a run, cancelling it right away if the token
+    /// was already used.
+    pub(crate) fn attach(&self, run: Sender<Event>) {
+        let mut inner = self.inner.lock().expect("cancel token poisoned");
+        if inner.cancelled {
+            let _ = run.send(Event::Cancel);
+        }
+        inner.run = Some(run);
+    }
+
+    pub(crate) fn detach(&self) {
+        self.inner.lock().expect("cancel token poisoned").run = None;
+    }
+}
@@ -0,0 +1,104 @@
+use regex::Regex;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureMode {
+    First,
+    Unique,
+    All,
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    NoMatch,
+    Conflict(Vec<String>),
+}
+
+/// Values extracted from the output with the `--capture` regex.
+///
+/// The first capture group is used, or the whole match when the regex has
+/// no groups.
+#[derive(Clone, Debug)]
+pub struct Capture {
+    regex: Regex,
+    mode: CaptureMode,
+    values: Vec<String>,
+}
+
+impl Capture {
+    pub fn new(regex: Regex, mode: CaptureMode) -> Self {
+        Self {
+            regex,
+            mode,
+            values: Vec::new(),
+        }
+    }
+
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    pub fn scan(&mut self, line: &str) {
+        if self.mode == CaptureMode::First && !self.values.is_empty() {
+            return;
+        }
+        for caps in self.regex.captures_iter(line) {
+            let value = caps.get(1).or_else(|| caps.get(0));
+            if let Some(value) = value {
+                self.values.push(value.as_str().to_string());
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<Vec<String>, CaptureError> {
+        let mut values = self.values;
+        if values.is_empty() {
+            return Err(CaptureError::NoMatch);
+        }
+        match self.mode {
+            CaptureMode::First => values.truncate(1),
+            CaptureMode::Unique => {
+                let mut distinct = Vec::new();
+                for value in values.drain(..) {
+                    if !distinct.contains(&value) {
+                        distinct.push(value);
+                    }
+                }
+                values = distinct;
+                if values.len() > 1 {
+                    return Err(CaptureError::Conflict(values));
+                }
+            }
+            CaptureMode::All => (),
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use regex::Regex;
+
+    use crate::capture::{Capture, CaptureError, CaptureMode};
+
+    #[test]
+    fn test_capture_modes() -> Result<()> {
+        let regex = Regex::new(r"digest: (\S+)")?;
+        let scan = |mode| {
+            let mut capture = Capture::new(regex.clone(), mode);
+            for line in ["digest: a", "noise", "digest: b"] {
+                capture.scan(line);
+            }
+            capture.finish()
+        };
+        assert!(matches!(scan(CaptureMode::First), Ok(v) if v == ["a"]));
+        assert!(matches!(scan(CaptureMode::All), Ok(v) if v == ["a", "b"]));
+        assert!(matches!(
+            scan(CaptureMode::Unique),
+            Err(CaptureError::Conflict(v)) if v == ["a", "b"]
+        ));
+        let capture = Capture::new(regex.clone(), CaptureMode::First);
+        assert!(matches!(capture.finish(), Err(CaptureError::NoMatch)));
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use regex::{Regex, RegexSet};
+
+/// A count of the lines matching a regex, given as `NAME:REGEX`, or a sum
+/// of the numbers its first capture group matched, given as
+/// `NAME:REGEX:sum`.
+#[derive(Clone, Debug)]
+pub struct Counter {
+    pub name: String,
+    pub regex: Regex,
+    pub sum: bool,
+}
+
+impl Counter {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let Some((name, regex)) = spec.split_once(':') else {
+            return Err(format!("expected NAME:REGEX, got {spec}"));
+        };
+        if name.is_empty() {
+            return Err(format!("the counter has no name: {spec}"));
+        }
+        let (regex, sum) = match regex.strip_suffix(":sum") {
+            Some(regex) => (regex, true),
+            None => (regex, false),
+        };
+        let regex = Regex::new(regex).map_err(|e| e.to_string())?;
+        if sum && regex.captures_len() < 2 {
+            return Err(format!("{name}: a capture group is needed to sum"));
+        }
+        Ok(Self {
+            name: name.into(),
+            regex,
+            sum,
+        })
+    }
+}
+
+/// The values of the counters of a run, updated line by line.
+#[derive(Clone, Debug)]
+pub(crate) struct Counters {
+    counters: Vec<Counter>,
+    /// All the regexes, so a line is scanned once to find those matching.
+    set: RegexSet,
+    values: Vec<f64>,
+}
+
+impl Counters {
+    pub(crate) fn new(counters: Vec<Counter>) -> Self {
+        let set = RegexSet::new(counters.iter().map(|c| c.regex.as_str()))
+            .expect("the regexes were already compiled");
+        let values = vec![0.0; counters.len()];
+        Self {
+            counters,
+            set,
+            values,
+        }
+    }
+
+    pub(crate) fn scan(&mut self, line: &str) {
+        for index in self.set.matches(line).iter() {
+            let counter = &self.counters[index];
+            self.values[index] += if counter.sum {
+                counter
+                    .regex
+                    .captures(line)
+                    .and_then(|caps| caps.get(1)?.as_str().parse().ok())
+                    .unwrap_or(0.0)
+            } else {
+                1.0
+            };
+        }
+    }
+
+    /// The name and value of each counter, in the order they were given.
+    pub(crate) fn values(&self) -> Vec<(String, f64)> {
+        let names = self.counters.iter().map(|c| c.name.clone());
+        names.zip(self.values.iter().copied()).collect()
+    }
+
+    /// The live values, as shown in the header of the box: each name and
+    /// value in [`Self::label_width`] columns.
+    pub(crate) fn label(&self) -> String {
+        let values = self.counters.iter().zip(&self.values);
+        let labels = values.map(|(counter, &value)| {
+            format!("{} {:<6}", counter.name, _compact(value))
+        });
+        labels.collect::<Vec<_>>().join(" ")
+    }
+
+    pub(crate) fn label_width(&self) -> usize {
+        let names = self.counters.iter().map(|c| c.name.chars().count());
+        names.map(|width| width + 7).sum::<usize>() + self.counters.len() - 1
+    }
+}
+
+/// `value` in at most 6 characters, like `12345`, `2.5` or `1.2M`.
+fn _compact(value: f64) -> String {
+    match value.abs() {
+        v if v < 100_000.0 && value.fract() == 0.0 => format!("{value}"),
+        v if v < 10_000.0 => format!("{value:.1}"),
+        v if v < 1e6 => format!("{:.0}k", value / 1e3),
+        v if v < 1e9 => format!("{:.1}M", value / 1e6),
+        _ => format!("{:.1}G", value / 1e9),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::counters::{Counter, Counters};
+
+    #[test]
+    fn test_counters() -> Result<(), String> {
+        let counters = ["hits:cache hit", "retries:Retrying in \\d+s"]
+            .into_iter()
+            .chain(["bytes:wrote (\\d+) bytes:sum", "ratio:ratio=(.*):sum"])
+            .map(Counter::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut counters = Counters::new(counters);
+        for line in [
+            "cache hit: a",
+            "cache hit: b, wrote 100 bytes",
+            "Retrying in 5s",
+            "wrote 1024 bytes",
+            "ratio=0.5",
+            "ratio=oops",
+        ] {
+            counters.scan(line);
+        }
+        let values = counters.values();
+        let expected = [("hits", 2.0), ("retries", 1.0), ("bytes", 1124.0)];
+        for ((name, value), (expected, want)) in values.iter().zip(expected) {
+            assert_eq!((name.as_str(), *value), (expected, want));
+        }
+        assert_eq!(values[3].1, 0.5);
+        assert_eq!(
+            counters.label(),
+            "hits 2      retries 1      bytes 1124   ratio 0.5   "
+        );
+        assert_eq!(counters.label().chars().count(), counters.label_width());
+
+        assert!(Counter::parse("no regex").is_err());
+        assert!(Counter::parse(":x").is_err());
+        assert!(Counter::parse("n:(").is_err());
+        assert!(Counter::parse("n:no group:sum").is_err());
+        let time = Counter::parse("time:at (\\d+:\\d+)")?;
+        assert_eq!((time.regex.as_str(), time.sum), ("at (\\d+:\\d+)", false));
+        Ok(())
+    }
+}
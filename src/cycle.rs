@@ -0,0 +1,127 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::time::Instant;
+
+use crate::Line;
+
+/// Longest block of lines recognized as repeating.
+const MAX_PERIOD: usize = 16;
+/// Shortest block: lines repeated alone or in pairs are left as they are.
+const MIN_PERIOD: usize = 3;
+
+/// A block of lines the output has been repeating, back to back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Cycle {
+    /// Lines in the block.
+    pub(crate) period: usize,
+    /// Lines printed since the block was first complete, all repeating it.
+    pub(crate) repeated: usize,
+    /// When the first repetition started.
+    pub(crate) since: Instant,
+}
+
+impl Cycle {
+    /// How many times the block was printed in full.
+    pub(crate) fn times(&self) -> usize {
+        self.repeated / self.period + 1
+    }
+}
+
+/// Finds the blocks of lines the output repeats, from hashes of the lines.
+pub(crate) struct CycleDetector {
+    /// Hashes of the last [`MAX_PERIOD`] lines, the latest last.
+    recent: VecDeque<u64>,
+    /// For each period from 1, how many lines in a row were the same as
+    /// the line that many lines before, and since when.
+    runs: [(usize, Option<Instant>); MAX_PERIOD],
+}
+
+impl CycleDetector {
+    pub(crate) fn new() -> Self {
+        Self {
+            recent: VecDeque::with_capacity(MAX_PERIOD + 1),
+            runs: [(0, None); MAX_PERIOD],
+        }
+    }
+
+    /// Takes `line`, printed at `now`, into account.
+    pub(crate) fn push(&mut self, line: &Line, now: Instant) {
+        let mut hasher = DefaultHasher::new();
+        line.line.hash(&mut hasher);
+        line.stream.is_error().hash(&mut hasher);
+        let hash = hasher.finish();
+        let len = self.recent.len();
+        for (period, run) in (1..).zip(&mut self.runs) {
+            if len >= period && self.recent[len - period] == hash {
+                run.0 += 1;
+                run.1.get_or_insert(now);
+            } else {
+                *run = (0, None);
+            }
+        }
+        self.recent.push_back(hash);
+        if self.recent.len() > MAX_PERIOD {
+            self.recent.pop_front();
+        }
+    }
+
+    /// The block the output is repeating, if it is repeating one.
+    pub(crate) fn current(&self) -> Option<Cycle> {
+        // The shortest period is the block: three lines repeated are also
+        // six lines repeated.
+        let (period, (repeated, since)) = (1..)
+            .zip(self.runs)
+            .find(|(period, (repeated, _))| repeated >= period)?;
+        Some(Cycle {
+            period,
+            repeated,
+            since: since?,
+        })
+        .filter(|_| period >= MIN_PERIOD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::cycle::CycleDetector;
+    use crate::{Line, Stream};
+
+    fn push(detector: &mut CycleDetector, lines: &str) {
+        for line in lines.split(' ') {
+            let line = Line {
+                line: line.into(),
+                stream: Stream::Stdout,
+                crlf: false,
+            };
+            detector.push(&line, Instant::now());
+        }
+    }
+
+    #[test]
+    fn test_cycle_detector() {
+        let mut detector = CycleDetector::new();
+        push(&mut detector, "start retry: failed sleeping");
+        assert_eq!(detector.current(), None);
+        push(&mut detector, "retry: failed sleeping retry: failed");
+        let cycle = detector.current().expect("a cycle");
+        assert_eq!((cycle.period, cycle.repeated, cycle.times()), (3, 5, 2));
+        for _ in 0..40 {
+            push(&mut detector, "sleeping retry: failed");
+        }
+        let cycle = detector.current().expect("a cycle");
+        assert_eq!((cycle.period, cycle.times()), (3, 42));
+
+        // Broken by a new line, and not yet repeated in full again.
+        push(&mut detector, "done retry: failed");
+        assert_eq!(detector.current(), None);
+
+        // A single line repeating isn't a block.
+        push(&mut detector, &["same"; 20].join(" "));
+        assert_eq!(detector.current(), None);
+        push(&mut detector, "a b a b a b a b");
+        assert_eq!(detector.current(), None);
+    }
+}
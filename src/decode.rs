@@ -0,0 +1,116 @@
+use std::borrow::Cow;
+use std::str;
+
+use encoding_rs::{Encoding, SHIFT_JIS, UTF_8, WINDOWS_1252};
+
+/// How the lines of a stream are decoded.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Decoding {
+    /// From this encoding.
+    Fixed(&'static Encoding),
+    /// From the encoding the stream looks to be in, see [`LineDecoder`].
+    Detect,
+}
+
+/// Decodes the lines of a stream, one after the other.
+///
+/// When detecting, a stream starting with a UTF-8 BOM is UTF-8. Otherwise
+/// lines are read as UTF-8 until one isn't valid UTF-8: the stream is then
+/// taken to be in Shift_JIS if that line is valid Shift_JIS, and in
+/// Windows-1252 (a superset of Latin-1) if not, for the rest of it.
+pub(crate) struct LineDecoder {
+    encoding: Option<&'static Encoding>,
+    first: bool,
+}
+
+impl LineDecoder {
+    pub(crate) fn new(decoding: Decoding) -> Self {
+        let encoding = match decoding {
+            Decoding::Fixed(encoding) => Some(encoding),
+            Decoding::Detect => None,
+        };
+        Self {
+            encoding,
+            first: true,
+        }
+    }
+
+    /// The text of `line`. Invalid sequences become `�`.
+    pub(crate) fn decode<'a>(&mut self, mut line: &'a [u8]) -> Cow<'a, str> {
+        if std::mem::take(&mut self.first) && self.encoding.is_none() {
+            if let Some(rest) = line.strip_prefix(b"\xef\xbb\xbf") {
+                line = rest;
+                self.encoding = Some(UTF_8);
+            }
+        }
+        let encoding = match self.encoding {
+            Some(encoding) => encoding,
+            None => match str::from_utf8(line) {
+                Ok(text) => return Cow::Borrowed(text),
+                Err(_) => *self.encoding.insert(_guess(line)),
+            },
+        };
+        encoding.decode_without_bom_handling(line).0
+    }
+}
+
+/// The legacy encoding `line`, which isn't UTF-8, is likely in.
+fn _guess(line: &[u8]) -> &'static Encoding {
+    // Latin-1 text is rarely valid Shift_JIS: accented letters are mostly
+    // lead bytes, followed by a space, punctuation or the end of the line.
+    if SHIFT_JIS
+        .decode_without_bom_handling_and_without_replacement(line)
+        .is_some()
+    {
+        SHIFT_JIS
+    } else {
+        WINDOWS_1252
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+
+    use crate::decode::{Decoding, LineDecoder};
+
+    fn decode(decoding: Decoding, lines: &[&[u8]]) -> Vec<String> {
+        let mut decoder = LineDecoder::new(decoding);
+        lines
+            .iter()
+            .map(|l| decoder.decode(l).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_line_decoder() {
+        let latin1: &[&[u8]] = &[b"ok", b"caf\xe9 cr\xe8me", b"\xe0 la"];
+        assert_eq!(
+            decode(Decoding::Detect, latin1),
+            ["ok", "café crème", "à la"]
+        );
+        assert_eq!(
+            decode(Decoding::Fixed(WINDOWS_1252), latin1),
+            ["ok", "café crème", "à la"]
+        );
+
+        // "エラー: ファイル" and "完了".
+        let sjis: &[&[u8]] = &[
+            b"\x83G\x83\x89\x81[: \x83t\x83@\x83C\x83\x8b",
+            b"\x8a\xae\x97\xb9",
+        ];
+        assert_eq!(
+            decode(Decoding::Detect, sjis),
+            ["エラー: ファイル", "完了"]
+        );
+        assert_eq!(
+            decode(Decoding::Fixed(SHIFT_JIS), sjis),
+            ["エラー: ファイル", "完了"]
+        );
+
+        assert_eq!(
+            decode(Decoding::Detect, &[b"\xef\xbb\xbfna\xc3\xafve", b"\xff"]),
+            ["naïve", "\u{fffd}"]
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::env;
+use std::ffi::OsString;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+
+/// Where athens may keep files between runs.
+///
+/// Each directory is probed for writability once, when resolved. If the
+/// usual place (from `XDG_*` or `HOME`) can't be written, a directory under
+/// the system temp dir is used instead; if that fails too, the directory is
+/// [`Dir::Unavailable`] and whatever needs it should be skipped, never
+/// failing the run.
+#[derive(Clone, Debug)]
+pub struct Dirs {
+    pub config: Dir,
+    pub cache: Dir,
+    pub state: Dir,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Dir {
+    /// The usual place.
+    Home(PathBuf),
+    /// The usual place is not writable, or `HOME` is not set.
+    Temp(PathBuf),
+    /// Nothing writable was found.
+    Unavailable,
+}
+
+impl Dir {
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Dir::Home(path) | Dir::Temp(path) => Some(path),
+            Dir::Unavailable => None,
+        }
+    }
+}
+
+impl Dirs {
+    /// Resolves the directories from the environment of this process.
+    pub fn resolve() -> Self {
+        Self::resolve_with(|name| env::var_os(name), &env::temp_dir())
+    }
+
+    /// Resolves the directories reading variables through `env`, and using
+    /// `temp` as the fallback.
+    pub fn resolve_with<F>(env: F, temp: &Path) -> Self
+    where
+        F: Fn(&str) -> Option<OsString>,
+    {
+        let resolve = |var, default, name| {
+            let base = _absolute(env(var)).or_else(|| {
+                _absolute(env("HOME")).map(|home| home.join(default))
+            });
+            match base.map(|base| base.join("athens")) {
+                Some(dir) if _writable(&dir) => Dir::Home(dir),
+                _ => {
+                    let dir = temp.join("athens").join(name);
+                    if _writable(&dir) {
+                        Dir::Temp(dir)
+                    } else {
+                        Dir::Unavailable
+                    }
+                }
+            }
+        };
+        Self {
+            config: resolve("XDG_CONFIG_HOME", ".config", "config"),
+            cache: resolve("XDG_CACHE_HOME", ".cache", "cache"),
+            state: resolve("XDG_STATE_HOME", ".local/state", "state"),
+        }
+    }
+
+    fn all(&self) -> [(&'static str, &Dir); 3] {
+        [
+            ("config", &self.config),
+            ("cache", &self.cache),
+            ("state", &self.state),
+        ]
+    }
+
+    /// A single warning naming every unavailable directory, if any.
+    pub fn warning(&self) -> Option<String> {
+        let missing = self
+            .all()
+            .into_iter()
+            .filter(|(_, dir)| **dir == Dir::Unavailable)
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        (!missing.is_empty()).then(|| {
+            format!(
+                "no writable {} directory; features using it are disabled",
+                missing.join(", ")
+            )
+        })
+    }
+}
+
+impl Display for Dirs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, dir) in self.all() {
+            match dir {
+                Dir::Home(path) => writeln!(f, "{name}: {}", path.display())?,
+                Dir::Temp(path) => writeln!(
+                    f,
+                    "{name}: {} (fallback, usual place not writable)",
+                    path.display()
+                )?,
+                Dir::Unavailable => writeln!(f, "{name}: unavailable")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Relative values are ignored, as the XDG spec asks.
+fn _absolute(value: Option<OsString>) -> Option<PathBuf> {
+    value.map(PathBuf::from).filter(|path| path.is_absolute())
+}
+
+/// Whether `dir` could be created and written to. Nothing is left behind:
+/// the probe is an unnamed file in the closest existing ancestor.
+fn _writable(dir: &Path) -> bool {
+    dir.ancestors()
+        .find(|path| path.exists())
+        .is_some_and(|path| {
+            path.is_dir() && tempfile::tempfile_in(path).is_ok()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::ffi::OsString;
+
+    use anyhow::Result;
+
+    use crate::dirs::{Dir, Dirs};
+
+    fn resolve(vars: &[(&str, &str)], temp: &str) -> Dirs {
+        let vars = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), OsString::from(v)))
+            .collect::<HashMap<_, _>>();
+        Dirs::resolve_with(|name| vars.get(name).cloned(), temp.as_ref())
+    }
+
+    #[test]
+    fn test_home_unset() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dirs = resolve(&[], &temp.path().to_string_lossy());
+        assert_eq!(dirs.cache, Dir::Temp(temp.path().join("athens/cache")));
+        assert_eq!(dirs.warning(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_home_read_only() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let dirs =
+            resolve(&[("HOME", "/proc/self")], &temp.path().to_string_lossy());
+        assert_eq!(dirs.config, Dir::Temp(temp.path().join("athens/config")));
+
+        let dirs = resolve(&[("HOME", "/proc/self")], "/proc/self");
+        assert_eq!(dirs.state, Dir::Unavailable);
+        assert_eq!(
+            dirs.warning().as_deref(),
+            Some(
+                "no writable config, cache, state directory; \
+                 features using it are disabled"
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_xdg_overrides() -> Result<()> {
+        let home = tempfile::tempdir()?;
+        let cache = tempfile::tempdir()?;
+        let dirs = resolve(
+            &[
+                ("HOME", &home.path().to_string_lossy()),
+                ("XDG_CACHE_HOME", &cache.path().to_string_lossy()),
+                ("XDG_CONFIG_HOME", "relative/is/ignored"),
+            ],
+            "/proc/self",
+        );
+        assert_eq!(dirs.cache, Dir::Home(cache.path().join("athens")));
+        assert_eq!(dirs.config, Dir::Home(home.path().join(".config/athens")));
+        // Probing doesn't create anything.
+        assert!(!home.path().join(".config").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use std::env;
+use std::ffi::{OsStr, OsString};
+
+use serde::Serialize;
+
+use crate::NESTED_ENV;
+
+/// Which inherited environment variables reach the command, by name
+/// patterns where `*` matches any run of characters and `?` a single one.
+///
+/// With no `allow` patterns every variable is allowed. A variable matching
+/// a `deny` pattern is always removed, even if it is also allowed.
+/// Variables set by athens itself are not filtered.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct EnvFilter {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl EnvFilter {
+    /// Whether there are no patterns, and everything is passed.
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Whether the variable `name` is passed to the command.
+    pub fn passes(&self, name: &OsStr) -> bool {
+        (self.allow.is_empty() || _matches(&self.allow, name))
+            && !_matches(&self.deny, name)
+    }
+
+    /// Whether `name` matches both an `allow` and a `deny` pattern, so that
+    /// it is removed despite being allowed.
+    pub fn conflicts(&self, name: &OsStr) -> bool {
+        _matches(&self.allow, name) && _matches(&self.deny, name)
+    }
+
+    /// The variables of this process that the command inherits.
+    pub fn effective(&self) -> Vec<(OsString, OsString)> {
+        env::vars_os()
+            .filter(|(name, _)| self.passes(name))
+            .collect()
+    }
+
+    /// The variables of this process that the command doesn't inherit.
+    pub fn removed(&self) -> Vec<OsString> {
+        env::vars_os()
+            .map(|(name, _)| name)
+            .filter(|name| name != NESTED_ENV && !self.passes(name))
+            .collect()
+    }
+}
+
+fn _matches(patterns: &[String], name: &OsStr) -> bool {
+    let name = name.to_string_lossy();
+    patterns.iter().any(|pattern| _glob(pattern, &name))
+}
+
+/// Whether `text` matches the whole of `pattern`.
+fn _glob(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    // Where to resume after the last `*`: its position in the pattern, and
+    // how far into the text it has matched.
+    let mut star = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use crate::env::{_glob, EnvFilter};
+
+    #[test]
+    fn test_glob() {
+        assert!(_glob("*_TOKEN", "GITHUB_TOKEN"));
+        assert!(!_glob("*_TOKEN", "GITHUB_TOKENS"));
+        assert!(_glob("CARGO_*", "CARGO_HOME"));
+        assert!(_glob("?ATH", "PATH"));
+        assert!(_glob("*A*B*", "xAyyBz"));
+        assert!(!_glob("PATH", "PATHS"));
+        assert!(_glob("*", ""));
+    }
+
+    #[test]
+    fn test_deny_wins() {
+        let filter = EnvFilter {
+            allow: vec!["PATH".into(), "*_TOKEN".into()],
+            deny: vec!["*_TOKEN".into()],
+        };
+        assert!(filter.passes(OsStr::new("PATH")));
+        assert!(!filter.passes(OsStr::new("GITHUB_TOKEN")));
+        assert!(!filter.passes(OsStr::new("HOME")));
+        assert!(filter.conflicts(OsStr::new("GITHUB_TOKEN")));
+        assert!(!filter.conflicts(OsStr::new("PATH")));
+        assert!(EnvFilter::default().passes(OsStr::new("HOME")));
+    }
+}
@@ -0,0 +1,220 @@
+use std::fmt::{self, Display};
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+use serde::Serialize;
+
+use crate::{describe_exit, exit_signal, RunResult, RunStats, Stream};
+
+/// Errors returned by the library functions.
+#[derive(Debug)]
+pub enum AthensError {
+    /// No program was given to run.
+    EmptyCommand,
+    /// The command could not be started.
+    SpawnFailed(io::Error),
+    /// The pipe of the given stream was not available on the child.
+    StreamTakeFailed(Stream),
+    /// Reading from the given stream failed.
+    ReadFailed(Stream, io::Error),
+    /// The thread reading the given stream panicked.
+    ReaderPanicked(Stream),
+    /// The thread waiting for the child panicked.
+    CollectorPanicked,
+    /// Lines could not be delivered because the receiving side was closed.
+    ChannelClosed,
+    /// Waiting for the child to exit failed.
+    WaitFailed(io::Error),
+    /// The captured output could not be written to disk.
+    DumpFailed(io::Error),
+    /// Writing status messages to the terminal failed.
+    OutputFailed(io::Error),
+    /// The file to give the command as input couldn't be opened.
+    InputFailed(io::Error),
+    /// The named resource limit can't be applied on this platform.
+    LimitUnsupported(&'static str),
+    /// The run was cancelled before the command was started, during
+    /// [`RunOptions::delay`](crate::RunOptions::delay).
+    CancelledBeforeStart,
+}
+
+impl Display for AthensError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyCommand => write!(f, "no command given"),
+            Self::SpawnFailed(err) => {
+                write!(f, "couldn't spawn command: {err}")
+            }
+            Self::StreamTakeFailed(stream) => {
+                write!(f, "couldn't get {stream}")
+            }
+            Self::ReadFailed(stream, err) => {
+                write!(f, "error while reading {stream}: {err}")
+            }
+            Self::ReaderPanicked(stream) => {
+                write!(f, "thread panicked while reading {stream}")
+            }
+            Self::CollectorPanicked => write!(f, "thread panicked"),
+            Self::ChannelClosed => write!(f, "output channel closed"),
+            Self::WaitFailed(err) => {
+                write!(f, "couldn't wait for command: {err}")
+            }
+            Self::DumpFailed(err) => write!(f, "couldn't save output: {err}"),
+            Self::OutputFailed(err) => {
+                write!(f, "couldn't write to terminal: {err}")
+            }
+            Self::InputFailed(err) => {
+                write!(f, "couldn't open input for command: {err}")
+            }
+            Self::LimitUnsupported(limit) => {
+                write!(f, "{limit} limits are not supported on this platform")
+            }
+            Self::CancelledBeforeStart => {
+                write!(f, "cancelled before the command started")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AthensError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::SpawnFailed(err)
+            | Self::ReadFailed(_, err)
+            | Self::WaitFailed(err)
+            | Self::DumpFailed(err)
+            | Self::OutputFailed(err)
+            | Self::InputFailed(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Why a run ended, for callers that need more than the exit code. Stopping
+/// the command takes precedence over how it then exited.
+///
+/// Serialized as `{"exited": 3}`, `{"signaled": 9}` or, for the others, a
+/// string like `"timed_out"`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitReason {
+    /// The command exited with this code.
+    Exited(i32),
+    /// The command was terminated by this signal.
+    Signaled(i32),
+    /// The command was stopped for not showing it was ready within the
+    /// timeout of [`RunOptions::ready`](crate::RunOptions::ready).
+    TimedOut,
+    /// The command was stopped for repeating itself, with
+    /// [`RunOptions::fail_on_loop`](crate::RunOptions::fail_on_loop).
+    Looped,
+    /// The command was stopped for drawing a full-screen interface, with
+    /// [`RunOptions::stop_on_tui`](crate::RunOptions::stop_on_tui).
+    Tui,
+    /// The run was stopped through its cancel token.
+    Cancelled,
+}
+
+impl ExitReason {
+    /// How a command that wasn't stopped ended with `status`.
+    pub fn from_status(status: &ExitStatus) -> Self {
+        match (status.code(), exit_signal(status)) {
+            (Some(code), _) => Self::Exited(code),
+            (None, Some(signal)) => Self::Signaled(signal),
+            (None, None) => Self::Exited(1),
+        }
+    }
+}
+
+/// Why a call to [`run_with_progress`] didn't succeed.
+///
+/// [`run_with_progress`]: crate::run_with_progress
+#[derive(Debug)]
+pub enum RunError {
+    /// The program to run does not exist.
+    NotFound(io::Error),
+    /// The program exists but couldn't be started (e.g. permission denied).
+    SpawnFailed(io::Error),
+    /// The command ran but its output couldn't be saved. `dump` is the
+    /// partially written file, if it was created.
+    DumpFailed {
+        source: io::Error,
+        dump: Option<PathBuf>,
+        stats: RunStats,
+    },
+    /// The run was stopped through its cancel token.
+    Cancelled(Box<RunResult>),
+    /// The command exited with a status not considered successful.
+    ChildFailed(Box<RunResult>),
+    /// The command exited successfully, but without the output required by
+    /// [`RunOptions::require_output`](crate::RunOptions::require_output).
+    NoOutput(Box<RunResult>),
+    /// No line showed that what the command started is up, within the
+    /// timeout of [`RunOptions::ready`](crate::RunOptions::ready).
+    NotReady(Box<RunResult>),
+    /// Running the command failed for another reason.
+    Failed(AthensError),
+}
+
+impl RunError {
+    /// Turns the errors where the command ran to completion back into a
+    /// result, and the rest into an [`AthensError`].
+    pub fn into_result(self) -> Result<RunResult, AthensError> {
+        match self {
+            Self::Cancelled(result)
+            | Self::ChildFailed(result)
+            | Self::NoOutput(result)
+            | Self::NotReady(result) => Ok(*result),
+            Self::NotFound(err) | Self::SpawnFailed(err) => {
+                Err(AthensError::SpawnFailed(err))
+            }
+            Self::DumpFailed { source, .. } => {
+                Err(AthensError::DumpFailed(source))
+            }
+            Self::Failed(err) => Err(err),
+        }
+    }
+}
+
+impl From<AthensError> for RunError {
+    fn from(err: AthensError) -> Self {
+        Self::Failed(err)
+    }
+}
+
+impl Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(_) => write!(f, "command not found"),
+            Self::SpawnFailed(err) => {
+                write!(f, "couldn't spawn command: {err}")
+            }
+            Self::DumpFailed { source, .. } => {
+                write!(f, "couldn't save output: {source}")
+            }
+            Self::Cancelled(_) => write!(f, "command was cancelled"),
+            Self::ChildFailed(result) => {
+                write!(f, "command {}", describe_exit(&result.status))
+            }
+            Self::NoOutput(_) => write!(f, "command produced no output"),
+            Self::NotReady(_) => write!(f, "command didn't get ready in time"),
+            Self::Failed(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RunError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::NotFound(err)
+            | Self::SpawnFailed(err)
+            | Self::DumpFailed { source: err, .. } => Some(err),
+            Self::Failed(err) => Some(err),
+            Self::Cancelled(_)
+            | Self::ChildFailed(_)
+            | Self::NoOutput(_)
+            | Self::NotReady(_) => None,
+        }
+    }
+}
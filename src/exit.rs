@@ -0,0 +1,127 @@
+//! The exit codes of the runner binaries, shared so that a wrapper like
+//! `cargo pretty` exits as `runner` would.
+
+use std::ffi::OsStr;
+
+use console::style;
+
+use crate::{exit_code, AthensError, RunError};
+
+/// Exit code used when the options are invalid, as clap does.
+pub const EXIT_USAGE: i32 = 2;
+/// Exit code used when `--capture` found nothing to print.
+pub const EXIT_CAPTURE_NO_MATCH: i32 = 3;
+/// Exit code used when `--capture-unique` saw different values.
+pub const EXIT_CAPTURE_CONFLICT: i32 = 4;
+/// Exit code used when `--ready-regex` didn't match in time.
+pub const EXIT_NOT_READY: i32 = 5;
+/// Exit code used when the output couldn't be saved (`EX_IOERR`).
+pub const EXIT_DUMP_FAILED: i32 = 74;
+/// Exit code used when the command couldn't be started, as in shells.
+pub const EXIT_CANNOT_EXECUTE: i32 = 126;
+/// Exit code used when the command doesn't exist, as in shells.
+pub const EXIT_NOT_FOUND: i32 = 127;
+/// Exit code used when the run was interrupted, as in shells for `SIGINT`.
+pub const EXIT_CANCELLED: i32 = 130;
+
+/// Reports why the run of `program` failed on stderr, unless its summary
+/// already did, and returns the exit code to use.
+pub fn report(err: &RunError, program: &OsStr) -> i32 {
+    let (msg, code) = match err {
+        RunError::NotFound(_) => (
+            format!("command not found: {}", program.to_string_lossy()),
+            EXIT_NOT_FOUND,
+        ),
+        RunError::SpawnFailed(_) => (err.to_string(), EXIT_CANNOT_EXECUTE),
+        RunError::DumpFailed { dump, stats, .. } => {
+            let partial = dump
+                .as_ref()
+                .map(|path| format!(", partially saved at {}", path.display()))
+                .unwrap_or_default();
+            (
+                format!("{err} ({} lines captured{partial})", stats.lines),
+                EXIT_DUMP_FAILED,
+            )
+        }
+        // The summary was already printed for these.
+        RunError::Cancelled(_)
+        | RunError::Failed(AthensError::CancelledBeforeStart) => {
+            return EXIT_CANCELLED
+        }
+        RunError::ChildFailed(result) => return exit_code(&result.status),
+        RunError::NoOutput(_) => return 1,
+        RunError::NotReady(_) => return EXIT_NOT_READY,
+        RunError::Failed(_) => (err.to_string(), 1),
+    };
+    eprintln!("{}", style(format!("Error: {msg}")).red());
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use std::fs::{self, File, OpenOptions};
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+
+    use anyhow::Result;
+    use console::Term;
+    use nonempty::nonempty;
+
+    use crate::exit::{
+        report, EXIT_CANNOT_EXECUTE, EXIT_DUMP_FAILED, EXIT_NOT_FOUND,
+    };
+    use crate::{run_with_progress, RunOptions};
+
+    #[test]
+    fn test_exit_code_propagation() -> Result<()> {
+        let out = Term::read_write_pair(
+            File::open("/dev/null")?,
+            OpenOptions::new().write(true).open("/dev/null")?,
+        );
+        let dir = tempfile::tempdir()?;
+        let options = RunOptions {
+            dump_dir: Some(dir.path().into()),
+            ..Default::default()
+        };
+        for (script, code) in
+            [("exit 3", 3), ("exit 255", 255), ("kill -9 $$", 137)]
+        {
+            let err = run_with_progress(
+                nonempty!["sh", "-c", script],
+                options.clone(),
+                &out,
+            )
+            .expect_err("the command fails");
+            assert_eq!(report(&err, OsStr::new("sh")), code, "{script}");
+        }
+
+        let missing = dir.path().join("missing");
+        let mut programs = vec![(missing.clone(), EXIT_NOT_FOUND)];
+        #[cfg(unix)]
+        {
+            let script = dir.path().join("script");
+            fs::write(&script, "#!/bin/sh\n")?;
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o644))?;
+            programs.push((script, EXIT_CANNOT_EXECUTE));
+        }
+        for (program, code) in programs {
+            let err = run_with_progress(
+                nonempty![program.clone()],
+                options.clone(),
+                &out,
+            )
+            .expect_err("the command doesn't start");
+            assert_eq!(report(&err, program.as_os_str()), code);
+        }
+
+        let no_dir = RunOptions {
+            dump_dir: Some(missing),
+            ..Default::default()
+        };
+        let err = run_with_progress(nonempty!["true"], no_dir, &out)
+            .expect_err("the output isn't saved");
+        assert_eq!(report(&err, OsStr::new("true")), EXIT_DUMP_FAILED);
+        Ok(())
+    }
+}
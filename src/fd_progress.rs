@@ -0,0 +1,96 @@
+use std::path::Path;
+use std::sync::mpsc::Sender;
+
+use crate::Event;
+
+/// Follows how far the command has read `path`, sending
+/// [`Event::FileProgress`] until the command closes the file or exits.
+///
+/// Only the command itself is looked at, not processes it starts. Progress
+/// is a convenience: if the file or the descriptor can't be found, nothing
+/// is sent and the spinner stays.
+#[cfg(all(target_os = "linux", feature = "fd-progress"))]
+pub(crate) fn watch(pid: u32, path: &Path, sender: Sender<Event>) {
+    use std::time::Duration;
+    use std::{fs, thread};
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+    let Ok(path) = fs::canonicalize(path) else {
+        return;
+    };
+    let Ok(size) = fs::metadata(&path).map(|metadata| metadata.len()) else {
+        return;
+    };
+    thread::spawn(move || {
+        let mut seen = false;
+        loop {
+            match _position(pid, &path) {
+                Some(position) => {
+                    seen = true;
+                    let event = Event::FileProgress(position, size);
+                    if sender.send(event).is_err() {
+                        break;
+                    }
+                }
+                // The file was closed, or the command is gone.
+                None if seen
+                    || !Path::new(&format!("/proc/{pid}")).exists() =>
+                {
+                    break
+                }
+                None => (),
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    });
+}
+
+#[cfg(not(all(target_os = "linux", feature = "fd-progress")))]
+pub(crate) fn watch(_pid: u32, _path: &Path, _sender: Sender<Event>) {}
+
+/// The offset of the first descriptor of `pid` open on `path`, which must
+/// be canonical.
+#[cfg(all(target_os = "linux", feature = "fd-progress"))]
+fn _position(pid: u32, path: &Path) -> Option<u64> {
+    use std::fs;
+
+    let fd = fs::read_dir(format!("/proc/{pid}/fd"))
+        .ok()?
+        .flatten()
+        .find(|fd| {
+            fs::read_link(fd.path()).is_ok_and(|target| target == path)
+        })?;
+    let info = fs::read_to_string(format!(
+        "/proc/{pid}/fdinfo/{}",
+        fd.file_name().to_string_lossy()
+    ))
+    .ok()?;
+    info.lines()
+        .find_map(|line| line.strip_prefix("pos:"))
+        .and_then(|position| position.trim().parse().ok())
+}
+
+#[cfg(all(test, target_os = "linux", feature = "fd-progress"))]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::process;
+
+    use anyhow::Result;
+
+    use crate::fd_progress::_position;
+
+    #[test]
+    fn test_position_of_open_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = fs::canonicalize(dir.path())?.join("input");
+        fs::write(&path, b"0123456789")?;
+        let mut file = File::open(&path)?;
+        file.read_exact(&mut [0; 4])?;
+        assert_eq!(_position(process::id(), &path), Some(4));
+        drop(file);
+        assert_eq!(_position(process::id(), &path), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::Stream;
+
+/// Hash functions for [`RunOptions::hash`](crate::RunOptions::hash).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    /// The name of the algorithm, as in `sha256sum`.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+        }
+    }
+}
+
+/// Hashes of the bytes the command printed, as read from the pipes: before
+/// decoding and with ANSI codes. There is one per stream, since how the two
+/// interleave varies from run to run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputHash {
+    pub algorithm: HashAlgorithm,
+    /// In lowercase hexadecimal, as printed by `sha256sum`.
+    pub stdout: String,
+    pub stderr: String,
+}
+
+enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(bytes),
+            Hasher::Sha512(hasher) => hasher.update(bytes),
+        }
+    }
+
+    fn hex(&self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => {
+                format!("{:x}", hasher.clone().finalize())
+            }
+            Hasher::Sha512(hasher) => {
+                format!("{:x}", hasher.clone().finalize())
+            }
+        }
+    }
+}
+
+/// The hashes of both streams, updated by the reader threads.
+#[derive(Clone)]
+pub(crate) struct Hashers {
+    algorithm: HashAlgorithm,
+    stdout: Arc<Mutex<Hasher>>,
+    stderr: Arc<Mutex<Hasher>>,
+}
+
+impl Hashers {
+    pub(crate) fn new(algorithm: HashAlgorithm) -> Self {
+        let hasher = || {
+            Arc::new(Mutex::new(match algorithm {
+                HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+                HashAlgorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+            }))
+        };
+        Self {
+            algorithm,
+            stdout: hasher(),
+            stderr: hasher(),
+        }
+    }
+
+    /// `reader`, hashing what is read from it into the hash of `stream`.
+    pub(crate) fn wrap<R>(&self, stream: &Stream, reader: R) -> HashingReader<R>
+    where
+        R: Read,
+    {
+        let hasher = match stream {
+            Stream::Stdout => &self.stdout,
+            Stream::Stderr => &self.stderr,
+        };
+        HashingReader {
+            reader,
+            hasher: hasher.clone(),
+        }
+    }
+
+    /// The hashes of what was read so far.
+    pub(crate) fn finish(&self) -> OutputHash {
+        let hex =
+            |hasher: &Mutex<Hasher>| hasher.lock().expect("not poisoned").hex();
+        OutputHash {
+            algorithm: self.algorithm,
+            stdout: hex(&self.stdout),
+            stderr: hex(&self.stderr),
+        }
+    }
+}
+
+pub(crate) struct HashingReader<R> {
+    reader: R,
+    hasher: Arc<Mutex<Hasher>>,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.hasher.lock().expect("not poisoned").update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::hash::{HashAlgorithm, Hashers};
+    use crate::Stream;
+
+    #[test]
+    fn test_hashers() -> std::io::Result<()> {
+        let hashers = Hashers::new(HashAlgorithm::Sha256);
+        let mut read = String::new();
+        hashers
+            .wrap(&Stream::Stdout, &b"\x1b[31mhello\x1b[0m\n"[..])
+            .read_to_string(&mut read)?;
+        assert_eq!(read, "\x1b[31mhello\x1b[0m\n");
+        let hash = hashers.finish();
+        // As printed by `printf '\033[31mhello\033[0m\n' | sha256sum`.
+        assert_eq!(
+            hash.stdout,
+            "1b4c41467dce097276316fcbe4d25e61effd27f4811d3d6f77378577c47fb3f2"
+        );
+        assert_eq!(
+            hash.stderr,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,259 @@
+//! Runs whose events are pulled from an iterator, instead of being passed to
+//! a callback as with [`spawn`](crate::spawn).
+//!
+//! With an iterator, the caller's loop drives the run:
+//!
+//! ```
+//! use nonempty::nonempty;
+//! use runner::{spawn_iter, RunEvent, RunOptions};
+//!
+//! let command = nonempty!["sh", "-c", "echo one; echo two >&2"];
+//! let mut run = spawn_iter(command, RunOptions::default())?;
+//! let mut lines = vec![];
+//! for event in &mut run {
+//!     match event {
+//!         RunEvent::Line(line) | RunEvent::StderrLine(line) => {
+//!             lines.push(line.to_string())
+//!         }
+//!         RunEvent::Exited(status) => assert!(status.success()),
+//!         _ => (),
+//!     }
+//! }
+//! lines.sort();
+//! assert_eq!(lines, ["one", "two"]);
+//! assert!(run.wait_outcome()?.success);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! With a callback, the run calls back into the caller for every line:
+//!
+//! ```
+//! use nonempty::nonempty;
+//! use runner::{build_command, spawn};
+//!
+//! let command = nonempty!["sh", "-c", "echo one; echo two >&2"];
+//! let mut command = build_command(command);
+//! let mut lines = vec![];
+//! let status = spawn(&mut command, |line| {
+//!     lines.push(line.line.to_string());
+//!     Ok(())
+//! })?;
+//! assert!(status.success());
+//! lines.sort();
+//! assert_eq!(lines, ["one", "two"]);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use console::Term;
+use nonempty::NonEmpty;
+
+use crate::{
+    AthensError, CancelToken, Line, RunError, RunOptions, RunResult, Spawner,
+    Stream,
+};
+
+/// What happens during a run started with [`spawn_iter`].
+#[derive(Clone, Debug)]
+pub enum RunEvent {
+    /// A line the command printed on stdout.
+    Line(Arc<str>),
+    /// A line the command printed on stderr.
+    StderrLine(Arc<str>),
+    /// Sent every 50ms, whether there was output or not.
+    Tick,
+    /// The command exited. All of its output came before.
+    Exited(ExitStatus),
+    /// The full output was saved at this path. This is the last event of a
+    /// run that went well.
+    DumpFinalized(PathBuf),
+}
+
+/// Sends the events of a run to its [`RunHandle`].
+pub(crate) struct Observer {
+    events: Sender<RunEvent>,
+    started: Sender<u32>,
+}
+
+impl Observer {
+    // Sending only fails once the handle is gone, and the run is then being
+    // cancelled anyway.
+    pub(crate) fn send(&self, event: RunEvent) {
+        let _ = self.events.send(event);
+    }
+
+    pub(crate) fn line(&self, line: &Line) {
+        self.send(match line.stream {
+            Stream::Stdout => RunEvent::Line(line.line.clone()),
+            Stream::Stderr => RunEvent::StderrLine(line.line.clone()),
+        });
+    }
+
+    pub(crate) fn started(&self, pid: u32) {
+        let _ = self.started.send(pid);
+    }
+}
+
+/// A run in progress, as an iterator over its [`RunEvent`]s.
+///
+/// The iterator blocks until the next event, and ends once the run is over.
+/// Dropping the handle cancels the run and waits for it to finish.
+pub struct RunHandle {
+    events: Receiver<RunEvent>,
+    pid: u32,
+    cancel: CancelToken,
+    run: Option<JoinHandle<Result<RunResult, RunError>>>,
+}
+
+impl RunHandle {
+    /// The pid of the command.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Terminates the command. The events up to its exit still come, and
+    /// [`wait_outcome`](Self::wait_outcome) then reports
+    /// [`RunError::Cancelled`].
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Waits for the run to finish, and returns what
+    /// [`run_with_progress`](crate::run_with_progress) would have. Events
+    /// not read yet are dropped.
+    pub fn wait_outcome(mut self) -> Result<RunResult, RunError> {
+        _join(self.run.take().expect("only taken once"))
+    }
+}
+
+impl Iterator for RunHandle {
+    type Item = RunEvent;
+
+    fn next(&mut self) -> Option<RunEvent> {
+        self.events.recv().ok()
+    }
+}
+
+impl Drop for RunHandle {
+    fn drop(&mut self) {
+        if let Some(run) = self.run.take() {
+            self.cancel.cancel();
+            let _ = _join(run);
+        }
+    }
+}
+
+fn _join(
+    run: JoinHandle<Result<RunResult, RunError>>,
+) -> Result<RunResult, RunError> {
+    run.join()
+        .map_err(|_| RunError::Failed(AthensError::CollectorPanicked))?
+}
+
+/// Like [`run_with_progress`](crate::run_with_progress), but in the
+/// background and without drawing anything: the output comes as
+/// [`RunEvent`]s from the returned handle. `options.plain` and
+/// `options.output` are ignored.
+///
+/// Returns once the command is spawned, or with the error that kept it from
+/// starting.
+pub fn spawn_iter<S>(
+    command: NonEmpty<S>,
+    mut options: RunOptions,
+) -> Result<RunHandle, RunError>
+where
+    S: AsRef<OsStr> + Send + 'static,
+{
+    // A buffered terminal is only written out when flushed, which the run
+    // never does: the summary lines are dropped with it.
+    let out = Term::buffered_stdout();
+    options.plain = true;
+    options.output = Some(out.clone());
+    let cancel = options.cancel.get_or_insert_with(CancelToken::new).clone();
+    let (events, receiver) = channel();
+    let (started, pid) = channel();
+    let observer = Observer { events, started };
+    let run = thread::spawn(move || {
+        crate::_run(command, options, &out, Some(observer), Spawner::Threads)
+    });
+    match pid.recv() {
+        Ok(pid) => Ok(RunHandle {
+            events: receiver,
+            pid,
+            cancel,
+            run: Some(run),
+        }),
+        // The run ended before spawning the command.
+        Err(_) => Err(_join(run)
+            .err()
+            .unwrap_or(RunError::Failed(AthensError::CollectorPanicked))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use anyhow::Result;
+    use nonempty::nonempty;
+
+    use crate::{spawn_iter, RunError, RunEvent, RunOptions};
+
+    #[test]
+    fn test_spawn_iter() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let options = RunOptions {
+            dump_dir: Some(dir.path().into()),
+            ..Default::default()
+        };
+        let run = spawn_iter(
+            nonempty!["sh", "-c", "echo one; echo two; exit 3"],
+            options.clone(),
+        )?;
+        assert!(run.pid() > 0);
+        let events: Vec<_> = run
+            .filter(|event| !matches!(event, RunEvent::Tick))
+            .collect();
+        let lines: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                RunEvent::Line(line) => Some(&**line),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(lines, ["one", "two"]);
+        match &events[2..] {
+            [RunEvent::Exited(status), RunEvent::DumpFinalized(path)] => {
+                assert_eq!(status.code(), Some(3));
+                assert!(path.exists());
+            }
+            other => panic!("unexpected events: {other:?}"),
+        }
+
+        let mut run = spawn_iter(
+            nonempty!["sh", "-c", "echo started; exec sleep 10"],
+            options.clone(),
+        )?;
+        let start = Instant::now();
+        for event in &mut run {
+            if matches!(event, RunEvent::Line(_)) {
+                break;
+            }
+        }
+        run.cancel();
+        assert!(matches!(run.wait_outcome(), Err(RunError::Cancelled(_))));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        assert!(matches!(
+            spawn_iter(nonempty!["./does-not-exist"], options),
+            Err(RunError::NotFound(_))
+        ));
+        Ok(())
+    }
+}
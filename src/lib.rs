@@ -0,0 +1,4240 @@
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::ffi::{OsStr, OsString};
+use std::fmt::{self, Display};
+use std::fs::{self, File};
+use std::io::{
+    self, BufRead, BufReader, BufWriter, ErrorKind, IsTerminal, Read, Write,
+};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use buffer::Buffer;
+use console::{style, AnsiCodeIterator, Color, StyledObject, Term};
+use counters::Counters;
+use cycle::{Cycle, CycleDetector};
+use decode::{Decoding, LineDecoder};
+use encoding_rs::{Encoding, UTF_8};
+use hash::Hashers;
+use indicatif::{
+    HumanBytes, HumanCount, MultiProgress, ProgressBar, ProgressState,
+    ProgressStyle,
+};
+use iter::Observer;
+use nonempty::NonEmpty;
+use oom::OomWatch;
+use ready::Tail;
+use regex::Regex;
+use screen::Screen;
+use signals::Listener;
+use stdin::Input;
+use throughput::Throughput;
+
+#[cfg(feature = "async-runtime")]
+mod async_run;
+pub mod batch;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
+mod buffer;
+mod cancel;
+pub mod capture;
+mod counters;
+mod cycle;
+mod decode;
+pub mod dirs;
+pub mod env;
+pub mod error;
+pub mod exit;
+mod fd_progress;
+mod hash;
+mod iter;
+mod limits;
+mod line_numbers;
+pub mod matrix;
+mod oom;
+pub mod pipeline;
+mod ready;
+pub mod resolve;
+mod screen;
+mod signals;
+mod stdin;
+pub mod terminal;
+mod test_format;
+mod throughput;
+mod uring;
+
+#[cfg(feature = "async-runtime")]
+pub use async_run::spawn_with_progress_async;
+
+pub use cancel::CancelToken;
+pub use capture::{Capture, CaptureError, CaptureMode};
+pub use counters::Counter;
+pub use dirs::{Dir, Dirs};
+pub use env::EnvFilter;
+pub use error::{AthensError, ExitReason, RunError};
+pub use hash::{HashAlgorithm, OutputHash};
+pub use iter::{spawn_iter, RunEvent, RunHandle};
+pub use limits::{parse_size, Limits};
+pub use line_numbers::LineNumberFormat;
+pub use ready::Ready;
+pub use resolve::ResolvedRun;
+pub use stdin::StdinInput;
+pub use test_format::{TestFormat, TestResults};
+pub use throughput::ThroughputUnit;
+
+pub type Result<T, E = AthensError> = std::result::Result<T, E>;
+
+const MAX_LINES: u16 = 4;
+
+/// Set to `1` in the environment of every command run by athens, so nested
+/// runs (and other tools) can tell they are running under it.
+pub const NESTED_ENV: &str = "ATHENS_NESTED";
+
+/// Rendered width of the progress bar, percent and ETA in the title.
+const DETERMINATE_WIDTH: usize = 35;
+
+/// How long a command asked to stop gets before being killed.
+const KILL_GRACE: Duration = Duration::from_secs(2);
+
+/// Most lines room is made for upfront, whatever `expected_lines` says.
+const MAX_RESERVED_LINES: u64 = 1 << 20;
+
+/// Room kept in the header for the rate shown by `--throughput`.
+const THROUGHPUT_WIDTH: usize = 14;
+
+/// Room kept in the header for the tally of `--test-format`.
+const TESTS_WIDTH: usize = 16;
+
+/// Failed tests listed in the summary; the rest are only in the file.
+const MAX_LISTED_FAILURES: usize = 10;
+
+/// Times a block of lines is printed in a row before the window shows it
+/// once, with how many times it was repeated.
+const CYCLE_MIN_TIMES: usize = 3;
+
+/// Number of lines looking like a frame after which a nested box is assumed.
+const NESTED_FRAME_LINES: usize = 8;
+
+/// Cursor moves to a position in a single line, or in what was read of an
+/// unfinished one, after which the command is assumed to draw a full-screen
+/// interface.
+const TUI_CURSOR_MOVES: usize = 10;
+
+/// Least time between two redraws of the window. Lines arriving faster are
+/// drawn as [`RunOptions::display_policy`] says.
+const FRAME_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Options controlling a run of [`spawn_with_progress`].
+#[derive(Clone, Default)]
+pub struct RunOptions {
+    /// Collects values matched by a regex while the command runs.
+    pub capture: Option<Capture>,
+    /// Encoding of the command's output. Defaults to UTF-8; only
+    /// ASCII-compatible encodings can be split into lines.
+    pub encoding: Option<&'static Encoding>,
+    /// Detects the encoding of each stream from its lines instead, telling
+    /// UTF-8, Shift_JIS and Latin-1 apart. Not with `raw_preview`, which
+    /// decodes from `encoding`.
+    pub detect_encoding: bool,
+    /// Reads raw bytes instead of lines and emulates `\r` and backspace in
+    /// the preview, for commands that animate in place.
+    pub raw_preview: bool,
+    /// Exit codes considered successful. When empty, only a zero exit
+    /// status is.
+    pub success_codes: Vec<i32>,
+    /// Prints lines as they arrive instead of drawing the box, with no
+    /// spinner and no colors, for limited terminals and log files.
+    pub plain: bool,
+    /// Number of lines the command is expected to print, turning the
+    /// spinner into a progress bar. Room for them is also made upfront.
+    pub expected_lines: Option<u64>,
+    /// Directory where the output is saved, instead of the system's
+    /// temporary directory.
+    pub dump_dir: Option<PathBuf>,
+    /// Lets another thread stop the run.
+    pub cancel: Option<CancelToken>,
+    /// Copies athens' stdin to the command through a pipe, saving what was
+    /// forwarded next to the output, as `<dump>.stdin`. Ignored when
+    /// `stdin` is set.
+    pub stdin_tee: bool,
+    /// Input written to the command instead of athens' stdin.
+    pub stdin: Option<StdinInput>,
+    /// Writes `stdin` this many times instead of once, with
+    /// `stdin_delimiter` between the copies. A file is read again from the
+    /// start for every copy, so it must be seekable.
+    pub stdin_repeat: Option<u64>,
+    /// Written between the copies of `stdin_repeat`, as is: a newline isn't
+    /// added. Empty by default, so the copies follow each other directly.
+    pub stdin_delimiter: Vec<u8>,
+    /// Keeps the last stderr line in the first row of the window, with how
+    /// long ago it was printed.
+    pub pin_last_error: bool,
+    /// Leaves blank lines at the end of the output out of the dump.
+    pub trim_trailing_blank: bool,
+    /// Keeps only the last lines in memory, and so in the dump. All lines
+    /// are kept by default.
+    pub max_buffer_lines: Option<usize>,
+    /// Where the command's stdout is printed with `plain`. Defaults to the
+    /// terminal given for the status lines.
+    pub output: Option<Term>,
+    /// Which of athens' environment variables the command inherits.
+    pub env: EnvFilter,
+    /// Shows how fast the command prints, in lines or bytes per second, in
+    /// the header of the window.
+    pub throughput: Option<ThroughputUnit>,
+    /// A file the command reads: how far it got drives the progress bar.
+    /// Linux only; elsewhere, or if the command never opens the file, the
+    /// spinner is shown.
+    pub fd_progress: Option<PathBuf>,
+    /// Which part of lines too long for the window is cut.
+    pub truncate: Truncate,
+    /// Collapses runs of whitespace in the lines shown in the window to a
+    /// single space, to fit more of tabular output. The dump is unchanged.
+    pub flatten_whitespace: bool,
+    /// Resource limits for the command.
+    pub limits: Limits,
+    /// How the lines of output stand out in the window.
+    pub contrast: Contrast,
+    /// Reads the output as the results of a test runner, tallying passed and
+    /// failed tests in the header and listing failures in the summary.
+    pub test_format: Option<TestFormat>,
+    /// Counts the lines matching regexes, or sums numbers they match,
+    /// showing the values in the header and in the summary.
+    pub counters: Vec<Counter>,
+    /// After this long without output, says so in the last row of the
+    /// window (not in the dump), so a silent command doesn't look hung.
+    pub heartbeat: Option<Duration>,
+    /// What the window shows when lines arrive faster than it is redrawn.
+    /// The dump always has every line.
+    pub display_policy: DisplayPolicy,
+    /// Hashes the output of the command, to compare runs without diffing
+    /// their dumps.
+    pub hash: Option<HashAlgorithm>,
+    /// Words the command is run through, like `valgrind --leak-check=full`:
+    /// the first is the program spawned, and the command its last arguments.
+    pub wrapper: Vec<OsString>,
+    /// How long the command is expected to run at most. Past that, a
+    /// warning is printed once and the frame turns yellow, but the command
+    /// keeps running.
+    pub max_runtime: Option<Duration>,
+    /// File mode creation mask of the command, like `0o022`. Unix only:
+    /// ignored elsewhere.
+    pub umask: Option<u32>,
+    /// Removes what this matches at the start of lines (like a timestamp
+    /// or a module path) from the window. Lines it doesn't match are shown
+    /// unchanged.
+    pub strip_prefix: Option<Regex>,
+    /// Also removes the prefix from the dump.
+    pub strip_prefix_dump: bool,
+    /// Draws the box as one of several, for runs going on at the same time:
+    /// each run adds its bar to this one, so the boxes stack instead of
+    /// drawing over each other, and lines printed by a run go above them.
+    pub multi: Option<MultiProgress>,
+    /// Numbers the lines of the dump, from 1, in this format.
+    pub line_numbers: Option<LineNumberFormat>,
+    /// Colors the line numbers of the dump like the lines of their stream
+    /// in the window, for pagers like `less -R`.
+    pub color_line_numbers: bool,
+    /// Starts the dump with lines recording the command, where and when it
+    /// ran and the environment changes, each starting with `#`.
+    pub dump_header: bool,
+    /// Waits for the dump to be on disk (`fsync`) before the run ends, so
+    /// it survives a crash or power loss right after.
+    pub sync_dump: bool,
+    /// Every this long, prints how long the command has run and how many
+    /// lines it printed above the box, so the progression stays in the
+    /// scrollback. The same lines are saved in the dump, where they were
+    /// printed.
+    pub announce_every: Option<Duration>,
+    /// Draws the box with `+`, `-` and `|`, for terminals that don't
+    /// decode UTF-8.
+    pub ascii_frame: bool,
+    /// Fails the run when the command didn't print this output, even if it
+    /// exited successfully.
+    pub require_output: Option<RequiredOutput>,
+    /// Line endings of the dump.
+    pub dump_line_endings: LineEndings,
+    /// Stops the command once it printed the same block of lines more than
+    /// this many times in a row, failing the run.
+    pub fail_on_loop: Option<usize>,
+    /// Stops the command, failing the run, once its output shows that it
+    /// draws a full-screen interface (like `htop` or `vim`), which the
+    /// window can't show.
+    pub stop_on_tui: bool,
+    /// Waits this long before starting the command, counting down in the
+    /// window. Cancelling meanwhile ends the run without starting it.
+    pub delay: Option<Duration>,
+    /// Succeeds only once a line shows that what the command started is up,
+    /// waiting for it after the command exited if need be.
+    pub ready: Option<Ready>,
+    /// Prints the command again after the summary of a run that failed, so
+    /// the failure can be told apart from others in a long log.
+    pub show_command_on_failure: bool,
+}
+
+/// How the lines of output are styled in the window, for readability across
+/// terminal themes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Contrast {
+    /// Dimmed, so they don't compete with the frame.
+    #[default]
+    Low,
+    /// In the stream's color only.
+    Normal,
+    /// Bold.
+    High,
+}
+
+impl Contrast {
+    fn apply<D>(self, text: StyledObject<D>) -> StyledObject<D> {
+        match self {
+            Contrast::Low => text.dim(),
+            Contrast::Normal => text,
+            Contrast::High => text.bold(),
+        }
+    }
+}
+
+/// What the window does with lines arriving faster than it can be redrawn.
+/// Only lines are held back or skipped: with [`RunOptions::raw_preview`],
+/// the latest screen is drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DisplayPolicy {
+    /// Shows every line, a window at a time, falling behind the command.
+    Lag,
+    /// Shows the latest lines, skipping the others.
+    Sample,
+    /// Like `Sample`, with a row saying how many lines were skipped.
+    #[default]
+    SampleMarked,
+}
+
+/// Where lines wider than the window are cut.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Truncate {
+    /// Keeps the beginning.
+    #[default]
+    End,
+    /// Keeps the beginning and the end, joined by `…`: good for paths.
+    Middle,
+    /// Keeps the end, after a `…`.
+    Start,
+}
+
+/// Line endings written in the dump, with
+/// [`RunOptions::dump_line_endings`]. Lines are shown without theirs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineEndings {
+    /// `\n`, whatever the command printed.
+    #[default]
+    Lf,
+    /// `\r\n`, whatever the command printed.
+    Crlf,
+    /// `\r\n` on Windows, `\n` elsewhere.
+    Native,
+    /// As the command printed each line. The lines athens adds end with
+    /// `\n`, and so does a last line printed without an ending.
+    Preserve,
+}
+
+impl LineEndings {
+    /// What ends `line` in the dump, or the lines athens adds with `None`.
+    fn of(self, line: Option<&Line>) -> &'static str {
+        match self {
+            LineEndings::Crlf => "\r\n",
+            LineEndings::Native if cfg!(windows) => "\r\n",
+            LineEndings::Preserve if line.is_some_and(|line| line.crlf) => {
+                "\r\n"
+            }
+            _ => "\n",
+        }
+    }
+}
+
+/// Output a run must have, with [`RunOptions::require_output`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequiredOutput {
+    /// At least a line, on either stream.
+    Any,
+    Stdout,
+    Stderr,
+}
+
+impl RequiredOutput {
+    /// Whether the required output is missing from a run with `stats`.
+    pub fn is_missing(self, stats: &RunStats) -> bool {
+        match self {
+            RequiredOutput::Any => stats.lines == 0,
+            RequiredOutput::Stdout => stats.lines == stats.stderr_lines,
+            RequiredOutput::Stderr => stats.stderr_lines == 0,
+        }
+    }
+}
+
+impl RunOptions {
+    /// Whether `status` counts as a successful run.
+    pub fn is_success(&self, status: &ExitStatus) -> bool {
+        if self.success_codes.is_empty() {
+            status.success()
+        } else {
+            status
+                .code()
+                .is_some_and(|code| self.success_codes.contains(&code))
+        }
+    }
+
+    fn encoding(&self) -> &'static Encoding {
+        self.encoding.unwrap_or(UTF_8)
+    }
+
+    fn decoding(&self) -> Decoding {
+        if self.detect_encoding {
+            Decoding::Detect
+        } else {
+            Decoding::Fixed(self.encoding())
+        }
+    }
+}
+
+/// Counts gathered while the command runs.
+#[derive(Clone, Debug, Default)]
+pub struct RunStats {
+    /// Lines printed by the command, on both streams.
+    pub lines: usize,
+    /// Lines printed on stderr.
+    pub stderr_lines: usize,
+    /// Time since the command was started.
+    pub elapsed: Duration,
+    /// Time until the command printed its first line, if it printed any.
+    pub first_output: Option<Duration>,
+    /// The most memory the command used at once, in bytes, where the
+    /// platform reports it.
+    pub peak_rss: Option<u64>,
+}
+
+/// Outcome of [`run_with_progress`] and [`spawn_with_progress`].
+#[derive(Debug)]
+pub struct RunResult {
+    /// The exit status of the command.
+    pub status: ExitStatus,
+    /// Why the run ended: how the command exited, or why it was stopped.
+    pub exit_reason: ExitReason,
+    /// Whether the status counts as successful for the run options.
+    pub success: bool,
+    /// Whether the run was stopped through its [`CancelToken`].
+    pub cancelled: bool,
+    /// Where the full output was saved.
+    pub dump: PathBuf,
+    /// Where the input forwarded to the command was saved, with
+    /// [`RunOptions::stdin_tee`].
+    pub stdin_dump: Option<PathBuf>,
+    /// The values collected by the capture regex, if one was given.
+    pub capture: Option<Capture>,
+    pub stats: RunStats,
+    /// Whether the command looks like it was killed for lack of memory.
+    pub likely_oom: bool,
+    /// The tests seen in the output, with [`RunOptions::test_format`].
+    pub tests: Option<TestResults>,
+    /// The hashes of the output, with [`RunOptions::hash`].
+    pub hash: Option<OutputHash>,
+    /// Whether the run went on for longer than [`RunOptions::max_runtime`].
+    pub overran: bool,
+    /// Whether the command didn't print the output required by
+    /// [`RunOptions::require_output`].
+    pub no_output: bool,
+    /// Whether the command was stopped for repeating itself, with
+    /// [`RunOptions::fail_on_loop`].
+    pub looped: bool,
+    /// Whether the command was stopped for drawing a full-screen interface,
+    /// with [`RunOptions::stop_on_tui`].
+    pub tui: bool,
+    /// When the line matching [`RunOptions::ready`] came, since the start.
+    pub ready: Option<Duration>,
+    /// The name and final value of each of [`RunOptions::counters`].
+    pub counters: Vec<(String, f64)>,
+    /// The rows of the window as it was last drawn, framed and styled as
+    /// on the terminal, to show it elsewhere. Empty with
+    /// [`RunOptions::plain`].
+    pub rows: Vec<String>,
+}
+
+struct State {
+    buf: Buffer<Line>,
+    stderr_lines: usize,
+    msg_buf: String,
+    pb: ProgressBar,
+    max_lines: u16,
+    term_lines: u16,
+    term_columns: u16,
+    stderr_only: bool,
+    capture: Option<Capture>,
+    screen: Option<Screen>,
+    plain: bool,
+    out: Term,
+    frame_lines: usize,
+    expected_lines: Option<u64>,
+    dump_dir: Option<PathBuf>,
+    started: Instant,
+    first_output: Option<Instant>,
+    pin_last_error: bool,
+    trim_trailing_blank: bool,
+    /// Index of the last stderr line, its text (kept even once dropped from
+    /// `buf`), and when it arrived.
+    pinned: Option<(usize, Arc<str>, Instant)>,
+    cancelled: bool,
+    /// Shared with the header, which redraws the rate on every tick.
+    throughput: Option<Arc<Mutex<Throughput>>>,
+    /// Size of the file followed with `fd_progress`, once it is being read.
+    file_size: Option<u64>,
+    truncate: Truncate,
+    contrast: Contrast,
+    /// Shared with the header, like `throughput`.
+    tests: Option<Arc<Mutex<TestResults>>>,
+    counters: Option<Arc<Mutex<Counters>>>,
+    heartbeat: Option<Duration>,
+    last_output: Instant,
+    display_policy: DisplayPolicy,
+    /// When the window was last redrawn.
+    drawn_at: Instant,
+    /// Lines consumed when the window was last redrawn. With `Lag`, where
+    /// the lines it showed end instead.
+    drawn: usize,
+    /// Lines consumed but not shown since the window last caught up, for the
+    /// marker row of `SampleMarked`.
+    skipped: usize,
+    /// Whether there is output the window doesn't show yet.
+    pending: bool,
+    max_runtime: Option<Duration>,
+    /// Whether the run went past `max_runtime`, and was flagged.
+    overran: bool,
+    /// `RunOptions::strip_prefix`, anchored at the start of lines.
+    strip_prefix: Option<Regex>,
+    strip_prefix_dump: bool,
+    line_numbers: Option<LineNumberFormat>,
+    color_line_numbers: bool,
+    /// Written at the top of the dump, from [`ResolvedRun::header`].
+    dump_header: Option<String>,
+    announce_every: Option<Duration>,
+    /// How many intervals of `announce_every` were announced.
+    announced: u32,
+    /// Lines for the dump that the command didn't print, with how many
+    /// lines it had printed before them.
+    markers: Vec<(usize, String)>,
+    /// The command, as shown in announcements.
+    command: String,
+    /// The rows of the window once the command moved the cursor up: the
+    /// index of the line last written on each. `buf` keeps every line as
+    /// printed, for the dump.
+    rows: Option<VecDeque<usize>>,
+    /// The row the next line goes on, when the command moved the cursor up
+    /// over earlier rows.
+    cursor: Option<usize>,
+    /// Where the events go, for [`spawn_iter`].
+    observer: Option<Observer>,
+    ascii_frame: bool,
+    line_endings: LineEndings,
+    cycles: CycleDetector,
+    fail_on_loop: Option<usize>,
+    /// The block of lines the command was stopped for repeating.
+    looped: Option<Cycle>,
+    /// When the command starts, while waiting for [`RunOptions::delay`].
+    starting: Option<Instant>,
+    ready: Option<Ready>,
+    /// When the line matching `ready` came, since the start.
+    ready_at: Option<Duration>,
+    /// Whether the command exited and the run waits for `ready`.
+    waiting_ready: bool,
+    /// Whether `ready` didn't come within its timeout.
+    not_ready: bool,
+    sync_dump: bool,
+    flatten_whitespace: bool,
+    stop_on_tui: bool,
+    /// Whether the output showed a full-screen interface, with
+    /// `stop_on_tui`.
+    tui: bool,
+}
+
+#[derive(Clone, Debug)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    /// Whether this is the error stream.
+    pub fn is_error(&self) -> bool {
+        matches!(self, Stream::Stderr)
+    }
+
+    /// Applies the color used for lines of this stream in the preview.
+    pub fn display_style<D>(&self, msg: StyledObject<D>) -> StyledObject<D> {
+        if self.is_error() {
+            msg.yellow()
+        } else {
+            msg.cyan()
+        }
+    }
+}
+
+impl Display for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stream::Stdout => write!(f, "stdout"),
+            Stream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Line {
+    /// The text of the line, without its line ending.
+    pub line: Arc<str>,
+    pub stream: Stream,
+    /// Whether the line ended with `\r\n`, as printed by Windows tools,
+    /// rather than `\n`.
+    pub crlf: bool,
+}
+
+/// Adjustments to the display requested while the command runs.
+#[derive(Clone, Copy, Debug)]
+enum Control {
+    /// Shows one more line, going back to the default height once the
+    /// terminal is full (`SIGUSR1`).
+    GrowWindow,
+    /// Switches between all output and only stderr (`SIGUSR2`).
+    ToggleStderrOnly,
+}
+
+/// What the reader threads send to the main thread.
+#[derive(Debug)]
+enum Event {
+    Line(Line),
+    Bytes(Stream, Vec<u8>),
+    Control(Control),
+    /// The run should stop: the command is asked to terminate.
+    Cancel,
+    /// The command should stop, as with `Cancel`, but because of what it
+    /// printed: the run isn't cancelled.
+    Stop,
+    /// The output of a stream showed a full-screen interface, see
+    /// [`_draws_tui`]. Sent at most once per stream.
+    Tui,
+    /// The child exited and all of its output has been sent.
+    Done,
+    /// The command was spawned, with this pid.
+    Started(u32),
+    /// How far the command has read the file given as
+    /// [`RunOptions::fd_progress`], and the size of the file.
+    #[cfg_attr(
+        not(all(target_os = "linux", feature = "fd-progress")),
+        allow(dead_code)
+    )]
+    FileProgress(u64, u64),
+    /// Sent every [`FRAME_INTERVAL`], to draw the output held back and keep
+    /// the time without output up to date.
+    Tick,
+}
+
+type Reader =
+    fn(Box<dyn Read + Send>, &Sender<Event>, Stream, Decoding) -> Result<()>;
+
+impl State {
+    fn new(options: &RunOptions, out: &Term) -> Self {
+        let (term_lines, term_columns) = Term::stdout().size();
+        let pb = match options.expected_lines {
+            _ if options.plain => ProgressBar::hidden(),
+            Some(n) => ProgressBar::new(n),
+            None => ProgressBar::new_spinner(),
+        };
+        let pb = match &options.multi {
+            Some(multi) if !options.plain => multi.add(pb),
+            _ => pb,
+        };
+        pb.enable_steady_tick(Duration::from_millis(200));
+        let mut buf = Buffer::new(options.max_buffer_lines);
+        if let Some(n) = options.expected_lines {
+            buf.reserve(min(n, MAX_RESERVED_LINES) as usize);
+        }
+        let mut state = Self {
+            buf,
+            stderr_lines: 0,
+            msg_buf: String::new(),
+            pb,
+            max_lines: MAX_LINES,
+            term_lines,
+            term_columns,
+            stderr_only: false,
+            capture: options.capture.clone(),
+            screen: options
+                .raw_preview
+                .then(|| Screen::new(MAX_LINES as usize, options.encoding())),
+            plain: options.plain,
+            out: options.output.clone().unwrap_or_else(|| out.clone()),
+            frame_lines: 0,
+            expected_lines: options.expected_lines,
+            dump_dir: options.dump_dir.clone(),
+            started: Instant::now(),
+            first_output: None,
+            pin_last_error: options.pin_last_error,
+            trim_trailing_blank: options.trim_trailing_blank,
+            pinned: None,
+            cancelled: false,
+            throughput: options.throughput.map(|unit| {
+                Arc::new(Mutex::new(Throughput::new(unit, Instant::now())))
+            }),
+            file_size: None,
+            truncate: options.truncate,
+            contrast: options.contrast,
+            tests: options
+                .test_format
+                .map(|format| Arc::new(Mutex::new(TestResults::new(format)))),
+            counters: (!options.counters.is_empty()).then(|| {
+                Arc::new(Mutex::new(Counters::new(options.counters.clone())))
+            }),
+            heartbeat: options.heartbeat,
+            last_output: Instant::now(),
+            display_policy: options.display_policy,
+            drawn_at: Instant::now(),
+            drawn: 0,
+            skipped: 0,
+            pending: false,
+            max_runtime: options.max_runtime,
+            overran: false,
+            strip_prefix: options.strip_prefix.as_ref().map(|regex| {
+                Regex::new(&format!("^(?:{})", regex.as_str()))
+                    .expect("a valid regex stays valid in a group")
+            }),
+            strip_prefix_dump: options.strip_prefix_dump,
+            line_numbers: options.line_numbers.clone(),
+            color_line_numbers: options.color_line_numbers,
+            dump_header: None,
+            announce_every: options.announce_every,
+            announced: 0,
+            markers: Vec::new(),
+            command: String::new(),
+            rows: None,
+            cursor: None,
+            observer: None,
+            ascii_frame: options.ascii_frame,
+            line_endings: options.dump_line_endings,
+            cycles: CycleDetector::new(),
+            fail_on_loop: options.fail_on_loop,
+            looped: None,
+            starting: None,
+            ready: options.ready.clone(),
+            ready_at: None,
+            waiting_ready: false,
+            not_ready: false,
+            sync_dump: options.sync_dump,
+            flatten_whitespace: options.flatten_whitespace,
+            stop_on_tui: options.stop_on_tui,
+            tui: false,
+        };
+        rebuild_layout(&mut state);
+        state
+    }
+
+    /// Saves the output, returning where. On failure, the path is returned
+    /// along the error if the file was already created.
+    fn dump(&self) -> Result<PathBuf, (io::Error, Option<PathBuf>)> {
+        let (temp, path) = self.create_dump().map_err(|e| (e, None))?;
+        let mut buf = BufWriter::new(&temp);
+        let eol = self.line_endings.of(None);
+        if let Some(header) = &self.dump_header {
+            buf.write_all(header.replace('\n', eol).as_bytes())
+                .map_err(|e| (e, Some(path.clone())))?;
+        }
+        let end = if self.trim_trailing_blank {
+            self.buf
+                .iter()
+                .rposition(|line| !line.line.trim().is_empty())
+                .map_or(0, |last| last + 1)
+        } else {
+            self.buf.len()
+        };
+        let mut markers = self.markers.iter().peekable();
+        let lines = self.buf.iter().take(end);
+        // Lines dropped from the front of `buf` still count.
+        for (n, line) in (self.buf.first() + 1..).zip(lines) {
+            while let Some((_, marker)) = markers.next_if(|(at, _)| *at < n) {
+                write!(&mut buf, "{marker}{eol}")
+                    .map_err(|e| (e, Some(path.clone())))?;
+            }
+            let text = if self.strip_prefix_dump {
+                self.strip(&line.line)
+            } else {
+                &line.line
+            };
+            let number = match &self.line_numbers {
+                // The dump is a file, where colors are off by default.
+                Some(format) if self.color_line_numbers => line
+                    .stream
+                    .display_style(style(format.format(n)).force_styling(true))
+                    .to_string(),
+                Some(format) => format.format(n),
+                None => String::new(),
+            };
+            let ending = self.line_endings.of(Some(line));
+            write!(&mut buf, "{number}{text}{ending}")
+                .map_err(|e| (e, Some(path.clone())))?;
+        }
+        for (_, marker) in markers {
+            write!(&mut buf, "{marker}{eol}")
+                .map_err(|e| (e, Some(path.clone())))?;
+        }
+        buf.flush().map_err(|e| (e, Some(path.clone())))?;
+        if self.sync_dump {
+            _sync(&temp, &path).map_err(|e| (e, Some(path.clone())))?;
+        }
+        Ok(path)
+    }
+
+    /// Creates a new file to keep, where dumps go. In `dump_dir`, it is
+    /// named after the program, like `make-3fJ1xq.log`, to tell the outputs
+    /// of many runs apart.
+    fn create_dump(&self) -> io::Result<(File, PathBuf)> {
+        let temp = match &self.dump_dir {
+            Some(dir) => tempfile::Builder::new()
+                .prefix(&format!("{}-", _program_name(&self.command)))
+                .suffix(".log")
+                .tempfile_in(dir),
+            None => tempfile::NamedTempFile::new(),
+        }?;
+        temp.keep().map_err(|e| e.error)
+    }
+
+    /// `line` as shown in the window, before it is clipped: without the
+    /// prefix matched by `strip_prefix`, and flattened if asked.
+    fn shown<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        let line = self.strip(line);
+        if self.flatten_whitespace {
+            _flatten_whitespace(line)
+        } else {
+            Cow::Borrowed(line)
+        }
+    }
+
+    /// `line` without the prefix matched by `strip_prefix`.
+    fn strip<'a>(&self, line: &'a str) -> &'a str {
+        match self
+            .strip_prefix
+            .as_ref()
+            .and_then(|regex| regex.find(line))
+        {
+            Some(prefix) => &line[prefix.end()..],
+            None => line,
+        }
+    }
+
+    fn stats(&self) -> RunStats {
+        RunStats {
+            lines: self.buf.total(),
+            stderr_lines: self.stderr_lines,
+            elapsed: self.started.elapsed(),
+            first_output: self
+                .first_output
+                .map(|at| at.duration_since(self.started)),
+            // Only known once the command has been waited for.
+            peak_rss: None,
+        }
+    }
+}
+
+/// The name of the program `command` runs, as fits in a file name.
+fn _program_name(command: &str) -> String {
+    let program = command.split(' ').next().unwrap_or_default();
+    let name = program.rsplit('/').next().unwrap_or_default();
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    match name.trim_start_matches('.') {
+        "" => "output".into(),
+        name => name.into(),
+    }
+}
+
+/// Waits for `file`, just written at `path`, to be on disk. Its entry in
+/// the directory is synced too, where the platform allows it, since the file
+/// is new.
+fn _sync(file: &File, path: &Path) -> io::Result<()> {
+    file.sync_all()?;
+    match path.parent() {
+        Some(dir) if cfg!(unix) => File::open(dir)?.sync_all(),
+        _ => Ok(()),
+    }
+}
+
+/// Runs `command` and returns how it exited with every line it printed, on
+/// either stream, in order. Nothing is drawn and the output isn't saved: of
+/// `options`, only what changes how the command runs (environment, working
+/// directory, input, limits, wrapper...), how its output is decoded, and
+/// `cancel` are used.
+pub fn run_capture<S>(
+    command: NonEmpty<S>,
+    options: &RunOptions,
+) -> Result<(ExitStatus, Vec<Line>)>
+where
+    S: AsRef<OsStr>,
+{
+    let run = ResolvedRun::resolve(command, options);
+    run.limits.check()?;
+    let input = match &options.stdin {
+        Some(stdin) => _feed(stdin, options)?,
+        None => Input::Inherit,
+    };
+    let (sender, receiver) = channel();
+    if let Some(cancel) = &options.cancel {
+        cancel.attach(sender.clone());
+    }
+    let mut lines = vec![];
+    let exited = spawn_events(
+        (&mut run.command(), &Spawner::Threads),
+        false,
+        options.decoding(),
+        input,
+        None,
+        (sender, &receiver),
+        |event| {
+            if let Event::Line(line) = event {
+                lines.push(line);
+            }
+            Ok(())
+        },
+    );
+    if let Some(cancel) = &options.cancel {
+        cancel.detach();
+    }
+    Ok((exited?.status, lines))
+}
+
+/// The input `stdin` gives the command, as [`RunOptions`] repeats it.
+fn _feed(stdin: &StdinInput, options: &RunOptions) -> Result<Input> {
+    let from: Box<dyn stdin::Source> = match stdin {
+        StdinInput::Data(data) => Box::new(io::Cursor::new(data.clone())),
+        StdinInput::File(path) => {
+            Box::new(File::open(path).map_err(AthensError::InputFailed)?)
+        }
+    };
+    Ok(Input::Feed {
+        from,
+        repeat: options.stdin_repeat.unwrap_or(1),
+        delimiter: options.stdin_delimiter.clone(),
+    })
+}
+
+pub fn build_command<S>(words: NonEmpty<S>) -> Command
+where
+    S: AsRef<OsStr>,
+{
+    let mut cmd = Command::new(words.first());
+    cmd.args(words.tail());
+    cmd.env(NESTED_ENV, "1");
+    cmd
+}
+
+/// Like [`build_command`], for callers that don't have a [`NonEmpty`] at
+/// hand. Fails if `words` is empty.
+pub fn build_command_iter<S, I>(words: I) -> Result<Command>
+where
+    S: AsRef<OsStr>,
+    I: IntoIterator<Item = S>,
+{
+    let mut words = words.into_iter();
+    let first = words.next().ok_or(AthensError::EmptyCommand)?;
+    Ok(build_command(NonEmpty::from((first, words.collect()))))
+}
+
+fn _read_stream<R>(
+    reader: R,
+    out: &Sender<Event>,
+    stream: Stream,
+    decoding: Decoding,
+) -> Result<()>
+where
+    R: Read,
+{
+    let mut decoder = LineDecoder::new(decoding);
+    let tui = Cell::new(false);
+    let mut send = |line: &[u8]| {
+        _watch_tui(&tui, line, out);
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let crlf = line.ends_with(b"\r");
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let line = decoder.decode(line);
+        out.send(Event::Line(Line {
+            line: line.into(),
+            stream: stream.clone(),
+            crlf,
+        }))
+        .map_err(|_| AthensError::ChannelClosed)
+    };
+    let mut reader = BufReader::new(reader);
+    // The start of a line that didn't fit in what was read so far.
+    let mut partial = Vec::new();
+    loop {
+        let available = match reader.fill_buf() {
+            Ok(available) => available,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => return Err(AthensError::ReadFailed(stream, e)),
+        };
+        if available.is_empty() {
+            break;
+        }
+        let used = match memchr::memchr(b'\n', available) {
+            // Most lines are whole in the buffer, and are sent from it.
+            Some(end) if partial.is_empty() => {
+                send(&available[..end])?;
+                end + 1
+            }
+            Some(end) => {
+                partial.extend_from_slice(&available[..end]);
+                send(&partial)?;
+                partial.clear();
+                end + 1
+            }
+            None => {
+                // A full-screen interface is drawn without ending lines.
+                _watch_tui(&tui, available, out);
+                partial.extend_from_slice(available);
+                available.len()
+            }
+        };
+        reader.consume(used);
+    }
+    if !partial.is_empty() {
+        send(&partial)?;
+    }
+    Ok(())
+}
+
+/// Sends raw chunks; they are decoded by the [`Screen`].
+fn _read_bytes<R>(
+    mut reader: R,
+    out: &Sender<Event>,
+    stream: Stream,
+    _decoding: Decoding,
+) -> Result<()>
+where
+    R: Read,
+{
+    let tui = Cell::new(false);
+    let mut buf = [0; 4096];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(AthensError::ReadFailed(stream, e)),
+        };
+        _watch_tui(&tui, &buf[..n], out);
+        out.send(Event::Bytes(stream.clone(), buf[..n].to_vec()))
+            .map_err(|_| AthensError::ChannelClosed)?;
+    }
+}
+
+/// Sends [`Event::Tui`] if `bytes` show a full-screen interface, unless
+/// `seen` says it was already sent.
+fn _watch_tui(seen: &Cell<bool>, bytes: &[u8], out: &Sender<Event>) {
+    if !seen.get()
+        && memchr::memchr(b'\x1b', bytes).is_some()
+        && _draws_tui(&String::from_utf8_lossy(bytes))
+    {
+        seen.set(true);
+        let _ = out.send(Event::Tui);
+    }
+}
+
+/// How the command ended, with what the OS reports about its run.
+struct Exited {
+    status: ExitStatus,
+    peak_rss: Option<u64>,
+}
+
+/// Waits for `child` and reads its peak RSS, which `wait4` reports in KiB
+/// on Linux and in bytes on macOS.
+#[cfg(unix)]
+fn _wait(child: &mut Child) -> io::Result<Exited> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    // SAFETY: `rusage` is plain data, filled in by `wait4`.
+    let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+    loop {
+        // SAFETY: both pointers are valid for the call. The child is only
+        // waited for here, so its pid can't have been reused.
+        let pid = unsafe {
+            libc::wait4(child.id() as libc::pid_t, &mut status, 0, &mut usage)
+        };
+        if pid != -1 {
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+    let max_rss = usage.ru_maxrss.max(0) as u64;
+    let peak_rss = if cfg!(target_os = "macos") {
+        max_rss
+    } else {
+        max_rss * 1024
+    };
+    Ok(Exited {
+        status: ExitStatus::from_raw(status),
+        peak_rss: Some(peak_rss),
+    })
+}
+
+#[cfg(not(unix))]
+fn _wait(child: &mut Child) -> io::Result<Exited> {
+    let status = child.wait()?;
+    Ok(Exited {
+        status,
+        peak_rss: None,
+    })
+}
+
+fn collect(
+    child: &mut Child,
+    sender: Sender<Event>,
+    raw: bool,
+    decoding: Decoding,
+    exited: Sender<Option<ExitStatus>>,
+    hashers: Option<Hashers>,
+) -> Result<Exited> {
+    let err = child
+        .stderr
+        .take()
+        .ok_or(AthensError::StreamTakeFailed(Stream::Stderr))?;
+    let out = child
+        .stdout
+        .take()
+        .ok_or(AthensError::StreamTakeFailed(Stream::Stdout))?;
+    let read: Reader = if raw { _read_bytes } else { _read_stream };
+    let (err, out): (Box<dyn Read + Send>, Box<dyn Read + Send>) =
+        match &hashers {
+            Some(hashers) => (
+                Box::new(hashers.wrap(&Stream::Stderr, uring::pipe(err))),
+                Box::new(hashers.wrap(&Stream::Stdout, uring::pipe(out))),
+            ),
+            None => (uring::pipe(err), uring::pipe(out)),
+        };
+    let t1 = thread::spawn({
+        let sender = sender.clone();
+        move || read(err, &sender, Stream::Stderr, decoding)
+    });
+    let t2 = thread::spawn({
+        let sender = sender.clone();
+        move || read(out, &sender, Stream::Stdout, decoding)
+    });
+    let status = _wait(child);
+    let _ = exited.send(status.as_ref().ok().map(|exited| exited.status));
+    // Both readers are joined before looking at their results, so a stream
+    // that closes early never cuts short the lines still in flight on the
+    // other one. Only then the sender is dropped, letting the receiver finish.
+    let err_read = t1.join();
+    let out_read = t2.join();
+    drop(sender);
+    err_read.map_err(|_| AthensError::ReaderPanicked(Stream::Stderr))??;
+    out_read.map_err(|_| AthensError::ReaderPanicked(Stream::Stdout))??;
+    status.map_err(AthensError::WaitFailed)
+}
+
+/// How the command is spawned, and its output read.
+#[derive(Clone, Debug)]
+enum Spawner {
+    /// With `std::process`, the output read from threads.
+    Threads,
+    /// With `tokio::process` on this runtime, the output read from its
+    /// tasks.
+    #[cfg(feature = "async-runtime")]
+    Tokio(tokio::runtime::Handle),
+}
+
+/// What collects the output of the command and waits for it to exit.
+enum Collector {
+    Thread(thread::JoinHandle<Result<Exited>>),
+    #[cfg(feature = "async-runtime")]
+    Task(
+        tokio::runtime::Handle,
+        tokio::task::JoinHandle<Result<Exited>>,
+    ),
+}
+
+impl Collector {
+    fn join(self) -> Result<Exited> {
+        match self {
+            Self::Thread(thread) => {
+                thread.join().map_err(|_| AthensError::CollectorPanicked)?
+            }
+            #[cfg(feature = "async-runtime")]
+            Self::Task(runtime, task) => runtime
+                .block_on(task)
+                .map_err(|_| AthensError::CollectorPanicked)?,
+        }
+    }
+}
+
+/// Spawns `cmd` and starts collecting its output in a thread, returning
+/// its pid, where its status is sent once it exited, and the thread.
+fn _start(
+    cmd: &mut Command,
+    raw: bool,
+    decoding: Decoding,
+    input: Input,
+    hashers: Option<Hashers>,
+    sender: Sender<Event>,
+) -> Result<(u32, Receiver<Option<ExitStatus>>, Collector)> {
+    input.configure(cmd);
+    let mut child = cmd.spawn().map_err(AthensError::SpawnFailed)?;
+    input.start(&mut child);
+    let pid = child.id();
+    let _ = sender.send(Event::Started(pid));
+    let (exited_sender, exited) = channel();
+    let t = thread::spawn({
+        let done = sender.clone();
+        move || {
+            let status = collect(
+                &mut child,
+                sender,
+                raw,
+                decoding,
+                exited_sender,
+                hashers,
+            );
+            let _ = done.send(Event::Done);
+            status
+        }
+    });
+    Ok((pid, exited, Collector::Thread(t)))
+}
+
+fn spawn_events<F>(
+    (cmd, spawner): (&mut Command, &Spawner),
+    raw: bool,
+    decoding: Decoding,
+    input: Input,
+    hashers: Option<Hashers>,
+    (sender, receiver): (Sender<Event>, &Receiver<Event>),
+    mut process: F,
+) -> Result<Exited>
+where
+    F: FnMut(Event) -> Result<()>,
+{
+    cmd.stderr(Stdio::piped()).stdout(Stdio::piped());
+    // The command leads its own process group, so stopping it reaches its
+    // children too, unless it reads our terminal: only the foreground group
+    // may.
+    let group = !matches!(input, Input::Inherit) || !io::stdin().is_terminal();
+    #[cfg(unix)]
+    if group {
+        use std::os::unix::process::CommandExt;
+
+        cmd.process_group(0);
+    }
+    let (pid, exited, collector) = match spawner {
+        Spawner::Threads => _start(cmd, raw, decoding, input, hashers, sender)?,
+        #[cfg(feature = "async-runtime")]
+        Spawner::Tokio(runtime) => async_run::start(
+            runtime, cmd, raw, decoding, input, hashers, sender,
+        )?,
+    };
+    // Once asked to stop, the command gets `KILL_GRACE` to exit after
+    // `SIGTERM`, and then as long again after `SIGKILL` for the pipes to
+    // close. Past that, the readers are left blocked (a grandchild out of
+    // the group may still hold the pipes) and the run ends without them.
+    let mut stop: Option<(Instant, bool)> = None;
+    loop {
+        // Other senders (like the signal listener) may outlive the child, so
+        // the end of the run is marked explicitly instead of by closing the
+        // channel.
+        let x = match stop {
+            None => receiver.recv().map_err(|_| AthensError::ChannelClosed)?,
+            Some((deadline, killed)) => {
+                let wait = deadline.saturating_duration_since(Instant::now());
+                match receiver.recv_timeout(wait) {
+                    Ok(x) => x,
+                    Err(RecvTimeoutError::Timeout) if !killed => {
+                        terminate(pid, group, true);
+                        stop = Some((Instant::now() + KILL_GRACE, true));
+                        continue;
+                    }
+                    Err(_) => {
+                        return exited
+                            .recv()
+                            .ok()
+                            .flatten()
+                            .map(|status| Exited {
+                                status,
+                                peak_rss: None,
+                            })
+                            .ok_or_else(|| {
+                                AthensError::WaitFailed(io::Error::other(
+                                    "command did not exit",
+                                ))
+                            });
+                    }
+                }
+            }
+        };
+        match x {
+            Event::Done => break,
+            Event::Cancel | Event::Stop => {
+                terminate(pid, group, false);
+                stop.get_or_insert((Instant::now() + KILL_GRACE, false));
+            }
+            _ => (),
+        }
+        process(x)?;
+    }
+    collector.join()
+}
+
+/// Asks the command to stop, or kills it when `force` is set, along with
+/// the rest of its process `group` when it leads one.
+#[cfg(unix)]
+fn terminate(pid: u32, group: bool, force: bool) {
+    let signal = if force { libc::SIGKILL } else { libc::SIGTERM };
+    let pid = pid as libc::pid_t;
+    // SAFETY: `kill` has no memory safety requirements. The group keeps the
+    // pid of its leader, our child, from being reused while any of its
+    // processes is left.
+    unsafe {
+        libc::kill(if group { -pid } else { pid }, signal);
+    }
+}
+
+#[cfg(not(unix))]
+fn terminate(_pid: u32, _group: bool, _force: bool) {}
+
+/// The signal that ended the command, when it didn't exit on its own.
+#[cfg(unix)]
+pub fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+
+    status.signal()
+}
+
+#[cfg(not(unix))]
+pub fn exit_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// The exit code a shell would report for `status`: the command's own, or
+/// 128 + N if it was killed by signal N.
+pub fn exit_code(status: &ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| exit_signal(status).map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+/// How the command ended, like `exited with status: 3` or `terminated by
+/// signal 9`.
+pub fn describe_exit(status: &ExitStatus) -> String {
+    match (status.code(), exit_signal(status)) {
+        (Some(code), _) => format!("exited with status: {code}"),
+        (None, Some(signal)) => match _limit_exceeded(signal) {
+            Some(limit) => {
+                format!("terminated by signal {signal} ({limit} exceeded)")
+            }
+            None => format!("terminated by signal {signal}"),
+        },
+        (None, None) => "terminated by signal".into(),
+    }
+}
+
+/// The resource limit a command killed by `signal` ran into, for the
+/// signals sent when rlimits are reached.
+#[cfg(unix)]
+fn _limit_exceeded(signal: i32) -> Option<&'static str> {
+    match signal {
+        libc::SIGXCPU => Some("CPU time limit"),
+        libc::SIGXFSZ => Some("file size limit"),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn _limit_exceeded(_signal: i32) -> Option<&'static str> {
+    None
+}
+
+pub fn spawn<F>(cmd: &mut Command, mut process: F) -> Result<ExitStatus>
+where
+    F: FnMut(&Line) -> Result<()>,
+{
+    let input = Input::Inherit;
+    let (sender, receiver) = channel();
+    spawn_events(
+        (cmd, &Spawner::Threads),
+        false,
+        Decoding::Fixed(UTF_8),
+        input,
+        None,
+        (sender, &receiver),
+        |event| match event {
+            Event::Line(line) => process(&line),
+            _ => Ok(()),
+        },
+    )
+    .map(|exited| exited.status)
+}
+
+/// Appends a framed row to `out`, on a new line unless it's the first.
+fn _draw_line<S>(out: &mut String, line: S, width: usize, border: &str)
+where
+    S: Display,
+{
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(border);
+    let start = out.len();
+    let _ = fmt::Write::write_fmt(out, format_args!("{line}"));
+    // Padded by display width: styles take none, and some characters two.
+    let used = _text_width(&out[start..]);
+    out.extend(std::iter::repeat_n(' ', width.saturating_sub(used)));
+    out.push_str(border);
+}
+
+/// The characters of the box: the corners clockwise from the top left, then
+/// the horizontal and vertical lines.
+fn _frame_chars(state: &State) -> [char; 6] {
+    if state.ascii_frame {
+        ['+', '+', '+', '+', '-', '|']
+    } else {
+        ['╭', '╮', '╯', '╰', '─', '│']
+    }
+}
+
+/// The style of the frame: yellow once the run is past `max_runtime`.
+fn _frame_style(state: &State) -> console::Style {
+    let style = console::Style::new();
+    if state.overran {
+        style.yellow()
+    } else {
+        style
+    }
+}
+
+/// Columns taken by `c` in a terminal; control characters count as none.
+///
+/// Measured as console does, and so indicatif when it lays out the window:
+/// its version of the Unicode tables may give some characters (format
+/// characters, some combining marks) another width than ours would.
+fn _char_width(c: char) -> usize {
+    console::measure_text_width(c.encode_utf8(&mut [0; 4]))
+}
+
+/// Columns taken by `text` in a terminal, leaving ANSI codes out.
+fn _text_width(text: &str) -> usize {
+    // The common case, and much faster.
+    if text.is_ascii() && !text.contains('\x1b') {
+        return text.bytes().filter(|b| !b.is_ascii_control()).count();
+    }
+    AnsiCodeIterator::new(text)
+        .filter(|(_, is_ansi)| !is_ansi)
+        .flat_map(|(part, _)| part.chars())
+        .map(_char_width)
+        .sum()
+}
+
+/// Length in bytes of the longest run of `chars` fitting in `width`
+/// columns.
+fn _fit<I>(chars: I, width: usize) -> usize
+where
+    I: Iterator<Item = char>,
+{
+    let mut used = 0;
+    let mut len = 0;
+    for c in chars {
+        used += _char_width(c);
+        if used > width {
+            break;
+        }
+        len += c.len_utf8();
+    }
+    len
+}
+
+fn _build_style(state: &State) -> ProgressStyle {
+    let width = (state.term_columns as usize).saturating_sub(2);
+    let title = if state.stderr_only {
+        "Running (stderr only)"
+    } else {
+        "Running"
+    };
+    let mut width_top = width.saturating_sub(title.chars().count() + 4);
+    // The rate is padded too, as it changes on every tick.
+    let rate =
+        if state.throughput.is_some() && width_top >= THROUGHPUT_WIDTH + 5 {
+            width_top -= THROUGHPUT_WIDTH + 1;
+            format!("{{throughput:>{THROUGHPUT_WIDTH}}} ")
+        } else {
+            String::new()
+        };
+    let tally = if state.tests.is_some() && width_top >= TESTS_WIDTH + 5 {
+        width_top -= TESTS_WIDTH + 1;
+        format!("{{tests:<{TESTS_WIDTH}}} ")
+    } else {
+        String::new()
+    };
+    let counters = state.counters.as_ref().map_or(0, |counters| {
+        counters.lock().expect("not poisoned").label_width()
+    });
+    let counters = if counters > 0 && width_top >= counters + 5 {
+        width_top -= counters + 1;
+        "{counters} "
+    } else {
+        ""
+    };
+    // Percent and ETA are padded so the frame keeps its width, which is
+    // `DETERMINATE_WIDTH` once rendered.
+    let determinate = "{bar:20} {percent:>3}% ETA {eta:>4} ";
+    let progress = if (state.expected_lines.is_some()
+        || state.file_size.is_some())
+        && width_top >= DETERMINATE_WIDTH + 4
+    {
+        width_top -= DETERMINATE_WIDTH;
+        determinate
+    } else {
+        ""
+    };
+    let frame = _frame_style(state);
+    let [top_left, top_right, bottom_right, bottom_left, line, _] =
+        _frame_chars(state);
+    let line = |width| line.to_string().repeat(width);
+    let top = format!(
+        "{}{{spinner:.dim.bold}} {tally}{counters}{progress}{rate}{}",
+        frame.apply_to(format!("{top_left} {title} ")),
+        frame.apply_to(format!("{}{top_right}", line(width_top))),
+    );
+    let bottom =
+        frame.apply_to(format!("{bottom_left}{}{bottom_right}", line(width)));
+    let progress_chars = if state.ascii_frame {
+        "#>-"
+    } else {
+        "█▉▊▋▌▍▎▏ "
+    };
+    let mut style =
+        ProgressStyle::with_template(&format!("{top}\n{{msg}}\n{bottom}"))
+            .expect("error in the ProgressStyle template")
+            .tick_chars("/|\\- ")
+            .progress_chars(progress_chars);
+    if let Some(tests) = &state.tests {
+        let tests = tests.clone();
+        style = style.with_key(
+            "tests",
+            move |_: &ProgressState, w: &mut dyn fmt::Write| {
+                let _ =
+                    w.write_str(&tests.lock().expect("not poisoned").label());
+            },
+        );
+    }
+    if let Some(counters) = &state.counters {
+        let counters = counters.clone();
+        style = style.with_key(
+            "counters",
+            move |_: &ProgressState, w: &mut dyn fmt::Write| {
+                let counters = counters.lock().expect("not poisoned");
+                let _ = w.write_str(&counters.label());
+            },
+        );
+    }
+    match &state.throughput {
+        Some(throughput) => {
+            let throughput = throughput.clone();
+            style.with_key(
+                "throughput",
+                move |_: &ProgressState, w: &mut dyn fmt::Write| {
+                    let throughput = throughput.lock().expect("not poisoned");
+                    let _ = w.write_str(&throughput.label(Instant::now()));
+                },
+            )
+        }
+        None => style,
+    }
+}
+
+/// Largest window that fits the terminal, leaving room for the frame.
+fn _max_window(state: &State) -> u16 {
+    state.term_lines.saturating_sub(3).max(1)
+}
+
+/// Re-derives everything that depends on the terminal size or on the
+/// display options, and redraws the frame right away.
+fn rebuild_layout(state: &mut State) {
+    state.max_lines = state.max_lines.min(_max_window(state));
+    if let Some(screen) = &mut state.screen {
+        screen.set_max_rows(state.max_lines as usize);
+    }
+    state.pb.set_style(_build_style(state));
+    let msg = _build_msg(state);
+    state.pb.set_message(msg);
+}
+
+fn _check_resize(state: &mut State) {
+    let (term_lines, term_columns) = Term::stdout().size();
+    if (term_lines, term_columns) != (state.term_lines, state.term_columns) {
+        state.term_lines = term_lines;
+        state.term_columns = term_columns;
+        rebuild_layout(state);
+    }
+}
+
+fn control(state: &mut State, control: Control) {
+    match control {
+        Control::GrowWindow => {
+            state.max_lines = if state.max_lines >= _max_window(state) {
+                MAX_LINES
+            } else {
+                state.max_lines + 1
+            };
+        }
+        Control::ToggleStderrOnly => state.stderr_only = !state.stderr_only,
+    }
+    rebuild_layout(state);
+}
+
+/// `line` with nothing a terminal would act on. ANSI codes are dropped, and
+/// so are string sequences like the OSC setting the window title, along with
+/// the rest of the line when one isn't terminated. Other control characters
+/// are shown as their symbol, like `␇` for BEL, or as `�`; tabs as a space.
+fn _visible(line: &str) -> Cow<'_, str> {
+    if !line.contains(char::is_control) {
+        return Cow::Borrowed(line);
+    }
+    let line = _strip_strings(line);
+    let line = console::strip_ansi_codes(&line);
+    Cow::Owned(
+        line.chars()
+            .map(|c| match c {
+                '\t' => ' ',
+                '\0'..='\x1f' => char::from_u32(0x2400 + c as u32)
+                    .unwrap_or(char::REPLACEMENT_CHARACTER),
+                '\x7f' => '␡',
+                c if c.is_control() => char::REPLACEMENT_CHARACTER,
+                c => c,
+            })
+            .collect(),
+    )
+}
+
+/// `line` without its string sequences (OSC, DCS, SOS, PM and APC), which
+/// end with BEL or ST. An ESC alone ends one too, as in terminals.
+fn _strip_strings(line: &str) -> Cow<'_, str> {
+    let Some((start, len)) = _string_start(line) else {
+        return Cow::Borrowed(line);
+    };
+    let mut text = String::with_capacity(line.len());
+    let mut rest = line;
+    let mut next = Some((start, len));
+    while let Some((start, len)) = next {
+        text.push_str(&rest[..start]);
+        rest = &rest[start + len..];
+        rest = &rest
+            [rest.find(['\x07', '\u{9c}', '\x1b']).unwrap_or(rest.len())..];
+        rest = rest
+            .strip_prefix(['\x07', '\u{9c}'])
+            .or_else(|| rest.strip_prefix("\x1b\\"))
+            .unwrap_or(rest);
+        next = _string_start(rest);
+    }
+    text.push_str(rest);
+    Cow::Owned(text)
+}
+
+/// Where the first string sequence in `text` starts, and the length of what
+/// introduces it.
+fn _string_start(text: &str) -> Option<(usize, usize)> {
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\u{90}' | '\u{98}' | '\u{9d}'..='\u{9f}' => {
+                return Some((i, c.len_utf8()))
+            }
+            '\x1b'
+                if chars.next_if(|(_, c)| "]PX^_".contains(*c)).is_some() =>
+            {
+                return Some((i, 2))
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// `line` with every run of whitespace collapsed to a single space.
+fn _flatten_whitespace(line: &str) -> Cow<'_, str> {
+    if !line.contains("  ")
+        && !line.contains(|c: char| c.is_whitespace() && c != ' ')
+    {
+        return Cow::Borrowed(line);
+    }
+    let mut text = String::with_capacity(line.len());
+    let mut space = false;
+    for c in line.chars() {
+        if !c.is_whitespace() {
+            text.push(c);
+        } else if !space {
+            text.push(' ');
+        }
+        space = c.is_whitespace();
+    }
+    Cow::Owned(text)
+}
+
+/// `line` as shown in the window: [`_visible`], and cut to `width` columns
+/// as `truncate` says. The ellipsis takes one of them.
+fn _clip(line: &str, width: usize, truncate: Truncate) -> Cow<'_, str> {
+    let line = _visible(line);
+    if _text_width(&line) <= width {
+        return line;
+    }
+    let kept = width.saturating_sub(1);
+    let head = |width| &line[.._fit(line.chars(), width)];
+    let tail = |width| &line[line.len() - _fit(line.chars().rev(), width)..];
+    match truncate {
+        Truncate::End => Cow::Owned(head(width).into()),
+        _ if width == 0 => Cow::Borrowed(""),
+        Truncate::Middle => {
+            Cow::Owned(format!("{}…{}", head(kept - kept / 2), tail(kept / 2)))
+        }
+        Truncate::Start => Cow::Owned(format!("…{}", tail(kept))),
+    }
+}
+
+/// Writes the rows of the window to `out`, one per line, framed and padded
+/// to the window height.
+fn _write_rows(state: &State, out: &mut String) {
+    let max_lines = state.max_lines as usize;
+    let width = (state.term_columns as usize).saturating_sub(2);
+    let marked = state.skipped > 0 && state.screen.is_none();
+    let tail = _tail_rows(state, marked);
+    let silence = _silence(state);
+    let cycle = _cycle(state);
+    let border = _frame_style(state)
+        .apply_to(_frame_chars(state)[5])
+        .to_string();
+    // With `Lag`, the window ends where the last redraw got to, and it
+    // ends before the repetitions of a block of lines anyway.
+    let end = match state.display_policy {
+        DisplayPolicy::Lag => state.drawn,
+        _ => usize::MAX,
+    }
+    .min(_shown_end(state));
+    let pinned = state.pinned.as_ref().map(|(index, ..)| *index);
+    let shown = |line: &Line| !state.stderr_only || line.stream.is_error();
+    let screen_rows;
+    let rows = match &state.screen {
+        Some(screen) => {
+            let pinned = state.pinned.as_ref().map(|(_, line, _)| line);
+            screen_rows = screen.rows();
+            let mut rows = screen_rows
+                .iter()
+                .filter(|line| {
+                    shown(line)
+                        && !(line.stream.is_error()
+                            && Some(&line.line) == pinned)
+                })
+                .collect::<Vec<_>>();
+            rows.split_off(rows.len().saturating_sub(tail))
+        }
+        None => {
+            let mut rows = _last_rows(state)
+                .filter(|&(index, line)| {
+                    shown(line) && Some(index) != pinned && index < end
+                })
+                .take(tail)
+                .map(|(_, line)| line)
+                .collect::<Vec<_>>();
+            rows.reverse();
+            rows
+        }
+    };
+    let mut written = 0;
+    if state.pin_last_error && max_lines > 0 {
+        match &state.pinned {
+            Some((_, line, at)) => {
+                let text = format!(
+                    "{}s ago: {}",
+                    at.elapsed().as_secs(),
+                    state.shown(line)
+                );
+                let text = _clip(&text, width, state.truncate);
+                let text = Stream::Stderr.display_style(style(text));
+                _draw_line(out, text, width, &border);
+            }
+            None => _draw_line(out, "", width, &border),
+        }
+        written += 1;
+    }
+    if marked && written < max_lines {
+        let text =
+            format!("… skipped {} lines", HumanCount(state.skipped as u64));
+        let text = _clip(&text, width, state.truncate);
+        _draw_line(out, style(text).dim().italic().reverse(), width, &border);
+        written += 1;
+    }
+    for line in rows {
+        let (_, text) = _cursor_moves(&line.line);
+        let shown = state.shown(text);
+        let text = _clip(&shown, width, state.truncate);
+        let text = line.stream.display_style(state.contrast.apply(style(text)));
+        _draw_line(out, text, width, &border);
+        written += 1;
+    }
+    if let Some(cycle) = cycle.filter(|_| written < max_lines) {
+        let text = format!(
+            "↻ last {} lines repeated {}× ({})",
+            cycle.period,
+            cycle.times(),
+            _short_duration(cycle.since.elapsed())
+        );
+        let text = _clip(&text, width, state.truncate);
+        _draw_line(out, style(text).italic().reverse(), width, &border);
+        written += 1;
+    }
+    if let Some(ready) = state.ready.as_ref().filter(|_| state.waiting_ready) {
+        let left = ready.timeout.saturating_sub(state.started.elapsed());
+        let text = format!(
+            "… waiting for a line matching {} ({} left)",
+            ready.regex,
+            _short_duration(left)
+        );
+        if written < max_lines {
+            let text = _clip(&text, width, state.truncate);
+            _draw_line(out, style(text).dim().italic(), width, &border);
+            written += 1;
+        }
+    }
+    if let Some(at) = state.starting.filter(|_| written < max_lines) {
+        let left = at.saturating_duration_since(Instant::now());
+        // Counting down, a second is left until the very end.
+        let text = format!(
+            "… starting in {}",
+            _short_duration(left + Duration::from_millis(999))
+        );
+        let text = _clip(&text, width, state.truncate);
+        _draw_line(out, style(text).dim().italic(), width, &border);
+        written += 1;
+    }
+    if let Some(silence) = silence.filter(|_| written < max_lines) {
+        let text = format!(
+            "… still running (no output for {})",
+            _short_duration(silence)
+        );
+        let text = _clip(&text, width, state.truncate);
+        _draw_line(out, style(text).dim().italic(), width, &border);
+        written += 1;
+    }
+    for _ in written..max_lines {
+        _draw_line(out, "", width, &border);
+    }
+}
+
+/// Rows of the window left for the lines of output: the pinned error takes
+/// the first one, the marker of skipped lines the next, and the repeated
+/// block, the countdown, the wait for readiness and the heartbeat the last
+/// ones.
+fn _tail_rows(state: &State, marked: bool) -> usize {
+    (state.max_lines as usize)
+        .saturating_sub(state.pin_last_error as usize)
+        .saturating_sub(marked as usize)
+        .saturating_sub(_cycle(state).is_some() as usize)
+        .saturating_sub(state.starting.is_some() as usize)
+        .saturating_sub(state.waiting_ready as usize)
+        .saturating_sub(_silence(state).is_some() as usize)
+}
+
+/// The block of lines the command keeps printing, once it printed it
+/// [`CYCLE_MIN_TIMES`] times. The window then shows it once.
+fn _cycle(state: &State) -> Option<Cycle> {
+    if state.screen.is_some() {
+        return None;
+    }
+    state
+        .cycles
+        .current()
+        .filter(|cycle| cycle.times() >= CYCLE_MIN_TIMES)
+}
+
+/// The lines on the rows of the window, with their index, last first.
+fn _last_rows(state: &State) -> impl Iterator<Item = (usize, &Line)> {
+    let first = state.buf.first();
+    let lines = state.rows.is_none().then(|| {
+        let lines = state.buf.iter().enumerate().rev();
+        lines.map(move |(index, line)| (first + index, line))
+    });
+    let redrawn = state.rows.iter().flat_map(|rows| {
+        let rows = rows.iter().rev();
+        rows.filter_map(|&index| Some((index, state.buf.get(index)?)))
+    });
+    lines.into_iter().flatten().chain(redrawn)
+}
+
+/// Where the lines the window can show end, before the repetitions of the
+/// block of lines the command keeps printing.
+fn _shown_end(state: &State) -> usize {
+    let total = state.buf.total();
+    _cycle(state).map_or(total, |cycle| total - cycle.repeated)
+}
+
+/// Lines consumed since the window was last redrawn that it could show,
+/// counting those dropped by `max_buffer_lines` in the meantime.
+fn _fresh_lines(state: &State) -> usize {
+    let total = _shown_end(state);
+    let since = state.drawn.max(state.buf.first()).min(total);
+    let kept = if state.stderr_only {
+        let hidden = state.buf.total() - total;
+        let recent = state.buf.iter().rev().skip(hidden).take(total - since);
+        recent.filter(|line| line.stream.is_error()).count()
+    } else {
+        total - since
+    };
+    // The pinned error has its own row.
+    let pinned = state
+        .pinned
+        .as_ref()
+        .is_some_and(|(index, ..)| (since..total).contains(index));
+    (since + kept).saturating_sub(state.drawn) - pinned as usize
+}
+
+/// Redraws the window, unless it was less than [`FRAME_INTERVAL`] ago: then
+/// the output is held back for the next redraw or tick.
+fn redraw(state: &mut State) {
+    if state.drawn_at.elapsed() < FRAME_INTERVAL {
+        state.pending = true;
+        return;
+    }
+    let total = state.buf.total();
+    match state.display_policy {
+        // The screen only has the latest rows.
+        _ if state.screen.is_some() => state.drawn = total,
+        DisplayPolicy::Lag => {
+            let start = state.drawn.max(state.buf.first());
+            state.drawn = min(total, start + _tail_rows(state, false).max(1));
+        }
+        DisplayPolicy::Sample => state.drawn = total,
+        DisplayPolicy::SampleMarked => {
+            let fresh = _fresh_lines(state);
+            state.skipped = if fresh <= _tail_rows(state, false) {
+                0
+            } else {
+                state.skipped + fresh - _tail_rows(state, true)
+            };
+            state.drawn = total;
+        }
+    }
+    state.pending = state.drawn < total;
+    state.drawn_at = Instant::now();
+    let msg = _build_msg(state);
+    state.pb.set_message(msg);
+}
+
+/// Flags the run once it goes past `max_runtime`: warns above the window
+/// and turns its frame yellow. The command is left running.
+fn check_runtime(state: &mut State, out: &Output) -> Result<()> {
+    let Some(max) = state.max_runtime else {
+        return Ok(());
+    };
+    if state.overran || state.started.elapsed() <= max {
+        return Ok(());
+    }
+    state.overran = true;
+    let text = format!(
+        "Warning: still running after {} (--max-runtime)",
+        _short_duration(max)
+    );
+    _status_line(out, state.plain, &text, Color::Yellow)?;
+    rebuild_layout(state);
+    Ok(())
+}
+
+/// Says how long the command has run once it's past the next multiple of
+/// `announce_every`, above the box and in the dump.
+fn announce(state: &mut State, out: &Output) -> Result<()> {
+    let Some(every) = state.announce_every else {
+        return Ok(());
+    };
+    let due = state.started.elapsed().as_millis() / every.as_millis().max(1);
+    let due = u32::try_from(due).unwrap_or(u32::MAX);
+    if due <= state.announced {
+        return Ok(());
+    }
+    // A late tick announces the last milestone passed only.
+    state.announced = due;
+    let lines = state.buf.total();
+    let text = format!(
+        "⏱ {} elapsed — {} line{} — still running: {}",
+        _short_duration(every.saturating_mul(due)),
+        HumanCount(lines as u64),
+        if lines == 1 { "" } else { "s" },
+        state.command
+    );
+    let line = if state.plain {
+        text.clone()
+    } else {
+        style(&text).dim().to_string()
+    };
+    out.write_line(&line)?;
+    state.markers.push((state.buf.total(), text));
+    Ok(())
+}
+
+/// How long the command has been silent, once it's past the heartbeat.
+fn _silence(state: &State) -> Option<Duration> {
+    let silence = state.last_output.elapsed();
+    state
+        .heartbeat
+        .filter(|&every| silence >= every)
+        .map(|_| silence)
+}
+
+/// `d` rounded down to its largest units, like `45s`, `2m 5s` or `1h 3m`.
+fn _short_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, 0) => format!("{m}m"),
+        (0, m, s) => format!("{m}m {s}s"),
+        (h, 0, _) => format!("{h}h"),
+        (h, m, _) => format!("{h}h {m}m"),
+    }
+}
+
+/// The rows of the window, one per line, already framed and padded to the
+/// window height.
+fn _build_lines(state: &State) -> Vec<String> {
+    let mut msg = String::new();
+    _write_rows(state, &mut msg);
+    msg.lines().map(String::from).collect()
+}
+
+/// The rows of the window joined for the progress bar, written in a buffer
+/// kept across calls.
+fn _build_msg(state: &mut State) -> String {
+    let mut msg = std::mem::take(&mut state.msg_buf);
+    msg.clear();
+    _write_rows(state, &mut msg);
+    state.msg_buf = msg;
+    // indicatif wants an owned message.
+    state.msg_buf.clone()
+}
+
+/// Whether `line` looks like a row of a box drawn by another athens.
+fn _looks_like_frame(line: &str) -> bool {
+    let line = console::strip_ansi_codes(line);
+    let line = line.trim_end();
+    line.starts_with('╭')
+        || line.starts_with('╰')
+        || (line.starts_with('│') && line.ends_with('│'))
+}
+
+/// Whether `text` shows that the command draws a full-screen interface: it
+/// switches to the alternate screen, or moves the cursor to many positions
+/// to draw the screen.
+fn _draws_tui(text: &str) -> bool {
+    if !text.contains('\x1b') {
+        return false;
+    }
+    let alternate = ["\x1b[?1049h", "\x1b[?1047h", "\x1b[?47h"];
+    if alternate.iter().any(|on| text.contains(on)) {
+        return true;
+    }
+    let moves = text.split("\x1b[").skip(1).filter(|csi| {
+        let end = csi.find(|c: char| !c.is_ascii_digit() && c != ';');
+        end.is_some_and(|end| matches!(csi.as_bytes()[end], b'H' | b'f'))
+    });
+    moves.count() >= TUI_CURSOR_MOVES
+}
+
+/// How many rows up the cursor moves at the start of `line`, and the rest
+/// of it. Only what commands print between whole lines to redraw them is
+/// recognized: `\r`, cursor up (`\x1b[A`, `\x1b[F`) and erase line
+/// (`\x1b[K`), which is a no-op since lines are replaced whole.
+fn _cursor_moves(line: &str) -> (usize, &str) {
+    let mut up = 0;
+    let mut rest = line;
+    loop {
+        if let Some(after) = rest.strip_prefix('\r') {
+            rest = after;
+            continue;
+        }
+        let Some(csi) = rest.strip_prefix("\x1b[") else {
+            break;
+        };
+        let digits = csi.find(|c: char| !c.is_ascii_digit()).unwrap_or(0);
+        let (count, after) = csi.split_at(digits);
+        match after.chars().next() {
+            Some('A' | 'F') => up += count.parse().unwrap_or(1).max(1),
+            Some('K') if matches!(count, "" | "0" | "2") => (),
+            _ => break,
+        }
+        rest = &after[1..];
+    }
+    (up, rest)
+}
+
+/// Stores `line` as printed. A line that starts by moving the cursor up is
+/// shown on the row it lands on instead, as in a terminal.
+fn record(state: &mut State, line: Line) -> Result<()> {
+    let (up, text) = _cursor_moves(&line.line);
+    let shown = if text.len() == line.line.len() {
+        line.clone()
+    } else {
+        Line {
+            line: text.into(),
+            ..line.clone()
+        }
+    };
+    let index = state.buf.total();
+    state.first_output.get_or_insert_with(Instant::now);
+    state.last_output = Instant::now();
+    if let Some(throughput) = &state.throughput {
+        let mut throughput = throughput.lock().expect("not poisoned");
+        throughput.add(&shown.line, Instant::now());
+    }
+    if let Some(capture) = &mut state.capture {
+        capture.scan(&shown.line);
+    }
+    if let Some(ready) = &state.ready {
+        if state.ready_at.is_none() && ready.regex.is_match(&shown.line) {
+            state.ready_at = Some(state.started.elapsed());
+        }
+    }
+    if let Some(tests) = &state.tests {
+        tests.lock().expect("not poisoned").scan(&shown.line);
+    }
+    if let Some(counters) = &state.counters {
+        counters.lock().expect("not poisoned").scan(&shown.line);
+    }
+    if _looks_like_frame(&shown.line) {
+        state.frame_lines += 1;
+    }
+    if state.plain && state.observer.is_none() {
+        let term = match shown.stream {
+            Stream::Stdout => state.out.clone(),
+            Stream::Stderr => Term::stderr(),
+        };
+        term.write_line(state.strip(&shown.line))
+            .map_err(AthensError::OutputFailed)?;
+    }
+    if let Some(observer) = &state.observer {
+        observer.line(&shown);
+    }
+    if shown.stream.is_error() {
+        state.stderr_lines += 1;
+        if state.pin_last_error {
+            state.pinned = Some((index, shown.line.clone(), Instant::now()));
+        }
+    }
+    state.buf.push(line);
+    if up > 0 && state.rows.is_none() {
+        state.rows = Some((state.buf.first()..index).collect());
+    }
+    let Some(rows) = &mut state.rows else {
+        state.cycles.push(&shown, Instant::now());
+        return Ok(());
+    };
+    // Rows whose lines `max_buffer_lines` dropped go too.
+    let first = state.buf.first();
+    let dropped = rows.iter().take_while(|&&row| row < first).count();
+    rows.drain(..dropped);
+    let end = rows.len();
+    let row = state
+        .cursor
+        .map_or(end, |row| row.saturating_sub(dropped))
+        .saturating_sub(up);
+    if row == end {
+        state.cursor = None;
+        state.cycles.push(&shown, Instant::now());
+        rows.push_back(index);
+    } else {
+        rows[row] = index;
+        state.cursor = Some(row + 1).filter(|&next| next < end);
+    }
+    Ok(())
+}
+
+fn _advance(state: &State) {
+    if state.file_size.is_some() {
+        return;
+    }
+    if let Some(n) = state.expected_lines {
+        // Commands printing more than announced just stay at 100%.
+        state.pb.set_position(min(state.buf.total() as u64, n));
+    }
+}
+
+fn progress(state: &mut State, line: &Line) -> Result<()> {
+    _check_resize(state);
+    record(state, line.clone())?;
+    _advance(state);
+    redraw(state);
+    Ok(())
+}
+
+fn progress_bytes(
+    state: &mut State,
+    stream: Stream,
+    bytes: &[u8],
+) -> Result<()> {
+    _check_resize(state);
+    if let Some(screen) = &mut state.screen {
+        for line in screen.feed(stream, bytes) {
+            record(state, line)?;
+        }
+    }
+    _advance(state);
+    redraw(state);
+    Ok(())
+}
+
+/// Moves the progress bar to how far the command has read its file.
+fn file_progress(state: &mut State, position: u64, size: u64) {
+    if state.file_size != Some(size) {
+        state.file_size = Some(size);
+        state.pb.set_length(size);
+        rebuild_layout(state);
+    }
+    state.pb.set_position(min(position, size));
+}
+
+/// Waits until `state.starting`, keeping the window up to date. Returns
+/// false if the run was cancelled meanwhile.
+fn wait_to_start(state: &mut State, receiver: &Receiver<Event>) -> bool {
+    while let Some(at) = state.starting {
+        let left = at.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            state.starting = None;
+            continue;
+        }
+        match receiver.recv_timeout(left) {
+            Ok(Event::Cancel) => return false,
+            Ok(Event::Control(c)) => control(state, c),
+            Ok(Event::Tick) => redraw(state),
+            _ => (),
+        }
+    }
+    true
+}
+
+/// Whether the command should be stopped for not showing it is ready within
+/// the timeout of [`RunOptions::ready`]. This is only the case once.
+fn check_ready(state: &mut State) -> bool {
+    let Some(ready) = &state.ready else {
+        return false;
+    };
+    let late = state.started.elapsed() > ready.timeout;
+    if late && state.ready_at.is_none() && !state.not_ready {
+        state.not_ready = true;
+        return true;
+    }
+    false
+}
+
+/// Once the command exited successfully without showing it is ready, waits
+/// for the followed file to show it, until the timeout of
+/// [`RunOptions::ready`].
+fn wait_ready(
+    state: &mut State,
+    receiver: &Receiver<Event>,
+    out: &Output,
+) -> Result<()> {
+    let Some(ready) = state.ready.clone() else {
+        return Ok(());
+    };
+    if state.ready_at.is_some() || state.not_ready || state.cancelled {
+        return Ok(());
+    }
+    if ready.follow.is_none() {
+        state.not_ready = true;
+        return Ok(());
+    }
+    if state.plain {
+        out.write_line(&format!(
+            "Waiting for a line matching {}...",
+            ready.regex
+        ))?;
+    }
+    let deadline = state.started + ready.timeout;
+    state.waiting_ready = true;
+    while state.ready_at.is_none() && !state.cancelled {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            state.not_ready = true;
+            break;
+        }
+        match receiver.recv_timeout(left) {
+            Ok(Event::Line(line)) => {
+                progress(state, &line)?;
+            }
+            Ok(Event::Control(c)) => control(state, c),
+            Ok(Event::Cancel) => state.cancelled = true,
+            Ok(Event::Tick) => redraw(state),
+            _ => (),
+        }
+    }
+    state.waiting_ready = false;
+    Ok(())
+}
+
+/// Whether the command should be stopped for printing the same block of
+/// lines more than [`RunOptions::fail_on_loop`] times. This is only the case
+/// once.
+fn check_loop(state: &mut State) -> bool {
+    let Some(max) = state.fail_on_loop else {
+        return false;
+    };
+    match state.cycles.current() {
+        Some(cycle) if cycle.times() > max && state.looped.is_none() => {
+            state.looped = Some(cycle);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Where the lines around the box are printed, with the box (or all the
+/// boxes of [`RunOptions::multi`]) cleared meanwhile.
+struct Output {
+    term: Term,
+    pb: ProgressBar,
+}
+
+impl Output {
+    fn write_line(&self, line: &str) -> Result<()> {
+        self.pb
+            .suspend(|| self.term.write_line(line))
+            .map_err(AthensError::OutputFailed)
+    }
+}
+
+/// Writes a line of the final summary, in `color` unless the output is plain.
+fn _status_line(
+    out: &Output,
+    plain: bool,
+    text: &str,
+    color: Color,
+) -> Result<()> {
+    let line = if plain {
+        text.to_string()
+    } else {
+        style(text).fg(color).to_string()
+    };
+    out.write_line(&line)
+}
+
+/// Prints the test tally and the failed tests, which are also saved next to
+/// the output, in `<dump>.failures`.
+fn _test_summary(
+    out: &Output,
+    plain: bool,
+    tests: &TestResults,
+    dump: &Path,
+    color: Color,
+) -> Result<()> {
+    let tally = format!("({} passed, {} failed)", tests.passed, tests.failed);
+    _status_line(out, plain, &tally, color)?;
+    if tests.failures.is_empty() {
+        return Ok(());
+    }
+    for name in tests.failures.iter().take(MAX_LISTED_FAILURES) {
+        _status_line(out, plain, &format!("  ✗ {name}"), Color::Red)?;
+    }
+    let more = tests.failures.len().saturating_sub(MAX_LISTED_FAILURES);
+    if more > 0 {
+        _status_line(out, plain, &format!("  ... and {more} more"), color)?;
+    }
+    let mut path = dump.as_os_str().to_owned();
+    path.push(".failures");
+    let mut names = tests.failures.join("\n");
+    names.push('\n');
+    // The list was printed already: failing to save it isn't worth an error.
+    if fs::write(&path, names).is_ok() {
+        let path = path.to_string_lossy();
+        let saved = format!("(failed tests saved at: {path})");
+        _status_line(out, plain, &saved, color)?;
+    }
+    Ok(())
+}
+
+/// Warns when the exit status contradicts the test results, which usually
+/// means the test harness is broken.
+fn _check_test_status(
+    out: &Output,
+    plain: bool,
+    tests: &TestResults,
+    success: bool,
+) -> Result<()> {
+    let warning = match (tests.failed, success) {
+        (0, false) if tests.passed > 0 => {
+            "no test failed, but the command failed".to_string()
+        }
+        (1, true) => "1 test failed, but the command succeeded".to_string(),
+        (failed, true) if failed > 0 => {
+            format!("{failed} tests failed, but the command succeeded")
+        }
+        _ => return Ok(()),
+    };
+    let warning = format!("(warning: {warning})");
+    _status_line(out, plain, &warning, Color::Yellow)
+}
+
+pub fn printable_command<S>(command: &NonEmpty<S>) -> OsString
+where
+    S: AsRef<OsStr>,
+{
+    command
+        .iter()
+        .map(|x| x.as_ref())
+        .collect::<Vec<_>>()
+        .join(&OsString::from(" "))
+}
+
+/// Runs `command` showing its output in a box, and saves the output.
+///
+/// Errors tell apart why the run didn't succeed. Where the command did run,
+/// the error carries the [`RunResult`] (or the statistics gathered so far).
+pub fn run_with_progress<S>(
+    command: NonEmpty<S>,
+    options: RunOptions,
+    out: &Term,
+) -> Result<RunResult, RunError>
+where
+    S: AsRef<OsStr>,
+{
+    _run(command, options, out, None, Spawner::Threads)
+}
+
+fn _run<S>(
+    command: NonEmpty<S>,
+    options: RunOptions,
+    out: &Term,
+    observer: Option<Observer>,
+    spawner: Spawner,
+) -> Result<RunResult, RunError>
+where
+    S: AsRef<OsStr>,
+{
+    let run = ResolvedRun::resolve(command, &options);
+    run.limits.check().map_err(RunError::Failed)?;
+    let mut c = run.command();
+    let mut state = State::new(&options, out);
+    state.observer = observer;
+    if options.dump_header {
+        state.dump_header = Some(run.header(SystemTime::now()));
+    }
+    state.command = printable_command(&run.argv).to_string_lossy().into();
+    let out = &Output {
+        term: out.clone(),
+        pb: state.pb.clone(),
+    };
+    if options.plain {
+        out.write_line("Running...")?;
+    }
+    let (sender, receiver) = channel();
+    let listener = Listener::start(sender.clone());
+    let watcher = sender.clone();
+    let stopper = sender.clone();
+    if !options.plain
+        || options.delay.is_some()
+        || options.ready.is_some()
+        || options.max_runtime.is_some()
+        || options.announce_every.is_some()
+        || state.observer.is_some()
+    {
+        let ticker = sender.clone();
+        // Stops once the run is over and the receiver is gone.
+        thread::spawn(move || {
+            while ticker.send(Event::Tick).is_ok() {
+                thread::sleep(FRAME_INTERVAL);
+            }
+        });
+    }
+    if let Some(cancel) = &options.cancel {
+        cancel.attach(sender.clone());
+    }
+    if let Some(delay) = options.delay {
+        if options.plain {
+            let text = format!("Starting in {}...", _short_duration(delay));
+            out.write_line(&text)?;
+        }
+        state.starting = Some(Instant::now() + delay);
+        if !wait_to_start(&mut state, &receiver) {
+            if let Some(cancel) = &options.cancel {
+                cancel.detach();
+            }
+            state.pb.finish_and_clear();
+            _status_line(
+                out,
+                options.plain,
+                "Cancelled before starting",
+                Color::Yellow,
+            )?;
+            return Err(RunError::Failed(AthensError::CancelledBeforeStart));
+        }
+    }
+    let (input, stdin_dump) = if let Some(stdin) = &options.stdin {
+        (_feed(stdin, &options)?, None)
+    } else if options.stdin_tee {
+        let (file, path) =
+            state.create_dump().map_err(|source| RunError::DumpFailed {
+                source,
+                dump: None,
+                stats: state.stats(),
+            })?;
+        (Input::Tee(file), Some(path))
+    } else {
+        (Input::Inherit, None)
+    };
+    let following = Arc::new(AtomicBool::new(false));
+    if let Some(path) = options.ready.as_ref().and_then(|r| r.follow.clone()) {
+        let tail = Tail::new(path, following.clone());
+        let follower = stopper.clone();
+        let decoding = options.decoding();
+        thread::spawn(move || {
+            _read_stream(tail, &follower, Stream::Stdout, decoding)
+        });
+    }
+    let oom = OomWatch::start();
+    let hashers = options.hash.map(Hashers::new);
+    let exited = spawn_events(
+        (&mut c, &spawner),
+        options.raw_preview,
+        options.decoding(),
+        input,
+        hashers.clone(),
+        (sender, &receiver),
+        |event| match event {
+            Event::Line(line) => {
+                progress(&mut state, &line)?;
+                if check_loop(&mut state) {
+                    let _ = stopper.send(Event::Stop);
+                }
+                Ok(())
+            }
+            Event::Bytes(stream, bytes) => {
+                progress_bytes(&mut state, stream, &bytes)
+            }
+            Event::Tui => {
+                if state.stop_on_tui && !state.tui {
+                    state.tui = true;
+                    let _ = stopper.send(Event::Stop);
+                }
+                Ok(())
+            }
+            Event::Control(c) => {
+                control(&mut state, c);
+                Ok(())
+            }
+            Event::Cancel => {
+                state.cancelled = true;
+                Ok(())
+            }
+            Event::Done | Event::Stop => Ok(()),
+            Event::Started(pid) => {
+                if let Some(observer) = &state.observer {
+                    observer.started(pid);
+                }
+                if let Some(path) = &options.fd_progress {
+                    fd_progress::watch(pid, path, watcher.clone());
+                }
+                Ok(())
+            }
+            Event::FileProgress(position, size) => {
+                file_progress(&mut state, position, size);
+                Ok(())
+            }
+            Event::Tick => {
+                if let Some(observer) = &state.observer {
+                    observer.send(RunEvent::Tick);
+                }
+                check_runtime(&mut state, out)?;
+                if check_ready(&mut state) {
+                    let _ = stopper.send(Event::Stop);
+                }
+                announce(&mut state, out)?;
+                if state.pending || _silence(&state).is_some() {
+                    redraw(&mut state);
+                }
+                Ok(())
+            }
+        },
+    );
+    if let Ok(exited) = &exited {
+        if options.is_success(&exited.status) {
+            wait_ready(&mut state, &receiver, out)?;
+        }
+    }
+    following.store(true, Ordering::Relaxed);
+    drop(listener);
+    if let Some(cancel) = &options.cancel {
+        cancel.detach();
+    }
+    state.pb.finish_and_clear();
+    let exited = exited.map_err(|err| match err {
+        AthensError::SpawnFailed(e) if e.kind() == ErrorKind::NotFound => {
+            RunError::NotFound(e)
+        }
+        AthensError::SpawnFailed(e) => RunError::SpawnFailed(e),
+        err => RunError::Failed(err),
+    })?;
+    if let Some(line) = state.screen.as_mut().and_then(Screen::finish) {
+        record(&mut state, line)?;
+    }
+    let status = exited.status;
+    if let Some(observer) = &state.observer {
+        observer.send(RunEvent::Exited(status));
+    }
+    let exited_ok = options.is_success(&status);
+    let no_output = options
+        .require_output
+        .is_some_and(|required| required.is_missing(&state.stats()));
+    let looped = state.looped.is_some();
+    let tui = state.tui;
+    let not_ready = state.not_ready && !state.cancelled;
+    let success = exited_ok
+        && !no_output
+        && !looped
+        && !tui
+        && !not_ready
+        && !state.cancelled;
+    let likely_oom = !state.cancelled && oom.likely(&status, exited.peak_rss);
+    let (msg, color) = match status.code() {
+        _ if state.cancelled => ("Cancelled".into(), Color::Yellow),
+        _ if not_ready => {
+            let ready = state.ready.as_ref().expect("set when not ready");
+            let msg = format!(
+                "Not ready: no line matched {} within {}",
+                ready.regex,
+                _short_duration(ready.timeout)
+            );
+            (msg, Color::Red)
+        }
+        _ if tui => {
+            let msg = "Command stopped: it draws a full-screen interface, \
+                       which can't be shown in the box";
+            (msg.into(), Color::Red)
+        }
+        _ if looped => {
+            let cycle = state.looped.expect("set when looped");
+            let msg = format!(
+                "Command stopped: the last {} lines were repeated {}×",
+                cycle.period,
+                cycle.times()
+            );
+            (msg, Color::Red)
+        }
+        _ if exited_ok && no_output => {
+            let on = match options.require_output {
+                Some(RequiredOutput::Stdout) => " on stdout",
+                Some(RequiredOutput::Stderr) => " on stderr",
+                _ => "",
+            };
+            (format!("Command produced no output{on}"), Color::Red)
+        }
+        Some(0) if success => ("Success!".into(), Color::Green),
+        Some(code) if success => {
+            (format!("Success! (exit status: {code})"), Color::Green)
+        }
+        _ if likely_oom => {
+            let peak = exited.peak_rss.map_or_else(String::new, |peak| {
+                format!(", peak RSS {}", HumanBytes(peak))
+            });
+            let msg = format!("Command {}", describe_exit(&status));
+            (format!("{msg} (likely OOM-killed{peak})"), Color::Red)
+        }
+        _ => (format!("Command {}", describe_exit(&status)), Color::Red),
+    };
+    let f = state
+        .dump()
+        .map_err(|(source, dump)| RunError::DumpFailed {
+            source,
+            dump,
+            stats: state.stats(),
+        })?;
+    if let Some(observer) = &state.observer {
+        observer.send(RunEvent::DumpFinalized(f.clone()));
+    }
+    _status_line(
+        out,
+        options.plain,
+        &format!("(check full output at: {})", f.to_string_lossy()),
+        color,
+    )?;
+    // The input is saved next to the output once the output's name is known.
+    let stdin_dump = stdin_dump.map(|path| {
+        let mut name = f.clone().into_os_string();
+        name.push(".stdin");
+        let name = PathBuf::from(name);
+        match fs::rename(&path, &name) {
+            Ok(()) => name,
+            Err(_) => path,
+        }
+    });
+    if let Some(path) = &stdin_dump {
+        _status_line(
+            out,
+            options.plain,
+            &format!("(input saved at: {})", path.to_string_lossy()),
+            color,
+        )?;
+    }
+    let hash = hashers.as_ref().map(Hashers::finish);
+    if let Some(hash) = &hash {
+        let name = hash.algorithm.name();
+        for (stream, hex) in
+            [("stdout", &hash.stdout), ("stderr", &hash.stderr)]
+        {
+            _status_line(
+                out,
+                options.plain,
+                &format!("({stream} {name}: {hex})"),
+                color,
+            )?;
+        }
+    }
+    if let Some(max) = options.max_runtime.filter(|_| state.overran) {
+        let text = format!(
+            "(ran for {}, over the max runtime of {})",
+            _short_duration(state.started.elapsed()),
+            _short_duration(max)
+        );
+        _status_line(out, options.plain, &text, Color::Yellow)?;
+    }
+    if let Some(first) = state.stats().first_output {
+        _status_line(
+            out,
+            options.plain,
+            &format!("(first output after {:.1}s)", first.as_secs_f64()),
+            color,
+        )?;
+    }
+    let counters = state.counters.as_ref().map_or_else(Vec::new, |counters| {
+        counters.lock().expect("not poisoned").values()
+    });
+    if !counters.is_empty() {
+        let values = counters
+            .iter()
+            .map(|(name, value)| format!("{name} {value}"));
+        let text =
+            format!("(counters: {})", values.collect::<Vec<_>>().join(", "));
+        _status_line(out, options.plain, &text, color)?;
+    }
+    if let Some(ready) = state.ready_at {
+        _status_line(
+            out,
+            options.plain,
+            &format!("(ready after {:.1}s)", ready.as_secs_f64()),
+            color,
+        )?;
+    }
+    let tests = state
+        .tests
+        .as_ref()
+        .map(|tests| tests.lock().expect("not poisoned").clone());
+    if let Some(tests) = &tests {
+        _test_summary(out, options.plain, tests, &f, color)?;
+        if !state.cancelled {
+            _check_test_status(out, options.plain, tests, success)?;
+        }
+    }
+    _status_line(out, options.plain, &msg, color)?;
+    if options.show_command_on_failure && !success && !state.cancelled {
+        let command = format!("Command: {}", state.command);
+        _status_line(out, options.plain, &command, Color::Red)?;
+    }
+    if tui {
+        let hint = "hint: run it directly instead of through athens";
+        let hint = if options.plain {
+            hint.into()
+        } else {
+            style(hint).dim().to_string()
+        };
+        out.write_line(&hint)?;
+    }
+    if !options.plain && state.frame_lines >= NESTED_FRAME_LINES {
+        out.write_line(&format!(
+            "{}",
+            style(
+                "hint: the output looks like another athens box; \
+                 run the inner one with --plain"
+            )
+            .dim()
+        ))?;
+    }
+    let stats = RunStats {
+        peak_rss: exited.peak_rss,
+        ..state.stats()
+    };
+    let exit_reason = if state.cancelled {
+        ExitReason::Cancelled
+    } else if not_ready {
+        ExitReason::TimedOut
+    } else if looped {
+        ExitReason::Looped
+    } else if tui {
+        ExitReason::Tui
+    } else {
+        ExitReason::from_status(&status)
+    };
+    let rows = if options.plain {
+        vec![]
+    } else {
+        _build_lines(&state)
+    };
+    let result = RunResult {
+        status,
+        exit_reason,
+        success,
+        cancelled: state.cancelled,
+        dump: f,
+        stdin_dump,
+        capture: state.capture,
+        stats,
+        likely_oom,
+        hash,
+        overran: state.overran,
+        no_output,
+        looped,
+        tui,
+        ready: state.ready_at,
+        counters,
+        rows,
+        tests,
+    };
+    if result.cancelled {
+        Err(RunError::Cancelled(Box::new(result)))
+    } else if not_ready {
+        Err(RunError::NotReady(Box::new(result)))
+    } else if !exited_ok || result.looped || result.tui {
+        Err(RunError::ChildFailed(Box::new(result)))
+    } else if result.no_output {
+        Err(RunError::NoOutput(Box::new(result)))
+    } else {
+        Ok(result)
+    }
+}
+
+/// Like [`run_with_progress`], but a command that ran and failed (or was
+/// cancelled) is not an error: check [`RunResult::success`] instead.
+pub fn spawn_with_progress<S>(
+    command: NonEmpty<S>,
+    options: RunOptions,
+    out: &Term,
+) -> Result<RunResult>
+where
+    S: AsRef<OsStr>,
+{
+    run_with_progress(command, options, out).or_else(RunError::into_result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::ffi::OsStr;
+    use std::fs::{self, File, OpenOptions};
+    use std::io;
+    use std::process::Command;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use anyhow::Result;
+    use console::Term;
+    use encoding_rs::{UTF_8, WINDOWS_1252};
+    use indicatif::{MultiProgress, ProgressDrawTarget};
+    use nonempty::nonempty;
+    use proptest::prelude::*;
+    use regex::Regex;
+
+    use crate::decode::Decoding;
+    use crate::{
+        _build_lines, _clip, _cursor_moves, _draws_tui, _flatten_whitespace,
+        _looks_like_frame, _program_name, _read_stream, _visible,
+        build_command, build_command_iter, control, progress, progress_bytes,
+        record, redraw, run_capture, run_with_progress, spawn,
+        spawn_with_progress, AthensError, CancelToken, Control, DisplayPolicy,
+        Event, ExitReason, HashAlgorithm, Limits, Line, LineEndings,
+        LineNumberFormat, Ready, RequiredOutput, RunError, RunOptions, Screen,
+        State, StdinInput, Stream, TestFormat, Truncate, FRAME_INTERVAL,
+        KILL_GRACE, MAX_LINES, NESTED_ENV,
+    };
+
+    /// A terminal discarding the summary lines.
+    fn quiet() -> Result<Term> {
+        let null = OpenOptions::new().write(true).open("/dev/null")?;
+        Ok(Term::read_write_pair(File::open("/dev/null")?, null))
+    }
+
+    #[test]
+    fn test_unicode_splitting() -> Result<()> {
+        let mut state = State::new(&RunOptions::default(), &quiet()?);
+        state.term_lines = 10;
+        state.term_columns = 3;
+        let line = Line {
+            line: "ëëëëf".into(),
+            stream: Stream::Stdout,
+            crlf: false,
+        };
+        progress(&mut state, &line)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_clip() {
+        let path = "/very/long/path/to/file.rs";
+        assert_eq!(_clip(path, 10, Truncate::End), "/very/long");
+        assert_eq!(_clip(path, 10, Truncate::Middle), "/very…e.rs");
+        assert_eq!(_clip(path, 10, Truncate::Start), "…o/file.rs");
+        assert_eq!(_clip("añoñoño", 4, Truncate::Middle), "añ…o");
+        assert_eq!(_clip(path, 1, Truncate::Middle), "…");
+        assert_eq!(_clip(path, 26, Truncate::Start), path);
+    }
+
+    #[test]
+    fn test_visible() -> Result<()> {
+        let cases = [
+            (
+                "ding\x07 back\x08\x08 tab\tvt\x0bnul\0",
+                "ding␇ back␈␈ tab vt␋nul␀",
+            ),
+            ("\x1b[1;31mred\x1b[0m \x1b[2Kstray \x1bx", "red stray ␛x"),
+            ("del\x7f c1\u{85}\u{9b}31mcsi", "del␡ c1�csi"),
+            ("\x1b]0;title\x07kept \x1b]8;;url\x1b\\link", "kept link"),
+            (
+                "\u{9d}0;title\u{9c}kept \x1bPq#0;data\x1b\\ \x1b_apc\x1b\\!",
+                "kept  !",
+            ),
+            ("cut \x1b]0;never terminated", "cut "),
+            ("cut \x1b]0;then \x1b[1mbold", "cut bold"),
+        ];
+        for (line, shown) in cases {
+            assert_eq!(_visible(line), shown);
+        }
+
+        // Nothing but the styles of the window reaches the terminal.
+        let mut state = State::new(&RunOptions::default(), &quiet()?);
+        for (line, _) in cases {
+            let line = Line {
+                line: line.into(),
+                stream: Stream::Stdout,
+                crlf: false,
+            };
+            record(&mut state, line)?;
+        }
+        let styles = Regex::new("\x1b\\[[0-9;]*m")?;
+        let frame = _build_lines(&state).join("\n");
+        let frame = styles.replace_all(&frame, "");
+        assert!(!frame.contains(|c: char| c.is_control() && c != '\n'));
+        assert!(frame.contains("cut bold"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_draws_tui() -> Result<()> {
+        let screens = [
+            // vim and htop starting.
+            "\x1b[?1049h\x1b[22;0;0t\x1b[?1h\x1b=\x1b[H\x1b[2J",
+            "\x1b[?1049h\x1b[22;0;0t\x1b[1;24r\x1b(B\x1b[m\x1b[4l\x1b[?7h\x1b[H",
+            // less on an old terminal.
+            "\x1b[?47h\x1b[H\x1b[2J",
+        ];
+        // top, in the normal screen.
+        let top = (1..=12)
+            .map(|row| format!("\x1b[{row};1H\x1b[K{row}"))
+            .collect();
+        let lines = [
+            "\x1b[1m\x1b[32m   Compiling\x1b[0m runner v0.1.0",
+            "\x1b[2K\x1b[1A 45% [=====>    ]",
+            "\x1b[H\x1b[2J cleared once",
+            "plain text",
+        ];
+        for screen in screens.into_iter().map(String::from).chain([top]) {
+            assert!(_draws_tui(&screen), "{screen:?}");
+        }
+        for line in lines {
+            assert!(!_draws_tui(line), "{line:?}");
+        }
+
+        let dir = tempfile::tempdir()?;
+        let started = Instant::now();
+        let err = run_with_progress(
+            nonempty![
+                "sh",
+                "-c",
+                "printf '\\033[?1049h\\033[H'; exec sleep 10"
+            ],
+            RunOptions {
+                stop_on_tui: true,
+                dump_dir: Some(dir.path().into()),
+                ..Default::default()
+            },
+            &quiet()?,
+        );
+        match err {
+            Err(RunError::ChildFailed(result)) => {
+                assert!(result.tui);
+                assert_eq!(result.exit_reason, ExitReason::Tui);
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten_whitespace() -> Result<()> {
+        assert_eq!(_flatten_whitespace("a b"), "a b");
+        assert_eq!(
+            _flatten_whitespace("  name \t  size\t\tdate "),
+            " name size date "
+        );
+
+        let options = RunOptions {
+            flatten_whitespace: true,
+            dump_dir: Some(env::temp_dir()),
+            ..Default::default()
+        };
+        let mut state = State::new(&options, &quiet()?);
+        let line = Line {
+            line: "file.rs      1024    today".into(),
+            stream: Stream::Stdout,
+            crlf: false,
+        };
+        record(&mut state, line)?;
+        let frame = console::strip_ansi_codes(&_build_lines(&state).concat())
+            .into_owned();
+        assert!(frame.contains("│file.rs 1024 today "));
+        let dump = state.dump().expect("saving the output");
+        assert_eq!(fs::read_to_string(&dump)?, "file.rs      1024    today\n");
+        fs::remove_file(dump)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_collect_keeps_lines_after_early_eof() -> Result<()> {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "exec 1>&-; sleep 0.2; echo late >&2; echo later >&2"]);
+        let mut lines = Vec::new();
+        spawn(&mut cmd, |line| {
+            lines.push(line.line.to_string());
+            Ok(())
+        })?;
+        assert_eq!(lines, ["late", "later"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_controls_rebuild_window() -> Result<()> {
+        let mut state = State::new(&RunOptions::default(), &quiet()?);
+        state.term_lines = 8;
+        state.term_columns = 20;
+        for (line, stream) in [("a", Stream::Stderr), ("b", Stream::Stdout)] {
+            state.buf.push(Line {
+                line: line.into(),
+                stream,
+                crlf: false,
+            });
+        }
+        control(&mut state, Control::GrowWindow);
+        assert_eq!(state.max_lines, 5);
+        control(&mut state, Control::GrowWindow);
+        assert_eq!(state.max_lines, MAX_LINES);
+        control(&mut state, Control::ToggleStderrOnly);
+        let lines = _build_lines(&state)
+            .iter()
+            .map(|l| console::strip_ansi_codes(l).into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), MAX_LINES as usize);
+        assert_eq!(lines[0], format!("│{:<18}│", "a"));
+        assert!(lines[1..]
+            .iter()
+            .all(|l| l.trim_matches('│').trim().is_empty()));
+
+        state.stderr_only = false;
+        state.pin_last_error = true;
+        for (line, stream) in [
+            ("boom", Stream::Stderr),
+            ("c", Stream::Stdout),
+            ("d", Stream::Stdout),
+            ("e", Stream::Stdout),
+        ] {
+            progress(
+                &mut state,
+                &Line {
+                    line: line.into(),
+                    stream,
+                    crlf: false,
+                },
+            )?;
+        }
+        let lines = _build_lines(&state)
+            .iter()
+            .map(|l| {
+                console::strip_ansi_codes(l)
+                    .trim_matches('│')
+                    .trim()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(lines, ["0s ago: boom", "c", "d", "e"]);
+
+        state.ascii_frame = true;
+        let line =
+            console::strip_ansi_codes(&_build_lines(&state)[1]).into_owned();
+        assert!(line.starts_with("|c ") && line.ends_with('|'));
+        Ok(())
+    }
+
+    /// A reader failing with the given error kind before yielding `data`.
+    struct Flaky {
+        error: Option<io::ErrorKind>,
+        data: &'static [u8],
+    }
+
+    impl io::Read for Flaky {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if let Some(kind) = self.error.take() {
+                return Err(kind.into());
+            }
+            let n = self.data.len().min(buf.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_read_stream_io_errors() -> Result<()> {
+        let read = |kind| -> Result<Vec<String>> {
+            let (sender, receiver) = channel();
+            let reader = Flaky {
+                error: Some(kind),
+                data: b"one\ntwo\n",
+            };
+            _read_stream(
+                reader,
+                &sender,
+                Stream::Stdout,
+                Decoding::Fixed(UTF_8),
+            )?;
+            drop(sender);
+            Ok(receiver
+                .into_iter()
+                .filter_map(|event| match event {
+                    Event::Line(line) => Some(line.line.to_string()),
+                    _ => None,
+                })
+                .collect())
+        };
+        assert_eq!(read(io::ErrorKind::Interrupted)?, ["one", "two"]);
+        assert!(read(io::ErrorKind::WouldBlock)?.is_empty());
+        assert!(read(io::ErrorKind::BrokenPipe).is_err());
+        Ok(())
+    }
+
+    /// Output mixing what makes rendering hard: invalid UTF-8, ANSI codes
+    /// (whole or cut), line endings, zero and double width characters, and
+    /// ones whose width changed between versions of the Unicode tables.
+    fn _hard_output() -> impl Strategy<Value = Vec<u8>> {
+        let piece = prop_oneof![
+            prop::collection::vec(any::<u8>(), 0..8),
+            "[a-z /.]{0,30}".prop_map(String::into_bytes),
+            prop::sample::select(vec![
+                "\n", "\r\n", "\r", "\t", "\x1b[31m", "\x1b[0m", "\x1b[",
+                "\x1b[2K", "\u{200b}", "e\u{301}", "漢字", "😀", "ｗ",
+                "\u{fffd}", "\u{600}", "\u{ad}", "\u{4dc0}", "\u{d7b0}",
+            ])
+            .prop_map(|piece| piece.as_bytes().to_vec()),
+        ];
+        prop::collection::vec(piece, 0..40).prop_map(|pieces| pieces.concat())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_rendering(
+            output in _hard_output(),
+            columns in 0u16..100,
+            truncate in prop::sample::select(vec![
+                Truncate::End,
+                Truncate::Middle,
+                Truncate::Start,
+            ]),
+            pin_last_error in any::<bool>(),
+            raw_preview in any::<bool>(),
+            display_policy in prop::sample::select(vec![
+                DisplayPolicy::Lag,
+                DisplayPolicy::Sample,
+                DisplayPolicy::SampleMarked,
+            ]),
+        ) {
+            let (sender, receiver) = channel();
+            _read_stream(&output[..], &sender, Stream::Stderr, Decoding::Fixed(UTF_8))
+                .expect("reading from memory");
+            drop(sender);
+            let lines = receiver
+                .into_iter()
+                .filter_map(|event| match event {
+                    Event::Line(line) => Some(line),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+
+            let options = RunOptions {
+                truncate,
+                pin_last_error,
+                raw_preview,
+                display_policy,
+                dump_dir: Some(env::temp_dir()),
+                ..Default::default()
+            };
+            let mut state = State::new(&options, &quiet().expect("/dev/null"));
+            if raw_preview {
+                for chunk in output.chunks(7) {
+                    progress_bytes(&mut state, Stream::Stderr, chunk)
+                        .expect("nothing is printed");
+                }
+                if let Some(line) = state.screen.as_mut().and_then(Screen::finish) {
+                    record(&mut state, line).expect("nothing is printed");
+                }
+            } else {
+                for line in &lines {
+                    progress(&mut state, line).expect("nothing is printed");
+                }
+                prop_assert_eq!(state.buf.len(), lines.len());
+            }
+            // Set last, as the window follows the size of the terminal.
+            state.term_columns = columns;
+            let rows = _build_lines(&state);
+            prop_assert_eq!(rows.len(), state.max_lines as usize);
+            for row in &rows {
+                prop_assert_eq!(
+                    console::measure_text_width(row),
+                    columns.max(2) as usize,
+                    "{:?}",
+                    row
+                );
+            }
+
+            let dump = state.dump().expect("saving the output");
+            let saved = fs::read(&dump).expect("reading the output back");
+            fs::remove_file(dump).expect("removing the output");
+            prop_assert_eq!(
+                saved.iter().filter(|&&b| b == b'\n').count(),
+                state.buf.len()
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_stream_encoding() -> Result<()> {
+        let (sender, receiver) = channel();
+        let reader: &[u8] = b"caf\xe9\r\nna\xefve";
+        _read_stream(
+            reader,
+            &sender,
+            Stream::Stdout,
+            Decoding::Fixed(WINDOWS_1252),
+        )?;
+        _read_stream(reader, &sender, Stream::Stdout, Decoding::Fixed(UTF_8))?;
+        _read_stream(reader, &sender, Stream::Stdout, Decoding::Detect)?;
+        drop(sender);
+        let lines = receiver
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Line(line) => Some(line.line.to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "café",
+                "naïve",
+                "caf\u{fffd}",
+                "na\u{fffd}ve",
+                "café",
+                "naïve"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cycles() -> Result<()> {
+        let mut state = State::new(&RunOptions::default(), &quiet()?);
+        state.max_lines = 6;
+        let lines = ["retry", "failed", "sleeping"].repeat(10);
+        for line in ["start"].into_iter().chain(lines) {
+            let line = Line {
+                line: line.into(),
+                stream: Stream::Stdout,
+                crlf: false,
+            };
+            record(&mut state, line)?;
+        }
+        let rows = _build_lines(&state)
+            .iter()
+            .map(|l| {
+                console::strip_ansi_codes(l)
+                    .trim_matches('│')
+                    .trim()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            [
+                "start",
+                "retry",
+                "failed",
+                "sleeping",
+                "↻ last 3 lines repeated 10× (0s)",
+                ""
+            ]
+        );
+
+        let dir = tempfile::tempdir()?;
+        let err = run_with_progress(
+            nonempty!["sh", "-c", "while :; do echo a; echo b; echo c; done"],
+            RunOptions {
+                fail_on_loop: Some(5),
+                dump_dir: Some(dir.path().into()),
+                ..Default::default()
+            },
+            &quiet()?,
+        );
+        assert!(matches!(
+            err,
+            Err(RunError::ChildFailed(r))
+                if r.exit_reason == ExitReason::Looped && r.stats.lines >= 18
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_line_endings() -> Result<()> {
+        let (sender, receiver) = channel();
+        let reader: &[u8] = b"lf\ncr\rlf\ncrlf\r\nlast\r";
+        _read_stream(reader, &sender, Stream::Stdout, Decoding::Fixed(UTF_8))?;
+        drop(sender);
+        let lines = receiver
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Line(line) => Some(line),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let read = lines
+            .iter()
+            .map(|line| (&*line.line, line.crlf))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            read,
+            [
+                ("lf", false),
+                ("cr\rlf", false),
+                ("crlf", true),
+                ("last", true)
+            ]
+        );
+
+        let options = RunOptions {
+            dump_dir: Some(env::temp_dir()),
+            ..Default::default()
+        };
+        let mut state = State::new(&options, &quiet()?);
+        for line in lines {
+            record(&mut state, line)?;
+        }
+        let mut saved = |endings| -> Result<String> {
+            state.line_endings = endings;
+            let dump = state.dump().map_err(|(e, _)| e)?;
+            let saved = fs::read_to_string(&dump)?;
+            fs::remove_file(dump)?;
+            Ok(saved)
+        };
+        assert_eq!(saved(LineEndings::Lf)?, "lf\ncr\rlf\ncrlf\nlast\n");
+        assert_eq!(
+            saved(LineEndings::Crlf)?,
+            "lf\r\ncr\rlf\r\ncrlf\r\nlast\r\n"
+        );
+        assert_eq!(
+            saved(LineEndings::Preserve)?,
+            "lf\ncr\rlf\ncrlf\r\nlast\r\n"
+        );
+        let native = saved(if cfg!(windows) {
+            LineEndings::Crlf
+        } else {
+            LineEndings::Lf
+        })?;
+        assert_eq!(saved(LineEndings::Native)?, native);
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_detection() -> Result<()> {
+        let script = format!("echo ${NESTED_ENV}");
+        let mut cmd = build_command(nonempty::nonempty!["sh", "-c", &script]);
+        let mut out = Vec::new();
+        spawn(&mut cmd, |line| {
+            out.push(line.line.to_string());
+            Ok(())
+        })?;
+        assert_eq!(out, ["1"]);
+        assert!(_looks_like_frame("│ some output   │"));
+        assert!(_looks_like_frame("\x1b[2m╭ Running / ───╮\x1b[0m"));
+        assert!(!_looks_like_frame("│ not closed"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_command_iter() {
+        let cmd = build_command_iter(vec!["ls", "-l", "/"]).unwrap();
+        assert_eq!(cmd.get_program(), "ls");
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(), ["-l", "/"]);
+        assert!(matches!(
+            build_command_iter(Vec::<String>::new()),
+            Err(AthensError::EmptyCommand)
+        ));
+    }
+
+    #[test]
+    fn test_run_error_variants() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let options = RunOptions {
+            dump_dir: Some(dir.path().into()),
+            ..Default::default()
+        };
+        let err = run_with_progress(
+            nonempty!["athens-no-such-command"],
+            options.clone(),
+            &quiet()?,
+        );
+        assert!(matches!(err, Err(RunError::NotFound(_))));
+
+        let not_executable = tempfile::NamedTempFile::new()?;
+        let err = run_with_progress(
+            nonempty![not_executable.path()],
+            options.clone(),
+            &quiet()?,
+        );
+        assert!(matches!(err, Err(RunError::SpawnFailed(_))));
+
+        let err = run_with_progress(
+            nonempty!["echo", "hi"],
+            RunOptions {
+                dump_dir: Some("/athens/no/such/dir".into()),
+                ..options.clone()
+            },
+            &quiet()?,
+        );
+        assert!(matches!(
+            err,
+            Err(RunError::DumpFailed { dump: None, stats, .. }) if stats.lines == 1
+        ));
+
+        let err = run_with_progress(
+            nonempty!["sh", "-c", "exit 3"],
+            options.clone(),
+            &quiet()?,
+        );
+        assert!(matches!(
+            err,
+            Err(RunError::ChildFailed(r)) if r.exit_reason == ExitReason::Exited(3)
+        ));
+
+        let required = |script, require_output| {
+            run_with_progress(
+                nonempty!["sh", "-c", script],
+                RunOptions {
+                    require_output: Some(require_output),
+                    ..options.clone()
+                },
+                &quiet().expect("/dev/null opens"),
+            )
+        };
+        assert!(matches!(
+            required("true", RequiredOutput::Any),
+            Err(RunError::NoOutput(r)) if r.status.success() && r.no_output
+        ));
+        assert!(matches!(
+            required("echo hi >&2", RequiredOutput::Stdout),
+            Err(RunError::NoOutput(_))
+        ));
+        assert!(required("echo hi >&2", RequiredOutput::Stderr).is_ok());
+        // A failed exit takes precedence.
+        assert!(matches!(
+            required("exit 3", RequiredOutput::Any),
+            Err(RunError::ChildFailed(r)) if r.no_output
+        ));
+
+        let err = run_with_progress(
+            nonempty!["sh", "-c", "kill -9 $$"],
+            options.clone(),
+            &quiet()?,
+        );
+        assert!(matches!(
+            &err,
+            Err(RunError::ChildFailed(r)) if r.exit_reason == ExitReason::Signaled(9)
+        ));
+        assert_eq!(
+            serde_json::to_string(&ExitReason::Signaled(9))?,
+            r#"{"signaled":9}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&ExitReason::TimedOut)?,
+            r#""timed_out""#
+        );
+        assert_eq!(
+            err.map_err(|e| e.to_string()).err().as_deref(),
+            Some("command terminated by signal 9")
+        );
+
+        let script = format!(
+            "exec head -c 8192 /dev/zero >{}/out",
+            dir.path().display()
+        );
+        let err = run_with_progress(
+            nonempty!["sh".to_string(), "-c".into(), script],
+            RunOptions {
+                limits: Limits {
+                    file_size: Some(4096),
+                    ..Default::default()
+                },
+                ..options.clone()
+            },
+            &quiet()?,
+        );
+        assert_eq!(
+            err.map_err(|e| e.to_string()).err().as_deref(),
+            Some("command terminated by signal 25 (file size limit exceeded)")
+        );
+
+        let cancel = CancelToken::new();
+        thread::spawn({
+            let cancel = cancel.clone();
+            move || {
+                thread::sleep(Duration::from_millis(200));
+                cancel.cancel();
+            }
+        });
+        let start = Instant::now();
+        let err = run_with_progress(
+            nonempty!["sleep", "5"],
+            RunOptions {
+                cancel: Some(cancel),
+                ..options
+            },
+            &quiet()?,
+        );
+        assert!(matches!(
+            err,
+            Err(RunError::Cancelled(r)) if r.exit_reason == ExitReason::Cancelled
+        ));
+        assert!(start.elapsed() < Duration::from_secs(4));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel_kills_stubborn_command() -> Result<()> {
+        let cancel = CancelToken::new();
+        thread::spawn({
+            let cancel = cancel.clone();
+            move || {
+                thread::sleep(Duration::from_millis(200));
+                cancel.cancel();
+            }
+        });
+        let start = Instant::now();
+        let dir = tempfile::tempdir()?;
+        let script = "trap '' TERM; while true; do echo busy; sleep 0.1; done";
+        let result = spawn_with_progress(
+            nonempty!["sh", "-c", script],
+            RunOptions {
+                cancel: Some(cancel),
+                dump_dir: Some(dir.path().into()),
+                ..Default::default()
+            },
+            &quiet()?,
+        )?;
+        assert!(result.cancelled && result.stats.lines > 0);
+        assert!(start.elapsed() < KILL_GRACE * 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel_stops_children() -> Result<()> {
+        let cancel = CancelToken::new();
+        thread::spawn({
+            let cancel = cancel.clone();
+            move || {
+                thread::sleep(Duration::from_millis(200));
+                cancel.cancel();
+            }
+        });
+        let start = Instant::now();
+        let dir = tempfile::tempdir()?;
+        // The sleep holds the pipes after the shell is gone.
+        let result = spawn_with_progress(
+            nonempty!["sh", "-c", "echo started; sleep 30 & wait"],
+            RunOptions {
+                cancel: Some(cancel),
+                dump_dir: Some(dir.path().into()),
+                ..Default::default()
+            },
+            &quiet()?,
+        )?;
+        assert!(result.cancelled && result.stats.lines == 1);
+        assert!(start.elapsed() < KILL_GRACE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_first_output() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let out = quiet()?;
+        let run = |script| {
+            spawn_with_progress(
+                nonempty!["sh", "-c", script],
+                RunOptions {
+                    dump_dir: Some(dir.path().into()),
+                    ..Default::default()
+                },
+                &out,
+            )
+        };
+        let first = run("sleep 0.3; echo hi; sleep 0.3")?.stats.first_output;
+        assert!(first.is_some_and(|d| d >= Duration::from_millis(300)
+            && d < Duration::from_millis(600)));
+        assert_eq!(run("true")?.stats.first_output, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_plain_output() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let log = tempfile::NamedTempFile::new()?;
+        let out =
+            Term::read_write_pair(File::open("/dev/null")?, log.reopen()?);
+        let result = spawn_with_progress(
+            nonempty!["sh", "-c", "echo one; echo two"],
+            RunOptions {
+                plain: true,
+                dump_dir: Some(dir.path().into()),
+                ..Default::default()
+            },
+            &out,
+        )?;
+        let log = std::fs::read_to_string(log.path())?;
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(lines[..3], ["Running...", "one", "two"]);
+        assert_eq!(lines.last(), Some(&"Success!"));
+        assert!(!log.contains('\x1b'));
+        assert_eq!(result.stats.lines, 2);
+
+        let log = tempfile::NamedTempFile::new()?;
+        let out =
+            Term::read_write_pair(File::open("/dev/null")?, log.reopen()?);
+        let err = run_with_progress(
+            nonempty!["sh", "-c", "exit 2"],
+            RunOptions {
+                plain: true,
+                show_command_on_failure: true,
+                dump_dir: Some(dir.path().into()),
+                ..Default::default()
+            },
+            &out,
+        );
+        assert!(matches!(err, Err(RunError::ChildFailed(_))));
+        let log = std::fs::read_to_string(log.path())?;
+        let lines = log.lines().rev().take(2).collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            ["Command: sh -c exit 2", "Command exited with status: 2"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_trim_trailing_blank() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let result = spawn_with_progress(
+            nonempty!["printf", "a\n\nb\n\n  \n\n"],
+            RunOptions {
+                trim_trailing_blank: true,
+                dump_dir: Some(dir.path().into()),
+                ..Default::default()
+            },
+            &quiet()?,
+        )?;
+        assert_eq!(std::fs::read_to_string(&result.dump)?, "a\n\nb\n");
+        assert_eq!(result.stats.lines, 6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_test_format() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let result = spawn_with_progress(
+            nonempty!["printf", "ok 1 - a\\nnot ok 2 - b\\nok 3 - c\\n"],
+            RunOptions {
+                test_format: Some(TestFormat::Tap),
+                dump_dir: Some(dir.path().into()),
+                ..Default::default()
+            },
+            &quiet()?,
+        )?;
+        let tests = result.tests.expect("test results");
+        assert_eq!((tests.passed, tests.failed), (2, 1));
+        let mut failures = result.dump.into_os_string();
+        failures.push(".failures");
+        assert_eq!(fs::read_to_string(failures)?, "b\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_heartbeat() -> Result<()> {
+        let options = RunOptions {
+            heartbeat: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let mut state = State::new(&options, &quiet()?);
+        state.term_columns = 60;
+        let line = Line {
+            line: "compiling".into(),
+            stream: Stream::Stdout,
+            crlf: false,
+        };
+        progress(&mut state, &line)?;
+        assert!(!_build_lines(&state).concat().contains("still running"));
+
+        state.last_output -= Duration::from_secs(125);
+        let lines = _build_lines(&state);
+        assert_eq!(lines.len(), MAX_LINES as usize);
+        assert!(lines[0].contains("compiling"));
+        assert!(lines[1].contains("still running (no output for 2m 5s)"));
+        // The dump is written from the buffer, which only has the output.
+        assert_eq!(state.buf.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_announce_every() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let result = spawn_with_progress(
+            nonempty!["sh", "-c", "echo a; sleep 0.5; echo b"],
+            RunOptions {
+                announce_every: Some(Duration::from_millis(100)),
+                dump_dir: Some(dir.path().into()),
+                ..Default::default()
+            },
+            &quiet()?,
+        )?;
+        let saved = fs::read_to_string(&result.dump)?;
+        let (markers, lines): (Vec<_>, Vec<_>) =
+            saved.lines().partition(|line| line.starts_with("⏱ "));
+        assert_eq!(lines, ["a", "b"]);
+        // At least one came while the command slept, after `a`.
+        let after_a = " — 1 line — still running: sh -c echo a; sleep 0.5; \
+                       echo b";
+        assert!(markers.iter().any(|marker| marker.ends_with(after_a)));
+        let b = saved.find("\nb\n").expect("b is saved");
+        assert!(saved[..b].ends_with(after_a));
+        Ok(())
+    }
+
+    #[test]
+    fn test_display_policy() -> Result<()> {
+        let window = |policy, lines| -> Result<_> {
+            let options = RunOptions {
+                display_policy: policy,
+                ..Default::default()
+            };
+            let mut state = State::new(&options, &quiet()?);
+            state.term_columns = 40;
+            for i in 0..lines {
+                let line = Line {
+                    line: format!("line {i}").into(),
+                    stream: Stream::Stdout,
+                    crlf: false,
+                };
+                progress(&mut state, &line)?;
+            }
+            Ok(state)
+        };
+        let frame = |state: &mut State| {
+            state.drawn_at -= FRAME_INTERVAL;
+            redraw(state);
+            _build_lines(state)
+                .iter()
+                .map(|l| l.trim_matches('│').trim().to_string())
+                .map(|l| console::strip_ansi_codes(&l).into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        let mut state = window(DisplayPolicy::SampleMarked, 100)?;
+        assert!(state.pending);
+        assert_eq!(
+            frame(&mut state),
+            ["… skipped 97 lines", "line 97", "line 98", "line 99"]
+        );
+        assert_eq!(state.buf.len(), 100);
+        let mut state = window(DisplayPolicy::Sample, 100)?;
+        assert_eq!(frame(&mut state)[0], "line 96");
+
+        let mut state = window(DisplayPolicy::Lag, 10)?;
+        assert_eq!(
+            frame(&mut state)[..],
+            ["line 0", "line 1", "line 2", "line 3"]
+        );
+        assert_eq!(frame(&mut state)[0], "line 4");
+        assert!(state.pending);
+        assert_eq!(frame(&mut state)[3], "line 9");
+        assert!(!state.pending);
+        Ok(())
+    }
+
+    #[test]
+    fn test_strip_prefix() -> Result<()> {
+        // Only at the start of lines, even without `^`.
+        let timestamp = regex::Regex::new(r"\[[\d:]+\] ")?;
+        for strip_prefix_dump in [false, true] {
+            let options = RunOptions {
+                strip_prefix: Some(timestamp.clone()),
+                strip_prefix_dump,
+                dump_dir: Some(env::temp_dir()),
+                ..Default::default()
+            };
+            let mut state = State::new(&options, &quiet()?);
+            state.term_columns = 30;
+            for line in ["[12:00:01] compiling", "done [12:00:02] "] {
+                let line = Line {
+                    line: line.into(),
+                    stream: Stream::Stdout,
+                    crlf: false,
+                };
+                progress(&mut state, &line)?;
+            }
+            let lines = _build_lines(&state);
+            assert!(lines[0].contains("│compiling "));
+            assert!(lines[1].contains("│done [12:00:02] "));
+            let dump = state.dump().map_err(|(e, _)| e)?;
+            let saved = fs::read_to_string(&dump)?;
+            fs::remove_file(dump)?;
+            let first = saved.lines().next().unwrap_or_default();
+            let expected = if strip_prefix_dump {
+                "compiling"
+            } else {
+                "[12:00:01] compiling"
+            };
+            assert_eq!(first, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_cursor_moves() -> Result<()> {
+        assert_eq!(_cursor_moves("\x1b[3A\x1b[0Kx"), (3, "x"));
+        assert_eq!(_cursor_moves("\x1b[1mbold"), (0, "\x1b[1mbold"));
+        assert_eq!(_cursor_moves("\x1b[5K"), (0, "\x1b[5K"));
+        let mut state = State::new(&RunOptions::default(), &quiet()?);
+        let printed = [
+            "a",
+            "b",
+            "\x1b[1A\x1b[2Kb2",
+            "c",
+            // Back over `b2` and `c`, rewriting both.
+            "\x1b[2A\r\x1b[Kb3",
+            "\x1b[Kc3",
+            "d",
+            // Further up than there are lines.
+            "\x1b[9Fa2",
+            "\x1b[31mred\x1b[0m",
+        ];
+        for line in printed {
+            let line = Line {
+                line: line.into(),
+                stream: Stream::Stdout,
+                crlf: false,
+            };
+            record(&mut state, line)?;
+        }
+        // Saved as printed.
+        assert_eq!(state.buf.len(), 9);
+        assert_eq!(&*state.buf.iter().nth(2).expect("kept").line, printed[2]);
+        state.term_columns = 12;
+        let rows = _build_lines(&state)
+            .iter()
+            .map(|row| {
+                console::strip_ansi_codes(row)
+                    .trim_matches('│')
+                    .trim()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rows, ["a2", "red", "c3", "d"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_line_numbers() -> Result<()> {
+        let options = RunOptions {
+            line_numbers: Some(LineNumberFormat::default()),
+            max_buffer_lines: Some(2),
+            dump_dir: Some(env::temp_dir()),
+            ..Default::default()
+        };
+        let mut state = State::new(&options, &quiet()?);
+        for (line, stream) in [
+            ("one", Stream::Stdout),
+            ("two", Stream::Stdout),
+            ("three", Stream::Stderr),
+        ] {
+            let line = Line {
+                line: line.into(),
+                stream,
+                crlf: false,
+            };
+            record(&mut state, line)?;
+        }
+        let mut saved = |color| -> Result<String> {
+            state.color_line_numbers = color;
+            let dump = state.dump().map_err(|(e, _)| e)?;
+            let saved = fs::read_to_string(&dump)?;
+            fs::remove_file(dump)?;
+            Ok(saved)
+        };
+        // Numbered as printed, though the first line was dropped.
+        assert_eq!(saved(false)?, "     2: two\n     3: three\n");
+        assert_eq!(
+            saved(true)?,
+            "\x1b[36m     2: \x1b[0mtwo\n\x1b[33m     3: \x1b[0mthree\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_dump() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let options = RunOptions {
+            sync_dump: true,
+            dump_dir: Some(dir.path().into()),
+            ..Default::default()
+        };
+        let result =
+            run_with_progress(nonempty!["echo", "hi"], options, &quiet()?)?;
+        assert_eq!(fs::read_to_string(&result.dump)?, "hi\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dump_dir() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let options = RunOptions {
+            dump_dir: Some(dir.path().into()),
+            ..Default::default()
+        };
+        let first = run_with_progress(
+            nonempty!["echo", "one"],
+            options.clone(),
+            &quiet()?,
+        )?;
+        let second = run_with_progress(
+            nonempty!["/bin/echo", "two"],
+            options,
+            &quiet()?,
+        )?;
+        assert_ne!(first.dump, second.dump);
+        for (dump, text) in [(&first.dump, "one\n"), (&second.dump, "two\n")] {
+            assert_eq!(dump.parent(), Some(dir.path()));
+            let name = dump.file_name().expect("a file").to_string_lossy();
+            assert!(name.starts_with("echo-") && name.ends_with(".log"));
+            assert_eq!(fs::read_to_string(dump)?, text);
+        }
+        assert_eq!(_program_name("'my tool' --flag"), "_my");
+        assert_eq!(_program_name(".hidden"), "hidden");
+        Ok(())
+    }
+
+    #[test]
+    fn test_delay() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let started = Instant::now();
+        let options = RunOptions {
+            delay: Some(Duration::from_millis(300)),
+            dump_dir: Some(dir.path().into()),
+            ..Default::default()
+        };
+        run_with_progress(nonempty!["true"], options.clone(), &quiet()?)?;
+        assert!(started.elapsed() >= Duration::from_millis(300));
+
+        let marker = dir.path().join("started");
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let err = run_with_progress(
+            nonempty![OsStr::new("touch"), marker.as_os_str()],
+            RunOptions {
+                cancel: Some(cancel),
+                ..options
+            },
+            &quiet()?,
+        );
+        assert!(matches!(
+            err,
+            Err(RunError::Failed(AthensError::CancelledBeforeStart))
+        ));
+        assert!(!marker.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ready() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let log = dir.path().join("service.log");
+        let ready = |script: &str, follow: bool| {
+            let ready = Ready {
+                regex: Regex::new("ready to accept").expect("a valid regex"),
+                timeout: Duration::from_millis(1000),
+                follow: follow.then(|| log.clone()),
+            };
+            run_with_progress(
+                nonempty!["sh".to_string(), "-c".into(), script.into()],
+                RunOptions {
+                    ready: Some(ready),
+                    dump_dir: Some(dir.path().into()),
+                    ..Default::default()
+                },
+                &quiet().expect("/dev/null opens"),
+            )
+        };
+        let result = ready("echo ready to accept connections", false)?;
+        assert!(result.ready.is_some());
+
+        // The service says it's up in its log, after the command exited.
+        let script = format!(
+            "(sleep 0.3; echo ready to accept connections >> {}) \
+             >/dev/null 2>&1 &",
+            log.display()
+        );
+        let result = ready(&script, true)?;
+        assert!(result
+            .ready
+            .is_some_and(|at| at >= Duration::from_millis(300)));
+
+        assert!(matches!(
+            ready("echo starting", false),
+            Err(RunError::NotReady(r)) if r.status.success() && r.ready.is_none()
+        ));
+        let started = Instant::now();
+        assert!(matches!(
+            ready("exec sleep 10", false),
+            Err(RunError::NotReady(r)) if r.exit_reason == ExitReason::TimedOut
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_runtime() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let options = RunOptions {
+            max_runtime: Some(Duration::from_millis(100)),
+            dump_dir: Some(dir.path().into()),
+            ..Default::default()
+        };
+        let result = spawn_with_progress(
+            nonempty!["sleep", "0.5"],
+            options.clone(),
+            &quiet()?,
+        )?;
+        // Only flagged: the command still ran to the end.
+        assert!(result.success);
+        assert!(result.overran);
+        let result =
+            spawn_with_progress(nonempty!["true"], options, &quiet()?)?;
+        assert!(!result.overran);
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let options = RunOptions {
+            multi: Some(MultiProgress::with_draw_target(
+                ProgressDrawTarget::hidden(),
+            )),
+            // Also prints a warning while the other box is drawn.
+            max_runtime: Some(Duration::from_millis(100)),
+            dump_dir: Some(dir.path().into()),
+            ..Default::default()
+        };
+        let runs = (0..2)
+            .map(|_| {
+                let options = options.clone();
+                let out = quiet()?;
+                Ok(thread::spawn(move || {
+                    spawn_with_progress(
+                        nonempty!["sh", "-c", "echo a; sleep 0.3; echo b"],
+                        options,
+                        &out,
+                    )
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for run in runs {
+            let result = run.join().expect("run panicked")?;
+            assert!(result.success && result.overran);
+            assert_eq!(result.stats.lines, 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let result = spawn_with_progress(
+            nonempty!["sh", "-c", "printf a; echo b >&2"],
+            RunOptions {
+                hash: Some(HashAlgorithm::Sha256),
+                dump_dir: Some(dir.path().into()),
+                ..Default::default()
+            },
+            &quiet()?,
+        )?;
+        let hash = result.hash.expect("hashes");
+        // Hashed as printed, without the newline the dump adds to `a`.
+        assert_eq!(
+            hash.stdout,
+            "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb"
+        );
+        assert_eq!(
+            hash.stderr,
+            "0263829989b6fd954f72baaf2fc64bc2e2f01d692d4de72986ea808f6e99813f"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_stdin_data() -> Result<()> {
+        // More than a pipe holds, so writing must not wait for the output
+        // to be read.
+        let data = "line\n".repeat(20_000);
+        let dir = tempfile::tempdir()?;
+        let result = spawn_with_progress(
+            nonempty!["cat"],
+            RunOptions {
+                stdin: Some(StdinInput::Data(data.into())),
+                dump_dir: Some(dir.path().into()),
+                ..Default::default()
+            },
+            &quiet()?,
+        )?;
+        assert_eq!(result.stats.lines, 20_000);
+
+        let result = spawn_with_progress(
+            nonempty!["cat"],
+            RunOptions {
+                stdin: Some(StdinInput::Data("a\nb\n".into())),
+                stdin_repeat: Some(3),
+                stdin_delimiter: "--\n".into(),
+                dump_dir: Some(dir.path().into()),
+                ..Default::default()
+            },
+            &quiet()?,
+        )?;
+        assert_eq!(result.stats.lines, 8);
+        if cfg!(unix) {
+            assert!(result.stats.peak_rss.is_some_and(|peak| peak > 0));
+        }
+
+        let err = spawn_with_progress(
+            nonempty!["cat"],
+            RunOptions {
+                stdin: Some(StdinInput::File("/no/such/input".into())),
+                ..Default::default()
+            },
+            &quiet()?,
+        );
+        assert!(matches!(err, Err(AthensError::InputFailed(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_capture() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let options = RunOptions {
+            dump_dir: Some(dir.path().into()),
+            stdin: Some(StdinInput::Data("in\n".into())),
+            ..Default::default()
+        };
+        let (status, lines) = run_capture(
+            nonempty!["sh", "-c", "cat; sleep 0.1; echo oops >&2; exit 3"],
+            &options,
+        )?;
+        assert_eq!(status.code(), Some(3));
+        let lines: Vec<_> = lines
+            .iter()
+            .map(|l| (&*l.line, l.stream.is_error()))
+            .collect();
+        assert_eq!(lines, [("in", false), ("oops", true)]);
+        assert_eq!(fs::read_dir(dir.path())?.count(), 0);
+
+        let err = run_capture(nonempty!["./does-not-exist"], &options);
+        assert!(matches!(err, Err(AthensError::SpawnFailed(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_result_rows() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let options = RunOptions {
+            dump_dir: Some(dir.path().into()),
+            ..Default::default()
+        };
+        let result = run_with_progress(
+            nonempty!["sh", "-c", "echo one; echo two"],
+            options.clone(),
+            &quiet()?,
+        )?;
+        let rows: Vec<_> = result
+            .rows
+            .iter()
+            .map(|row| console::strip_ansi_codes(row).into_owned())
+            .collect();
+        assert!(rows.iter().any(|row| row.contains("one")));
+        assert!(rows.iter().any(|row| row.contains("two")));
+        assert!(rows.iter().all(|row| row.starts_with('│')));
+
+        let plain = RunOptions {
+            plain: true,
+            output: Some(quiet()?),
+            ..options
+        };
+        let result =
+            run_with_progress(nonempty!["echo", "one"], plain, &quiet()?)?;
+        assert!(result.rows.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+use std::process::Command;
+
+use indicatif::HumanBytes;
+use serde::Serialize;
+
+use crate::AthensError;
+
+/// Resource limits set on the command before it starts.
+///
+/// They are applied in the child, between `fork` and `exec`: memory and file
+/// size as rlimits (Unix), CPUs as the affinity mask (Linux). Asking for a
+/// limit the platform can't apply is an error, not a no-op.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Limits {
+    /// Address space of the command, in bytes (`RLIMIT_AS`).
+    pub memory: Option<u64>,
+    /// How many CPUs the command may run on: the first ones of those athens
+    /// may use.
+    pub cpus: Option<usize>,
+    /// Largest file the command may write, in bytes (`RLIMIT_FSIZE`).
+    pub file_size: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The limits, like `memory 8.00 GiB, 2 CPUs`.
+    pub fn describe(&self) -> String {
+        let mut parts = vec![];
+        if let Some(bytes) = self.memory {
+            parts.push(format!("memory {}", HumanBytes(bytes)));
+        }
+        if let Some(n) = self.cpus {
+            parts.push(format!("{n} CPU{}", if n == 1 { "" } else { "s" }));
+        }
+        if let Some(bytes) = self.file_size {
+            parts.push(format!("file size {}", HumanBytes(bytes)));
+        }
+        parts.join(", ")
+    }
+
+    /// Fails if a limit can't be applied on this platform.
+    pub fn check(&self) -> Result<(), AthensError> {
+        let unsupported = |limit| Err(AthensError::LimitUnsupported(limit));
+        if !cfg!(unix) && self.memory.is_some() {
+            return unsupported("memory");
+        }
+        if !cfg!(unix) && self.file_size.is_some() {
+            return unsupported("file size");
+        }
+        if !cfg!(target_os = "linux") && self.cpus.is_some() {
+            return unsupported("CPU");
+        }
+        Ok(())
+    }
+
+    /// Sets the limits up on `cmd`. They are only applied when it spawns,
+    /// which fails if they can't be.
+    #[cfg(unix)]
+    pub(crate) fn apply(&self, cmd: &mut Command) {
+        use std::io;
+        use std::os::unix::process::CommandExt;
+
+        if self.is_empty() {
+            return;
+        }
+        let Self {
+            memory, file_size, ..
+        } = *self;
+        #[cfg(target_os = "linux")]
+        let cpus = self.cpus.map(_first_cpus);
+        let set = |resource, bytes: u64| {
+            let limit = libc::rlimit {
+                rlim_cur: bytes as libc::rlim_t,
+                rlim_max: bytes as libc::rlim_t,
+            };
+            // SAFETY: `limit` is valid for the call.
+            match unsafe { libc::setrlimit(resource, &limit) } {
+                0 => Ok(()),
+                _ => Err(io::Error::last_os_error()),
+            }
+        };
+        // SAFETY: the closure only makes system calls, which are safe to
+        // make between `fork` and `exec`, and allocates nothing.
+        unsafe {
+            cmd.pre_exec(move || {
+                if let Some(bytes) = memory {
+                    set(libc::RLIMIT_AS, bytes)?;
+                }
+                if let Some(bytes) = file_size {
+                    set(libc::RLIMIT_FSIZE, bytes)?;
+                }
+                #[cfg(target_os = "linux")]
+                if let Some(cpus) = &cpus {
+                    let size = std::mem::size_of::<libc::cpu_set_t>();
+                    if libc::sched_setaffinity(0, size, cpus) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn apply(&self, _cmd: &mut Command) {}
+}
+
+/// The first `n` CPUs this process may run on, or all of them if there are
+/// fewer.
+#[cfg(target_os = "linux")]
+fn _first_cpus(n: usize) -> libc::cpu_set_t {
+    let size = std::mem::size_of::<libc::cpu_set_t>();
+    // SAFETY: `cpu_set_t` is plain data, and the pointer is valid for the
+    // calls.
+    unsafe {
+        let mut allowed = std::mem::zeroed::<libc::cpu_set_t>();
+        let mut first = std::mem::zeroed::<libc::cpu_set_t>();
+        if libc::sched_getaffinity(0, size, &mut allowed) != 0 {
+            // Then the child fails to spawn with the error of `setaffinity`.
+            return first;
+        }
+        let cpus = (0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &allowed))
+            .take(n);
+        for cpu in cpus {
+            libc::CPU_SET(cpu, &mut first);
+        }
+        first
+    }
+}
+
+/// Parses a size in bytes, like `512M` or `8G` (powers of 1024).
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let invalid = || {
+        format!(
+            "invalid size: {size} (expected a whole number, optionally \
+             followed by K, M, G or T)"
+        )
+    };
+    let digits = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(digits);
+    let number = number.parse::<u64>().map_err(|_| invalid())?;
+    let shift = match unit.trim_end_matches("iB").trim_end_matches('B') {
+        "" => 0,
+        "K" | "k" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(invalid()),
+    };
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size too large: {size}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::limits::parse_size;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("8G"), Ok(8 << 30));
+        assert_eq!(parse_size("1KiB"), Ok(1024));
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("1.5G").is_err());
+        assert!(parse_size("99999999999T").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_limits_apply() -> anyhow::Result<()> {
+        use std::process::Command;
+
+        use crate::limits::Limits;
+
+        let limits = Limits {
+            cpus: Some(1),
+            file_size: Some(4096),
+            ..Default::default()
+        };
+        assert_eq!(limits.describe(), "1 CPU, file size 4.00 KiB");
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "ulimit -f; nproc"]);
+        limits.apply(&mut cmd);
+        let output = cmd.output()?;
+        // `ulimit -f` counts blocks of 512 bytes.
+        assert_eq!(String::from_utf8(output.stdout)?, "8\n1\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+/// How line numbers are written in front of the lines of the dump, from a
+/// printf-style format with a single `%d`, like the default `%6d: `.
+///
+/// The `%d` takes a width and the `-` (align left) and `0` (pad with zeros)
+/// flags, and `%%` is a literal `%`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineNumberFormat {
+    before: String,
+    after: String,
+    width: usize,
+    align: Align,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Align {
+    Right,
+    Left,
+    /// Right, padded with zeros.
+    Zeros,
+}
+
+impl Default for LineNumberFormat {
+    fn default() -> Self {
+        Self::parse("%6d: ").expect("a valid format")
+    }
+}
+
+impl LineNumberFormat {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        let invalid =
+            |why: &str| format!("invalid line number format: {format} ({why})");
+        let mut parts = [String::new(), String::new()];
+        let mut number = None;
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                parts[number.is_some() as usize].push(c);
+                continue;
+            }
+            if chars.next_if_eq(&'%').is_some() {
+                parts[number.is_some() as usize].push('%');
+                continue;
+            }
+            if number.is_some() {
+                return Err(invalid("expected a single %d"));
+            }
+            let align = match chars.next_if(|&c| c == '-' || c == '0') {
+                Some('-') => Align::Left,
+                Some(_) => Align::Zeros,
+                None => Align::Right,
+            };
+            let mut width = String::new();
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                width.push(digit);
+            }
+            if chars.next() != Some('d') {
+                return Err(invalid("only %d is supported"));
+            }
+            let width = match width.as_str() {
+                "" => 0,
+                width => {
+                    width.parse().map_err(|_| invalid("width too large"))?
+                }
+            };
+            number = Some((width, align));
+        }
+        let Some((width, align)) = number else {
+            return Err(invalid("expected a %d"));
+        };
+        let [before, after] = parts;
+        Ok(Self {
+            before,
+            after,
+            width,
+            align,
+        })
+    }
+
+    /// The prefix of line `n`.
+    pub fn format(&self, n: usize) -> String {
+        let (before, after, width) = (&self.before, &self.after, self.width);
+        match self.align {
+            Align::Right => format!("{before}{n:>width$}{after}"),
+            Align::Left => format!("{before}{n:<width$}{after}"),
+            Align::Zeros => format!("{before}{n:0width$}{after}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::line_numbers::LineNumberFormat;
+
+    #[test]
+    fn test_line_number_format() {
+        let format =
+            |spec: &str, n| LineNumberFormat::parse(spec).map(|f| f.format(n));
+        assert_eq!(LineNumberFormat::default().format(42), "    42: ");
+        assert_eq!(format("%d|", 7).as_deref(), Ok("7|"));
+        assert_eq!(format("[%-4d] ", 7).as_deref(), Ok("[7   ] "));
+        assert_eq!(format("%04d %% ", 7).as_deref(), Ok("0007 % "));
+        assert!(format("%s", 7).is_err());
+        assert!(format("no number", 7).is_err());
+        assert!(format("%d %d", 7).is_err());
+    }
+}
@@ -1,279 +1,1476 @@
-use std::cmp::min;
-use std::ffi::{OsStr, OsString};
-use std::fmt::Display;
-use std::io::{BufRead, BufReader, BufWriter, Read, Write};
-use std::path::PathBuf;
-use std::process::{exit, Child, Command, ExitStatus, Stdio};
-use std::sync::mpsc::{channel, Sender};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::exit;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use anyhow::{anyhow, Result};
-use clap::Parser;
-use console::{style, Color, Term};
-use indicatif::{ProgressBar, ProgressStyle};
+use anyhow::Result;
+use clap::{ArgGroup, CommandFactory, FromArgMatches, Parser, ValueEnum};
+use console::{style, Term};
+use encoding_rs::Encoding;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use nonempty::NonEmpty;
+use regex::Regex;
+use runner::batch::Batch;
+use runner::exit::{
+    report, EXIT_CANCELLED, EXIT_CAPTURE_CONFLICT, EXIT_CAPTURE_NO_MATCH,
+    EXIT_DUMP_FAILED, EXIT_USAGE,
+};
+use runner::matrix::{self, Var};
+use runner::pipeline;
+use runner::terminal::{self, Capabilities};
+use runner::{
+    exit_signal, parse_size, printable_command, run_with_progress, AthensError,
+    CancelToken, Capture, CaptureError, CaptureMode, Contrast, Counter, Dirs,
+    DisplayPolicy, EnvFilter, HashAlgorithm, Limits, LineEndings,
+    LineNumberFormat, Ready, RequiredOutput, ResolvedRun, RunError, RunOptions,
+    RunResult, StdinInput, TestFormat, ThroughputUnit, Truncate, NESTED_ENV,
+};
 
-const MAX_LINES: u16 = 4;
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum StatusStream {
+    Stdout,
+    Stderr,
+}
 
-struct State {
-    buf: Vec<Line>,
-    pb: ProgressBar,
-    max_lines: u16,
-    _term_lines: u16,
-    term_columns: u16,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ContrastLevel {
+    Low,
+    Normal,
+    High,
 }
 
-#[derive(Clone)]
-enum Stream {
-    Stdout,
-    Stderr,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum DisplayMode {
+    Lag,
+    Sample,
+    SampleMarked,
 }
 
-#[derive(Clone)]
-struct Line {
-    line: String,
-    stream: Stream,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum HashName {
+    Sha256,
+    Sha512,
 }
 
-impl State {
-    fn new() -> Self {
-        let term = Term::stdout();
-        let (term_lines, term_columns) = term.size();
-        let width = (term_columns as usize).saturating_sub(2);
-        let width_top = width.saturating_sub(11);
-        let top = format!(
-            "╭ Running {{spinner:.dim.bold}} {:─<width_top$}╮",
-            "",
-            width_top = width_top
-        );
-        let bottom = format!("╰{:─<width$}╯", "", width = width);
-        let pb = ProgressBar::new_spinner();
-        pb.enable_steady_tick(Duration::from_millis(200));
-        pb.set_style(
-            ProgressStyle::with_template(&format!("{top}\n{{msg}}\n{bottom}"))
-                .expect("error in the ProgressStyle template")
-                .tick_chars("/|\\- "),
-        );
-        Self {
-            buf: Default::default(),
-            pb,
-            max_lines: MAX_LINES,
-            _term_lines: term_lines,
-            term_columns,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum TestOutput {
+    Tap,
+    Cargo,
+    Pytest,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum DumpLineEndings {
+    Native,
+    Lf,
+    Crlf,
+    Preserve,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum FrameChars {
+    Auto,
+    Unicode,
+    Ascii,
+}
+
+/// An encoding given to `--encoding`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputEncoding {
+    /// Detected from the output of each stream.
+    Auto,
+    Named(&'static Encoding),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum TruncateAt {
+    End,
+    Middle,
+    Start,
+}
+
+#[derive(Parser, Debug)]
+#[clap(
+    version = "0.1.0",
+    author = "Walter Moreira <walter@waltermoreira.net>",
+    about = "Run commands using pretty output",
+    after_help = "EXIT STATUS:\n    \
+        The status of the command, or 128 + N if it was killed by signal N.\n    \
+        Otherwise: 1 if --require-output got nothing, 2 if the options\n    \
+        are invalid, 3 if --capture found nothing, 4 if it found\n    \
+        conflicting values, 5 if --ready-regex didn't match in time, 74 if\n    \
+        the output couldn't be saved, 126 if the command couldn't be\n    \
+        started, 127 if it wasn't found, 130 if the run was interrupted.",
+    arg_required_else_help = true
+)]
+#[clap(propagate_version = true)]
+#[clap(group(ArgGroup::new("wrappers").multiple(true)))]
+#[clap(group(ArgGroup::new("stdin-input")))]
+#[clap(group(ArgGroup::new("required-output")))]
+struct Cli {
+    #[clap(
+        value_parser,
+        required_unless_present_any = [
+            "diagnostics",
+            "batch-watch",
+            "probe-terminal",
+            "reset-terminal-cache",
+        ],
+        help = "command to run"
+    )]
+    command: Vec<String>,
+    #[clap(short, long, value_parser, help = "Optional name of command")]
+    name: Option<OsString>,
+    #[clap(
+        long,
+        value_name = "REGEX",
+        help = "Print the first capture group of the first matching line \
+                to stdout (everything else goes to stderr)"
+    )]
+    capture: Option<String>,
+    #[clap(
+        long,
+        requires = "capture",
+        conflicts_with = "capture-all",
+        help = "Fail if the capture regex matched different values"
+    )]
+    capture_unique: bool,
+    #[clap(
+        long,
+        requires = "capture",
+        help = "Print every value matched by the capture regex"
+    )]
+    capture_all: bool,
+    #[clap(
+        long,
+        help = "Read raw bytes and emulate \\r and backspace in the preview, \
+                for commands that animate in place"
+    )]
+    no_buffer_preview: bool,
+    #[clap(
+        long,
+        value_name = "LIST",
+        value_delimiter = ',',
+        help = "Comma-separated exit codes treated as success (e.g. 0,1)"
+    )]
+    success_codes: Vec<i32>,
+    #[clap(
+        long,
+        help = "Print the output as it arrives, without the box, spinner or \
+                colors"
+    )]
+    plain: bool,
+    #[clap(
+        long,
+        value_name = "N",
+        help = "Number of lines the command is expected to print, to show \
+                a progress bar with percentage and ETA"
+    )]
+    expected_lines: Option<u64>,
+    #[clap(
+        long,
+        help = "Forward stdin to the command through a pipe, saving a copy \
+                next to the output (as <output>.stdin)"
+    )]
+    stdin_tee: bool,
+    #[clap(
+        long,
+        value_name = "STRING",
+        conflicts_with_all = &["stdin-file", "stdin-tee"],
+        group = "stdin-input",
+        help = "Write STRING to the command's stdin, then close it"
+    )]
+    stdin_data: Option<String>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with = "stdin-tee",
+        group = "stdin-input",
+        help = "Write the contents of PATH to the command's stdin, then \
+                close it"
+    )]
+    stdin_file: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "stdin-input",
+        help = "Write the input of --stdin-data or --stdin-file N times"
+    )]
+    stdin_repeat: Option<u64>,
+    #[clap(
+        long,
+        value_name = "TEXT",
+        requires = "stdin-repeat",
+        help = "Write TEXT between the copies of --stdin-repeat"
+    )]
+    stdin_delimiter: Option<String>,
+    #[clap(
+        long,
+        help = "Keep the last stderr line in the first row of the window"
+    )]
+    pin_last_error: bool,
+    #[clap(long, help = "Leave trailing blank lines out of the saved output")]
+    trim_trailing_blank: bool,
+    #[clap(
+        long,
+        alias = "buffer-lines",
+        value_name = "N",
+        help = "Keep only the last N lines in memory (and in the saved output)"
+    )]
+    max_buffer_lines: Option<usize>,
+    #[clap(
+        long,
+        alias = "output-encoding",
+        value_name = "NAME",
+        value_parser = parse_encoding,
+        help = "Encoding of the command's output (e.g. latin1, shift_jis), \
+                UTF-8 by default. With auto, detected for each stream from \
+                its lines (UTF-8, Shift_JIS or Latin-1)"
+    )]
+    encoding: Option<OutputEncoding>,
+    #[clap(
+        long = "var",
+        value_name = "NAME=V1,V2",
+        value_parser = Var::parse,
+        conflicts_with = "capture",
+        help = "Run the command once per value, replacing {NAME} in its \
+                arguments and --name (repeat for every combination)"
+    )]
+    vars: Vec<Var>,
+    #[clap(
+        long,
+        alias = "continue-on-error",
+        requires = "vars",
+        help = "With --var, keep running the remaining combinations after a \
+                failure"
+    )]
+    keep_going: bool,
+    #[clap(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "vars",
+        conflicts_with = "keep-going",
+        help = "With --var, stop running the remaining combinations after N \
+                failures"
+    )]
+    stop_after_n_failures: Option<u64>,
+    #[clap(
+        long,
+        help = "Exit with 0 even if the command fails (its status is still \
+                shown), e.g. in Makefiles where the failure is expected"
+    )]
+    ignore_exit_code: bool,
+    #[clap(
+        long,
+        group = "required-output",
+        help = "Fail if the command printed nothing, even if it exited \
+                successfully"
+    )]
+    require_output: bool,
+    #[clap(
+        long,
+        group = "required-output",
+        help = "Fail if the command printed nothing on stdout"
+    )]
+    require_stdout: bool,
+    #[clap(
+        long,
+        group = "required-output",
+        help = "Fail if the command printed nothing on stderr"
+    )]
+    require_stderr: bool,
+    #[clap(
+        long,
+        value_enum,
+        value_name = "STREAM",
+        default_value = "stderr",
+        help = "Where to print athens' own lines (command, status, paths); \
+                always stderr with --capture"
+    )]
+    status_stream: StatusStream,
+    #[clap(
+        long,
+        value_name = "ID",
+        help = "Register this run in the batch ID, shown by --batch-watch, \
+                and print its output plainly (as with --plain)"
+    )]
+    batch_id: Option<String>,
+    #[clap(
+        long,
+        value_name = "ID",
+        conflicts_with = "batch-id",
+        help = "Show a live table of the runs registered in the batch ID"
+    )]
+    batch_watch: Option<String>,
+    #[clap(
+        long,
+        value_name = "PATTERN",
+        help = "Pass only environment variables matching PATTERN (a glob, \
+                e.g. 'CARGO_*'; repeat for more)"
+    )]
+    env_allow: Vec<String>,
+    #[clap(
+        long,
+        value_name = "PATTERN",
+        help = "Don't pass environment variables matching PATTERN (a glob, \
+                e.g. '*_TOKEN'), even if allowed"
+    )]
+    env_deny: Vec<String>,
+    #[clap(
+        long,
+        help = "Show how many lines per second the command prints, over \
+                the last few seconds"
+    )]
+    throughput: bool,
+    #[clap(
+        long,
+        requires = "throughput",
+        help = "With --throughput, count bytes instead of lines"
+    )]
+    bytes: bool,
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with = "expected-lines",
+        help = "Show how much of FILE the command has read as a progress \
+                bar (Linux only)"
+    )]
+    fd_progress: Option<PathBuf>,
+    #[clap(
+        long,
+        value_enum,
+        value_name = "WHERE",
+        default_value = "end",
+        help = "Where to cut lines too long for the window; middle keeps \
+                both ends, as in /very/long/…/file.rs"
+    )]
+    truncate: TruncateAt,
+    #[clap(
+        long,
+        help = "Collapse runs of whitespace to a single space in the window, \
+                to fit more of tabular output (the saved output is unchanged)"
+    )]
+    flatten_whitespace: bool,
+    #[clap(
+        long,
+        value_enum,
+        value_name = "LEVEL",
+        default_value = "low",
+        help = "How the output stands out in the window: dimmed (low), in \
+                plain color (normal) or bold (high)"
+    )]
+    contrast: ContrastLevel,
+    #[clap(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        help = "Read the output as test results (pytest needs -v), showing \
+                passed and failed tests as they come and listing failures \
+                at the end"
+    )]
+    test_format: Option<TestOutput>,
+    #[clap(
+        long = "count",
+        value_name = "NAME:REGEX[:sum]",
+        value_parser = Counter::parse,
+        help = "Count the lines matching REGEX, or with :sum add up the \
+                numbers its first group matches, showing the value in the \
+                header and the summary (repeat for more counters)"
+    )]
+    counters: Vec<Counter>,
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        help = "After DURATION without output (e.g. 30s, 2m), say the \
+                command is still running in the window"
+    )]
+    heartbeat: Option<Duration>,
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        help = "Every DURATION (e.g. 15m), print how long the command has \
+                run above the box, and save it in the output"
+    )]
+    announce_every: Option<Duration>,
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        help = "Warn once the command has run for longer than DURATION \
+                (e.g. 10m), turning the box yellow, but let it finish"
+    )]
+    max_runtime: Option<Duration>,
+    #[clap(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Stop the command and fail once it printed the same block \
+                of lines more than N times in a row, as when stuck retrying"
+    )]
+    fail_on_loop: Option<u64>,
+    #[clap(
+        long,
+        help = "Let commands that draw a full-screen interface (like htop or \
+                vim) go on, instead of stopping them as the box can't show \
+                them"
+    )]
+    allow_tui: bool,
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        help = "Wait DURATION (e.g. 30s) before starting the command, \
+                counting down in the box. Interrupting meanwhile doesn't \
+                start it"
+    )]
+    delay: Option<Duration>,
+    #[clap(
+        long,
+        value_name = "REGEX",
+        help = "Succeed only once a line matches REGEX, for commands \
+                starting a service: after they exit, wait for it in \
+                --ready-follow"
+    )]
+    ready_regex: Option<String>,
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        default_value = "60s",
+        requires = "ready-regex",
+        help = "Fail if no line matched --ready-regex DURATION after the \
+                start, stopping the command if it still runs"
+    )]
+    ready_timeout: Duration,
+    #[clap(
+        long,
+        value_name = "FILE",
+        requires = "ready-regex",
+        help = "Also look for --ready-regex in the lines written to FILE, \
+                like the log of the service, including after the command \
+                exited"
+    )]
+    ready_follow: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "OCTAL",
+        value_parser = parse_umask,
+        help = "File mode creation mask of the command, e.g. 0022 (Unix only)"
+    )]
+    umask: Option<u32>,
+    #[clap(
+        long,
+        value_name = "REGEX",
+        help = "Remove what REGEX matches at the start of lines (e.g. a \
+                timestamp) from the window, to make room for the rest"
+    )]
+    strip_prefix: Option<String>,
+    #[clap(
+        long,
+        requires = "strip-prefix",
+        help = "Also remove the prefix from the saved output"
+    )]
+    strip_prefix_dump: bool,
+    #[clap(long, help = "Number the lines of the saved output, from 1")]
+    output_line_numbers: bool,
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        value_parser = LineNumberFormat::parse,
+        requires = "output-line-numbers",
+        help = "How --output-line-numbers writes the numbers, with a \
+                printf-style %d [default: '%6d: ']"
+    )]
+    line_number_format: Option<LineNumberFormat>,
+    #[clap(
+        long,
+        requires = "output-line-numbers",
+        help = "Color the line numbers by stream: cyan for stdout, yellow \
+                for stderr (not with --plain or NO_COLOR)"
+    )]
+    color_line_numbers: bool,
+    #[clap(
+        long,
+        value_name = "DIR",
+        help = "Save the output in DIR (created if needed), named after the \
+                program, like make-3fJ1xq.log, instead of a temporary file"
+    )]
+    output_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Start the saved output with the command, working \
+                directory, start time and environment changes"
+    )]
+    dump_header: bool,
+    #[clap(
+        long,
+        value_enum,
+        value_name = "ENDINGS",
+        default_value = "lf",
+        help = "Line endings of the saved output: always \\n (lf), always \
+                \\r\\n (crlf), as usual on this platform (native), or as the \
+                command printed each line (preserve)"
+    )]
+    dump_line_endings: DumpLineEndings,
+    #[clap(
+        long,
+        help = "Wait for the saved output to be on disk before exiting, so \
+                it survives a crash or power loss (slower on some disks)"
+    )]
+    sync: bool,
+    #[clap(
+        long,
+        value_enum,
+        value_name = "CHARS",
+        default_value = "auto",
+        help = "Draw the box with Unicode or ASCII characters. With auto, \
+                as the terminal was found to support (see --probe-terminal)"
+    )]
+    frame: FrameChars,
+    #[clap(
+        long,
+        help = "Print the command again, in red, after the summary of a run \
+                that failed"
+    )]
+    show_command_on_failure: bool,
+    #[clap(
+        long,
+        value_enum,
+        value_name = "POLICY",
+        default_value = "sample-marked",
+        help = "When lines come faster than the window is redrawn, show \
+                them all, falling behind (lag), only the latest (sample), or \
+                the latest and how many were skipped (sample-marked). The \
+                saved output always has every line"
+    )]
+    display_policy: DisplayMode,
+    #[clap(
+        long,
+        value_enum,
+        value_name = "ALGORITHM",
+        help = "Print a hash of the output of each stream in the summary, as \
+                read (with ANSI codes), to compare runs"
+    )]
+    hash: Option<HashName>,
+    #[clap(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        help = "Limit the memory (address space) of the command, e.g. 8G"
+    )]
+    memory_limit: Option<u64>,
+    #[clap(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u16).range(1..),
+        help = "Run the command on at most N CPUs (Linux only)"
+    )]
+    cpu_limit: Option<u16>,
+    #[clap(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        help = "Limit the size of the files the command writes, e.g. 1G"
+    )]
+    fsize_limit: Option<u64>,
+    #[clap(
+        long,
+        conflicts_with = "vars",
+        help = "Run the command as a shell pipeline (e.g. 'make | grep x'), \
+                prefixing stderr lines with the stage that printed them"
+    )]
+    annotate_pipe: bool,
+    #[clap(
+        long,
+        value_name = "CMD",
+        value_parser = parse_wrapper,
+        group = "wrappers",
+        help = "Run the command through CMD, split into words as by the \
+                shell (e.g. 'valgrind --leak-check=full', 'time')"
+    )]
+    wrap_with: Option<Wrapper>,
+    #[clap(
+        long,
+        value_name = "CMD",
+        value_parser = parse_wrapper,
+        multiple_occurrences = true,
+        group = "wrappers",
+        help = "Run the command through CMD, like --wrap-with, to set up its \
+                environment (e.g. 'direnv exec .'). Can be repeated: the \
+                first is outermost, and all come before --wrap-with"
+    )]
+    prefix_command: Vec<Wrapper>,
+    #[clap(
+        long,
+        requires = "wrappers",
+        help = "Show the wrapper and prefix commands in the name of the \
+                command"
+    )]
+    show_wrapper: bool,
+    #[clap(
+        long,
+        help = "Print what would be run (argv, cwd, environment changes, \
+                output directory...) as JSON, and exit without running it"
+    )]
+    dry_run: bool,
+    #[clap(long, help = "Show the directories athens uses, and exit")]
+    diagnostics: bool,
+    #[clap(
+        long,
+        help = "Ask the terminal what it supports, show and save the \
+                answers, and exit. Done once per terminal type otherwise"
+    )]
+    probe_terminal: bool,
+    #[clap(
+        long,
+        help = "Forget what terminals were found to support, and exit"
+    )]
+    reset_terminal_cache: bool,
+}
+
+/// Accepts a mask in octal, like `022` or `0022`.
+fn parse_umask(text: &str) -> Result<u32, String> {
+    match u32::from_str_radix(text, 8) {
+        Ok(mask) if mask <= 0o777 => Ok(mask),
+        _ => Err(format!(
+            "invalid umask: {text} (expected 0 to 0777, in octal)"
+        )),
+    }
+}
+
+/// The words of `--wrap-with`, never empty.
+#[derive(Clone, Debug)]
+struct Wrapper(Vec<String>);
+
+fn parse_wrapper(text: &str) -> Result<Wrapper, String> {
+    match shlex::split(text) {
+        Some(words) if !words.is_empty() => Ok(Wrapper(words)),
+        Some(_) => Err("the wrapper is empty".into()),
+        None => Err(format!("invalid wrapper: {text} (unbalanced quotes?)")),
+    }
+}
+
+/// Accepts `auto` and the labels of the WHATWG Encoding Standard, except
+/// for encodings where `\n` isn't a plain byte, since output is split into
+/// lines.
+fn parse_encoding(name: &str) -> Result<OutputEncoding, String> {
+    if name.eq_ignore_ascii_case("auto") {
+        return Ok(OutputEncoding::Auto);
+    }
+    let encoding = Encoding::for_label(name.as_bytes())
+        .ok_or_else(|| format!("unknown encoding: {name}"))?;
+    if !encoding.is_ascii_compatible() {
+        return Err(format!("{} is not supported", encoding.name()));
+    }
+    Ok(OutputEncoding::Named(encoding))
+}
+
+/// Accepts a number of hours, minutes, seconds or milliseconds, like `2m`
+/// or `500ms`. A plain number is in seconds.
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let digits = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(digits);
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| format!("invalid duration: {text}"))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "" | "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number.saturating_mul(60))),
+        "h" => Ok(Duration::from_secs(number.saturating_mul(3600))),
+        _ => Err(format!(
+            "unknown unit in duration: {text} (use ms, s, m or h)"
+        )),
+    }
+}
+
+impl Cli {
+    /// The words of the command to run. With `--annotate-pipe`, the
+    /// command is a pipeline run by `sh`, as a single command if it can't
+    /// be split into stages.
+    fn words(&self) -> Vec<String> {
+        if !self.annotate_pipe {
+            return self.command.clone();
+        }
+        let script = self.command.join(" ");
+        match pipeline::split(&script) {
+            Some(stages) => pipeline::annotate(&stages),
+            None => vec!["sh".into(), "-c".into(), script],
         }
     }
 
-    fn dump(&self) -> Result<PathBuf> {
-        let temp = tempfile::NamedTempFile::new()?;
-        let (temp, path) = temp.keep()?;
-        let mut buf = BufWriter::new(&temp);
-        for line in &self.buf {
-            writeln!(&mut buf, "{}", line.line)?;
+    /// Whether to print the output without the box: also for runs in a
+    /// batch, whose progress is shown by --batch-watch, and inside another
+    /// athens.
+    fn plain(&self) -> bool {
+        self.plain || self.batch_id.is_some() || nested()
+    }
+
+    /// The words the command is run through: the prefix commands in order,
+    /// then the wrapper.
+    fn wrapper(&self) -> Vec<String> {
+        self.prefix_command
+            .iter()
+            .chain(&self.wrap_with)
+            .flat_map(|Wrapper(words)| words.iter().cloned())
+            .collect()
+    }
+
+    /// The program athens spawns: the outermost wrapper's, if there is one.
+    fn program(&self, command: &str) -> OsString {
+        let wrapper = self.wrapper();
+        OsString::from(wrapper.first().map_or(command, String::as_str))
+    }
+
+    /// `name`, after the wrappers with `--show-wrapper`.
+    fn wrapped_name(&self, name: OsString) -> OsString {
+        let wrapper = self.wrapper();
+        if !self.show_wrapper || wrapper.is_empty() {
+            return name;
         }
-        Ok(path)
+        let mut wrapped = OsString::from(wrapper.join(" "));
+        wrapped.push(" ");
+        wrapped.push(name);
+        wrapped
+    }
+
+    fn capture(&self) -> Option<Capture> {
+        let mode = if self.capture_all {
+            CaptureMode::All
+        } else if self.capture_unique {
+            CaptureMode::Unique
+        } else {
+            CaptureMode::First
+        };
+        compile(&self.capture).map(|regex| Capture::new(regex, mode))
     }
-}
 
-fn build_command<S>(words: NonEmpty<S>) -> Command
-where
-    S: AsRef<OsStr>,
-{
-    let mut cmd = Command::new(words.first());
-    cmd.args(words.tail());
-    cmd
+    fn options(&self) -> RunOptions {
+        RunOptions {
+            capture: self.capture(),
+            encoding: match self.encoding {
+                Some(OutputEncoding::Named(encoding)) => Some(encoding),
+                _ => None,
+            },
+            detect_encoding: self.encoding == Some(OutputEncoding::Auto),
+            raw_preview: self.no_buffer_preview,
+            success_codes: self.success_codes.clone(),
+            plain: self.plain(),
+            expected_lines: self.expected_lines,
+            dump_dir: self.output_dir.clone(),
+            cancel: Some(CancelToken::on_interrupt()),
+            stdin_tee: self.stdin_tee,
+            stdin: match (&self.stdin_data, &self.stdin_file) {
+                (Some(data), _) => Some(StdinInput::Data(data.clone().into())),
+                (_, Some(path)) => Some(StdinInput::File(path.clone())),
+                _ => None,
+            },
+            stdin_repeat: self.stdin_repeat,
+            stdin_delimiter: self
+                .stdin_delimiter
+                .clone()
+                .map(String::into_bytes)
+                .unwrap_or_default(),
+            pin_last_error: self.pin_last_error,
+            trim_trailing_blank: self.trim_trailing_blank,
+            max_buffer_lines: self.max_buffer_lines,
+            output: self.capture.is_none().then(Term::stdout),
+            env: EnvFilter {
+                allow: self.env_allow.clone(),
+                deny: self.env_deny.clone(),
+            },
+            throughput: self.throughput.then_some(if self.bytes {
+                ThroughputUnit::Bytes
+            } else {
+                ThroughputUnit::Lines
+            }),
+            fd_progress: self.fd_progress.clone(),
+            truncate: match self.truncate {
+                TruncateAt::End => Truncate::End,
+                TruncateAt::Middle => Truncate::Middle,
+                TruncateAt::Start => Truncate::Start,
+            },
+            flatten_whitespace: self.flatten_whitespace,
+            counters: self.counters.clone(),
+            contrast: match self.contrast {
+                ContrastLevel::Low => Contrast::Low,
+                ContrastLevel::Normal => Contrast::Normal,
+                ContrastLevel::High => Contrast::High,
+            },
+            test_format: self.test_format.map(|format| match format {
+                TestOutput::Tap => TestFormat::Tap,
+                TestOutput::Cargo => TestFormat::Cargo,
+                TestOutput::Pytest => TestFormat::Pytest,
+            }),
+            heartbeat: self.heartbeat,
+            announce_every: self.announce_every,
+            max_runtime: self.max_runtime,
+            umask: self.umask,
+            strip_prefix: compile(&self.strip_prefix),
+            strip_prefix_dump: self.strip_prefix_dump,
+            multi: None,
+            line_numbers: self
+                .output_line_numbers
+                .then(|| self.line_number_format.clone().unwrap_or_default()),
+            dump_header: self.dump_header,
+            sync_dump: self.sync,
+            dump_line_endings: match self.dump_line_endings {
+                DumpLineEndings::Native => LineEndings::Native,
+                DumpLineEndings::Lf => LineEndings::Lf,
+                DumpLineEndings::Crlf => LineEndings::Crlf,
+                DumpLineEndings::Preserve => LineEndings::Preserve,
+            },
+            ascii_frame: self.frame == FrameChars::Ascii,
+            fail_on_loop: self.fail_on_loop.map(|n| n as usize),
+            stop_on_tui: !self.allow_tui,
+            delay: self.delay,
+            show_command_on_failure: self.show_command_on_failure,
+            ready: compile(&self.ready_regex).map(|regex| Ready {
+                regex,
+                timeout: self.ready_timeout,
+                follow: self.ready_follow.clone(),
+            }),
+            require_output: if self.require_output {
+                Some(RequiredOutput::Any)
+            } else if self.require_stdout {
+                Some(RequiredOutput::Stdout)
+            } else if self.require_stderr {
+                Some(RequiredOutput::Stderr)
+            } else {
+                None
+            },
+            color_line_numbers: self.color_line_numbers
+                && !self.plain()
+                && env::var_os("NO_COLOR").is_none(),
+            wrapper: self.wrapper().into_iter().map(OsString::from).collect(),
+            display_policy: match self.display_policy {
+                DisplayMode::Lag => DisplayPolicy::Lag,
+                DisplayMode::Sample => DisplayPolicy::Sample,
+                DisplayMode::SampleMarked => DisplayPolicy::SampleMarked,
+            },
+            hash: self.hash.map(|hash| match hash {
+                HashName::Sha256 => HashAlgorithm::Sha256,
+                HashName::Sha512 => HashAlgorithm::Sha512,
+            }),
+            limits: Limits {
+                memory: self.memory_limit,
+                cpus: self.cpu_limit.map(usize::from),
+                file_size: self.fsize_limit,
+            },
+        }
+    }
 }
 
-fn _read_stream<R>(reader: R, out: &Sender<Line>, stream: Stream) -> Result<()>
-where
-    R: Read,
-{
-    let buf = BufReader::new(reader).lines();
-    for line in buf {
-        let line = line?;
-        out.send(Line {
-            line,
-            stream: stream.clone(),
-        })?;
+/// Asks the terminal what it supports and saves the answers, for
+/// `--probe-terminal`.
+fn probe_terminal(dirs: &Dirs) -> Result<()> {
+    let caps = terminal::probe(terminal::PROBE_TIMEOUT)
+        .map_err(|err| anyhow::anyhow!("cannot probe the terminal: {err}"))?;
+    let answer = |answer: Option<String>| {
+        answer.unwrap_or_else(|| "no answer".to_string())
+    };
+    let key = terminal::cache_key();
+    println!("Terminal: {key}");
+    println!(
+        "Unicode: {}",
+        answer(
+            caps.unicode
+                .map(|yes| if yes { "yes" } else { "no" }.into())
+        )
+    );
+    println!(
+        "Device attributes: {}",
+        answer(caps.device_attributes.clone())
+    );
+    match dirs.cache.path() {
+        Some(dir) => {
+            let path = terminal::save(dir, &key, &caps)?;
+            println!("Saved to {}", path.display());
+        }
+        None => println!("Not saved: no writable cache directory"),
     }
     Ok(())
 }
 
-fn collect(child: &mut Child, sender: &Sender<Line>) -> Result<ExitStatus> {
-    let err = child
-        .stderr
-        .take()
-        .ok_or_else(|| anyhow!("couldn't get stderr"))?;
-    let out = child
-        .stdout
-        .take()
-        .ok_or_else(|| anyhow!("couldn't get stdout"))?;
-    let t1 = thread::spawn({
-        let sender = sender.clone();
-        move || _read_stream(err, &sender, Stream::Stderr)
-    });
-    let t2 = thread::spawn({
-        let sender = sender.clone();
-        move || _read_stream(out, &sender, Stream::Stdout)
-    });
-    let status = child.wait()?;
-    t1.join()
-        .map_err(|_| anyhow!("thread panicked while reading stderr"))??;
-    t2.join()
-        .map_err(|_| anyhow!("thread panicked while reading stdout"))??;
-    Ok(status)
+/// What the terminal supports: as cached for its type, or probed and cached
+/// on first use. Nothing is known when `out` is not a terminal.
+fn terminal_capabilities(dirs: &Dirs, out: &Term) -> Capabilities {
+    let key = terminal::cache_key();
+    let cache = dirs.cache.path();
+    if let Some(caps) = cache.and_then(|dir| terminal::cached(dir, &key)) {
+        return caps;
+    }
+    if !out.is_term() {
+        return Capabilities::default();
+    }
+    let Ok(caps) = terminal::probe(terminal::PROBE_TIMEOUT) else {
+        return Capabilities::default();
+    };
+    if let Some(dir) = cache {
+        let _ = terminal::save(dir, &key, &caps);
+    }
+    caps
 }
 
-fn spawn<F>(cmd: &mut Command, mut process: F) -> Result<ExitStatus>
-where
-    F: FnMut(&Line) -> Result<()>,
-{
-    let (sender, receiver) = channel();
-    cmd.stderr(Stdio::piped()).stdout(Stdio::piped());
-    let mut child = cmd.spawn()?;
-    let t = thread::spawn(move || collect(&mut child, &sender));
-    for x in receiver {
-        process(&x)?;
-    }
-    t.join().map_err(|_| anyhow!("thread panicked"))?
+/// Whether this is running under another athens.
+fn nested() -> bool {
+    env::var_os(NESTED_ENV).is_some_and(|value| value == "1")
 }
 
-fn _draw_line<S>(line: S, width: usize) -> String
-where
-    S: Display,
-{
-    format!("│{:<width$}│", line, width = width)
+fn print_captured(capture: Capture) {
+    match capture.finish() {
+        Ok(values) => {
+            for value in values {
+                println!("{value}");
+            }
+        }
+        Err(CaptureError::NoMatch) => {
+            eprintln!("{}", style("No output matched the capture regex").red());
+            exit(EXIT_CAPTURE_NO_MATCH);
+        }
+        Err(CaptureError::Conflict(values)) => {
+            eprintln!(
+                "{}",
+                style(format!(
+                    "Capture regex matched conflicting values: {}",
+                    values.join(", ")
+                ))
+                .red()
+            );
+            exit(EXIT_CAPTURE_CONFLICT);
+        }
+    }
 }
 
-// TODO: change to take just State as parameter
-fn _build_msg(state: &State) -> String {
-    let buf = &state.buf;
-    let max_lines = state.max_lines as usize;
-    let width = (state.term_columns as usize).saturating_sub(2);
-    buf[buf.len().saturating_sub(max_lines)..]
-        .iter()
-        .map(|line| {
-            let l = &line
-                .line
-                .chars()
-                .take(min(line.line.len(), width))
-                .collect::<String>();
-            let msg = style(l).dim();
-            _draw_line(
-                match line.stream {
-                    Stream::Stdout => msg.cyan(),
-                    Stream::Stderr => msg.yellow(),
-                },
-                width,
-            )
+/// A short status of a run, as shown in summaries.
+fn outcome(result: &Result<RunResult, RunError>) -> String {
+    match result {
+        Ok(_) => "success".into(),
+        Err(
+            RunError::Cancelled(_)
+            | RunError::Failed(AthensError::CancelledBeforeStart),
+        ) => "cancelled".into(),
+        Err(RunError::NoOutput(_)) => "no output".into(),
+        Err(RunError::NotReady(_)) => "not ready".into(),
+        Err(RunError::ChildFailed(result)) => {
+            match (result.status.code(), exit_signal(&result.status)) {
+                (Some(code), _) => format!("exit {code}"),
+                (None, Some(signal)) => format!("signal {signal}"),
+                (None, None) => "signal".into(),
+            }
+        }
+        Err(_) => "error".into(),
+    }
+}
+
+/// Opens the batch to register runs in. Registering is a convenience, so
+/// failing to do it only warns.
+fn open_batch(dirs: &Dirs, id: &str) -> Option<Batch> {
+    let batch = match dirs.state.path() {
+        Some(dir) => Batch::open(dir, id).map_err(|e| e.to_string()),
+        None => Err("no writable state directory".into()),
+    };
+    batch
+        .map_err(|e| {
+            let msg = format!("Warning: not registering in batch {id}: {e}");
+            eprintln!("{}", style(msg).yellow());
         })
-        .chain([_draw_line(" ", width)].iter().cloned().cycle())
-        .take(max_lines)
-        .collect::<Vec<_>>()
-        .join("\n")
+        .ok()
 }
 
-fn progress(state: &mut State, line: &Line) -> Result<()> {
-    state.buf.push(line.clone());
-    let msg = _build_msg(state);
-    state.pb.set_message(msg);
-    Ok(())
+/// Shows the runs of `batch` as they start and finish, until interrupted.
+fn watch_batch(batch: &Batch) -> Result<()> {
+    let bars = MultiProgress::new();
+    let running = ProgressStyle::with_template("{spinner} {msg}")?;
+    let done = ProgressStyle::with_template("  {msg}")?;
+    let mut shown = HashMap::<String, ProgressBar>::new();
+    loop {
+        let runs = batch.runs()?;
+        let width = runs.iter().map(|run| run.name.len()).max().unwrap_or(0);
+        for run in runs {
+            let bar = shown.entry(run.id).or_insert_with(|| {
+                let bar = bars.add(ProgressBar::new_spinner());
+                bar.set_style(running.clone());
+                bar.enable_steady_tick(Duration::from_millis(100));
+                bar
+            });
+            if bar.is_finished() {
+                continue;
+            }
+            let (status, end) = match &run.finished {
+                Some((status, at)) => (status.as_str(), *at),
+                None => ("running", std::time::SystemTime::now()),
+            };
+            let elapsed = end.duration_since(run.started).unwrap_or_default();
+            let line = format!(
+                "{:<width$}  {status:<10} {:.1}s",
+                run.name,
+                elapsed.as_secs_f64()
+            );
+            match status {
+                "running" => bar.set_message(line),
+                "success" => {
+                    bar.set_style(done.clone());
+                    bar.finish_with_message(style(line).green().to_string());
+                }
+                _ => {
+                    bar.set_style(done.clone());
+                    bar.finish_with_message(style(line).red().to_string());
+                }
+            }
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
 }
 
-fn printable_command<S>(command: &NonEmpty<S>) -> OsString
-where
-    S: AsRef<OsStr>,
-{
-    command
-        .iter()
-        .map(|x| x.as_ref())
-        .collect::<Vec<_>>()
-        .join(&OsString::from(" "))
+/// Compiles a pattern given on the command line. An invalid one is left
+/// out: [`validate`] reports it before anything runs.
+fn compile(pattern: &Option<String>) -> Option<Regex> {
+    pattern
+        .as_deref()
+        .and_then(|pattern| Regex::new(pattern).ok())
 }
 
-fn spawn_with_progress<S>(command: NonEmpty<S>) -> Result<(ExitStatus, PathBuf)>
-where
-    S: AsRef<OsStr>,
-{
-    let mut c = build_command(command);
-    let mut state = State::new();
-    let initial_msg = _build_msg(&state);
-    state.pb.set_message(initial_msg);
-    let status = spawn(&mut c, |s| progress(&mut state, s))?;
-    state.pb.finish_and_clear();
-    let (msg, color) = if status.success() {
-        ("Success!".into(), Color::Green)
+/// Checks the options for what clap can't, before anything runs, so that a
+/// mistake fails right away instead of after a long build. Returns every
+/// problem found.
+fn validate(cli: &Cli, options: &RunOptions) -> Vec<String> {
+    let mut errors = vec![];
+    for (flag, pattern) in [
+        ("--capture", &cli.capture),
+        ("--ready-regex", &cli.ready_regex),
+        ("--strip-prefix", &cli.strip_prefix),
+    ] {
+        if let Some(Err(err)) = pattern.as_deref().map(Regex::new) {
+            errors.push(format!("{flag}: {err}"));
+        }
+    }
+    if let Some(path) = &cli.stdin_file {
+        match fs::metadata(path) {
+            Ok(meta) if meta.is_dir() => errors.push(format!(
+                "--stdin-file: {} is a directory",
+                path.display()
+            )),
+            Ok(_) => (),
+            Err(err) => {
+                errors.push(format!("--stdin-file: {}: {err}", path.display()))
+            }
+        }
+    }
+    let mut seen = HashSet::new();
+    for var in &cli.vars {
+        if !seen.insert(&var.name) {
+            errors.push(format!("--var {}: given more than once", var.name));
+            continue;
+        }
+        let placeholder = format!("{{{}}}", var.name);
+        let used = cli.command.iter().any(|word| word.contains(&placeholder))
+            || cli.name.as_ref().is_some_and(|name| {
+                name.to_string_lossy().contains(&placeholder)
+            });
+        if !used {
+            errors.push(format!(
+                "--var {}: {placeholder} is in neither the command nor --name",
+                var.name
+            ));
+        }
+    }
+    let mut names = HashSet::new();
+    for counter in &cli.counters {
+        if !names.insert(&counter.name) {
+            errors.push(format!(
+                "--count {}: given more than once",
+                counter.name
+            ));
+        }
+    }
+    if let Some(dir) = &cli.output_dir {
+        if dir.exists() && !dir.is_dir() {
+            errors.push(format!(
+                "--output-dir: {} is not a directory",
+                dir.display()
+            ));
+        }
+    }
+    if let Err(err) = options.limits.check() {
+        errors.push(err.to_string());
+    }
+    errors
+}
+
+/// Prints the runs `--dry-run` stands for: one object, or an array with a
+/// run per combination of `--var` values.
+fn dry_run(cli: &Cli, options: &RunOptions) -> Result<()> {
+    let resolve = |words: Vec<String>| {
+        let cmd = NonEmpty::from((words[0].clone(), words[1..].to_vec()));
+        ResolvedRun::resolve(cmd, options)
+    };
+    let json = if cli.vars.is_empty() {
+        serde_json::to_string_pretty(&resolve(cli.words()))?
     } else {
-        (
-            format!(
-                "Command exited with status: {}",
-                status
-                    .code()
-                    .map(|x| x.to_string())
-                    .unwrap_or_else(|| "none".into())
-            ),
-            Color::Red,
-        )
+        let runs = matrix::combinations(&cli.vars)
+            .iter()
+            .map(|combination| {
+                resolve(
+                    cli.command
+                        .iter()
+                        .map(|word| matrix::substitute(word, combination))
+                        .collect(),
+                )
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_string_pretty(&runs)?
     };
-    let f = state.dump()?;
-    println!(
-        "{}",
-        style(format!("(check full output at: {})", f.to_string_lossy()))
-            .fg(color)
-    );
-    println!("{}", style(msg).fg(color));
-    Ok((status, f))
+    println!("{json}");
+    Ok(())
 }
 
-#[derive(Parser, Debug)]
-#[clap(
-    version = "0.1.0",
-    author = "Walter Moreira <walter@waltermoreira.net>",
-    about = "Run commands using pretty output",
-    arg_required_else_help = true
-)]
-#[clap(propagate_version = true)]
-struct Cli {
-    #[clap(value_parser, help = "command to run")]
-    command: Vec<String>,
-    #[clap(short, long, value_parser, help = "Optional name of command")]
-    name: Option<OsString>,
+/// How many variables the command inherits, gets from athens and loses to
+/// the environment filter, when there is one. Values are not printed.
+fn print_env(out: &Term, cli: &Cli, options: &RunOptions) -> io::Result<()> {
+    if options.env.is_empty() {
+        return Ok(());
+    }
+    let words = cli.words();
+    let cmd = NonEmpty::from((&words[0], words[1..].iter().collect()));
+    let run = ResolvedRun::resolve(cmd, options);
+    let inherited = env::vars_os()
+        .filter(|(name, _)| {
+            !run.env_removed.contains(name)
+                && !run.env_set.contains_key(&*name.to_string_lossy())
+        })
+        .count();
+    out.write_line(&format!(
+        "Environment: {inherited} inherited, {} added, {} removed",
+        run.env_set.len(),
+        run.env_removed.len()
+    ))
+}
+
+/// Shows the resource limits under the command, if there are any.
+fn print_limits(out: &Term, limits: &Limits) -> io::Result<()> {
+    if limits.is_empty() {
+        return Ok(());
+    }
+    out.write_line(&format!("Limits: {}", limits.describe()))
+}
+
+/// Runs the command once per combination of `--var` values, then prints a
+/// summary. Returns the exit code of the first failed run, if any.
+fn run_matrix(
+    cli: &Cli,
+    options: RunOptions,
+    out: &Term,
+    batch: Option<&Batch>,
+) -> Result<i32> {
+    let mut code = 0;
+    let mut summary = Vec::<(String, String, Option<Duration>)>::new();
+    // The runs share one set of bars, so each box is drawn where the last
+    // one was cleared.
+    let options = RunOptions {
+        multi: Some(MultiProgress::new()),
+        ..options
+    };
+    print_env(out, cli, &options)?;
+    print_limits(out, &options.limits)?;
+    let max_failures = match cli.stop_after_n_failures {
+        Some(n) => n,
+        None if cli.keep_going => u64::MAX,
+        None => 1,
+    };
+    let mut failures = 0;
+    let combinations = matrix::combinations(&cli.vars);
+    let mut pending = combinations.iter();
+    for combination in pending.by_ref() {
+        let words = cli
+            .command
+            .iter()
+            .map(|word| matrix::substitute(word, combination))
+            .collect::<Vec<_>>();
+        let cmd = NonEmpty::from((words[0].clone(), words[1..].to_vec()));
+        let pretty = match &cli.name {
+            Some(name) => {
+                matrix::substitute(&name.to_string_lossy(), combination)
+            }
+            None => cli
+                .wrapped_name(printable_command(&cmd))
+                .to_string_lossy()
+                .into_owned(),
+        };
+        let label = matrix::describe(combination);
+        out.write_line(&format!("Command: {pretty} [{label}]"))?;
+        let program = cli.program(cmd.first());
+        let started = Instant::now();
+        let run =
+            batch.and_then(|b| b.start(&format!("{pretty} [{label}]")).ok());
+        let result = run_with_progress(cmd, options.clone(), out);
+        let status = outcome(&result);
+        if let (Some(batch), Some(run)) = (batch, &run) {
+            let _ = batch.finish(run, &status);
+        }
+        let run_code = match &result {
+            Ok(_) => 0,
+            Err(err) => report(err, &program),
+        };
+        summary.push((label, status, Some(started.elapsed())));
+        let ignored = cli.ignore_exit_code
+            && matches!(result, Err(RunError::ChildFailed(_)));
+        if code == 0 && !ignored {
+            code = run_code;
+        }
+        if run_code != 0 && !ignored {
+            failures += 1;
+        }
+        if run_code == EXIT_CANCELLED || failures >= max_failures {
+            break;
+        }
+    }
+    // Listed too, so it's clear they didn't run rather than passed.
+    for combination in pending {
+        summary.push((matrix::describe(combination), "skipped".into(), None));
+    }
+    let width = summary
+        .iter()
+        .map(|(label, ..)| label.len())
+        .max()
+        .unwrap_or(0);
+    out.write_line("Matrix summary:")?;
+    for (label, status, elapsed) in summary {
+        let elapsed = elapsed.map_or("-".into(), |elapsed| {
+            format!("{:.1}s", elapsed.as_secs_f64())
+        });
+        let line = format!("  {label:<width$}  {status:<10} {elapsed}");
+        let line = match status.as_str() {
+            "success" => style(line).green(),
+            "skipped" => style(line).dim(),
+            _ => style(line).red(),
+        };
+        out.write_line(&line.to_string())?;
+    }
+    if let Some(dir) = &options.dump_dir {
+        out.write_line(&format!("Outputs saved in: {}", dir.display()))?;
+    }
+    Ok(code)
+}
+
+/// Parses the arguments, with help and errors naming the program as it was
+/// invoked, since packagers may rename the binary.
+fn parse_cli() -> Cli {
+    let mut command = Cli::command();
+    let arg0 = env::args_os().next();
+    if let Some(name) =
+        arg0.as_deref().and_then(|arg0| Path::new(arg0).file_stem())
+    {
+        command = command.name(name.to_string_lossy());
+    }
+    Cli::from_arg_matches(&command.get_matches())
+        .unwrap_or_else(|err| err.exit())
 }
 
 pub fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let cmd =
-        NonEmpty::from((&cli.command[0], cli.command[1..].iter().collect()));
-    let pretty = cli.name.unwrap_or_else(|| printable_command(&cmd));
-    println!("Command: {}", pretty.to_string_lossy());
-    let (status, _) = spawn_with_progress(cmd)?;
-    status
-        .success()
-        .then_some(())
-        .ok_or_else(|| exit(status.code().unwrap_or(1)))
+    let cli = parse_cli();
+    let dirs = Dirs::resolve();
+    if cli.diagnostics {
+        print!("{dirs}");
+        if let Some(warning) = dirs.warning() {
+            println!("{warning}");
+        }
+        return Ok(());
+    }
+    if cli.reset_terminal_cache {
+        let removed = match dirs.cache.path() {
+            Some(dir) => terminal::reset(dir)?,
+            None => 0,
+        };
+        println!("Forgot {removed} terminal type(s)");
+        return Ok(());
+    }
+    if cli.probe_terminal {
+        return probe_terminal(&dirs);
+    }
+    if let Some(id) = &cli.batch_watch {
+        let dir = dirs
+            .state
+            .path()
+            .ok_or_else(|| anyhow::anyhow!("no writable state directory"))?;
+        return watch_batch(&Batch::open(dir, id)?);
+    }
+    if let Some(warning) = dirs.warning() {
+        eprintln!("{}", style(format!("Warning: {warning}")).yellow());
+    }
+    let batch = cli.batch_id.as_deref().and_then(|id| open_batch(&dirs, id));
+    let mut options = cli.options();
+    let errors = validate(&cli, &options);
+    if !errors.is_empty() {
+        eprintln!("{}", style("Error: invalid options:").red());
+        for error in errors {
+            // Regex errors span several lines, kept under their option.
+            let error = error.replace('\n', "\n    ");
+            eprintln!("{}", style(format!("  {error}")).red());
+        }
+        exit(EXIT_USAGE);
+    }
+    for (name, _) in env::vars_os() {
+        if options.env.conflicts(&name) {
+            let msg = format!(
+                "Warning: {} matches both --env-allow and --env-deny; it is \
+                 not passed",
+                name.to_string_lossy()
+            );
+            eprintln!("{}", style(msg).yellow());
+        }
+    }
+    #[cfg(not(unix))]
+    if cli.umask.is_some() {
+        eprintln!(
+            "{}",
+            style("Warning: --umask is not supported on this platform")
+                .yellow()
+        );
+    }
+    if cli.dry_run {
+        return dry_run(&cli, &options);
+    }
+    if let Some(dir) = &cli.output_dir {
+        if let Err(err) = fs::create_dir_all(dir) {
+            let msg = format!("Error: --output-dir: {}: {err}", dir.display());
+            eprintln!("{}", style(msg).red());
+            exit(EXIT_DUMP_FAILED);
+        }
+    }
+    if options.plain {
+        // Also covers the error messages printed here.
+        console::set_colors_enabled(false);
+        console::set_colors_enabled_stderr(false);
+    }
+    // When capturing, stdout is reserved for the captured value(s).
+    let out = if options.capture.is_some()
+        || cli.status_stream == StatusStream::Stderr
+    {
+        Term::stderr()
+    } else {
+        Term::stdout()
+    };
+    if cli.frame == FrameChars::Auto && !options.plain {
+        options.ascii_frame =
+            terminal_capabilities(&dirs, &out).unicode == Some(false);
+    }
+    if !cli.vars.is_empty() {
+        exit(run_matrix(&cli, options, &out, batch.as_ref())?);
+    }
+    let words = cli.words();
+    let cmd = NonEmpty::from((&words[0], words[1..].iter().collect()));
+    let pretty = cli.name.clone().unwrap_or_else(|| {
+        cli.wrapped_name(if cli.annotate_pipe {
+            cli.command.join(" ").into()
+        } else {
+            printable_command(&cmd)
+        })
+    });
+    let note = if nested() { " [nested]" } else { "" };
+    out.write_line(&format!("Command: {}{note}", pretty.to_string_lossy()))?;
+    print_env(&out, &cli, &options)?;
+    print_limits(&out, &options.limits)?;
+    let program = cli.program(&words[0]);
+    let run = batch
+        .as_ref()
+        .and_then(|b| b.start(&pretty.to_string_lossy()).ok());
+    let result = run_with_progress(cmd, options, &out);
+    if let (Some(batch), Some(run)) = (&batch, &run) {
+        let _ = batch.finish(run, &outcome(&result));
+    }
+    match result {
+        Ok(result) => {
+            if let Some(capture) = result.capture {
+                print_captured(capture);
+            }
+            Ok(())
+        }
+        Err(RunError::ChildFailed(_)) if cli.ignore_exit_code => exit(0),
+        Err(err) => exit(report(&err, &program)),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use anyhow::Result;
-    use indicatif::ProgressBar;
+    use clap::Parser;
 
-    use crate::{progress, Line, State, Stream, MAX_LINES};
+    use crate::{validate, Cli};
 
     #[test]
-    fn test_unicode_splitting() -> Result<()> {
-        let mut state = State {
-            buf: Default::default(),
-            pb: ProgressBar::new_spinner(),
-            max_lines: MAX_LINES,
-            _term_lines: 10,
-            term_columns: 3,
-        };
-        let line = Line {
-            line: "ëëëëf".into(),
-            stream: Stream::Stdout,
-        };
-        progress(&mut state, &line)?;
-        Ok(())
+    fn test_wrapper_order() {
+        let cli = Cli::parse_from([
+            "runner",
+            "--wrap-with",
+            "time -p",
+            "--prefix-command",
+            "direnv exec .",
+            "--prefix-command",
+            "env 'A=a b'",
+            "--",
+            "make",
+        ]);
+        assert_eq!(
+            cli.wrapper(),
+            ["direnv", "exec", ".", "env", "A=a b", "time", "-p"]
+        );
+        assert_eq!(cli.program("make"), "direnv");
+        assert_eq!(cli.wrapped_name("make".into()), "make");
+    }
+
+    #[test]
+    fn test_validate() {
+        let cli = Cli::parse_from([
+            "runner",
+            "--stdin-file",
+            "/nonexistent",
+            "--var",
+            "os=linux,mac",
+            "--var",
+            "arch=x86,arm",
+            "--var",
+            "os=bsd",
+            "--",
+            "build",
+            "--os={os}",
+        ]);
+        let errors = validate(&cli, &cli.options());
+        assert_eq!(errors.len(), 3, "{errors:?}");
+        assert!(errors[0].starts_with("--stdin-file: /nonexistent: "));
+        assert_eq!(
+            errors[1],
+            "--var arch: {arch} is in neither the command nor --name"
+        );
+        assert_eq!(errors[2], "--var os: given more than once");
+
+        let cli = Cli::parse_from(["runner", "--var", "v=1,2", "echo", "{v}"]);
+        assert!(validate(&cli, &cli.options()).is_empty());
+
+        let cli = Cli::parse_from([
+            "runner",
+            "--capture",
+            "(",
+            "--strip-prefix",
+            "[",
+            "--output-dir",
+            "/dev/null",
+            "true",
+        ]);
+        let errors = validate(&cli, &cli.options());
+        assert_eq!(errors.len(), 3, "{errors:?}");
+        assert!(errors[0].starts_with("--capture: regex parse error"));
+        assert!(errors[1].starts_with("--strip-prefix: regex parse error"));
+        assert_eq!(errors[2], "--output-dir: /dev/null is not a directory");
     }
 }
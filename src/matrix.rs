@@ -0,0 +1,84 @@
+/// A variable of a matrix run, given as `name=value1,value2`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Var {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+/// Values picked for each variable, in the order the variables were given.
+pub type Combination = Vec<(String, String)>;
+
+impl Var {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (name, values) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=VALUES, got: {spec}"))?;
+        if name.is_empty() {
+            return Err(format!("missing variable name in: {spec}"));
+        }
+        Ok(Self {
+            name: name.into(),
+            values: values.split(',').map(String::from).collect(),
+        })
+    }
+}
+
+/// Every combination of values (the cartesian product), varying the last
+/// variable fastest.
+pub fn combinations(vars: &[Var]) -> Vec<Combination> {
+    vars.iter().fold(vec![vec![]], |combinations, var| {
+        combinations
+            .iter()
+            .flat_map(|combination| {
+                var.values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push((var.name.clone(), value.clone()));
+                    combination
+                })
+            })
+            .collect()
+    })
+}
+
+/// Replaces every `{name}` in `word` with the value picked for `name`.
+pub fn substitute(word: &str, combination: &Combination) -> String {
+    combination
+        .iter()
+        .fold(word.to_string(), |word, (name, value)| {
+            word.replace(&format!("{{{name}}}"), value)
+        })
+}
+
+/// A short label for a combination, like `target=x86_64, profile=release`.
+pub fn describe(combination: &Combination) -> String {
+    combination
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::matrix::{combinations, describe, substitute, Var};
+
+    #[test]
+    fn test_expand_matrix() {
+        let vars = [
+            Var::parse("os=linux,mac").unwrap(),
+            Var::parse("v=1").unwrap(),
+        ];
+        let all = combinations(&vars);
+        assert_eq!(
+            all.iter().map(describe).collect::<Vec<_>>(),
+            ["os=linux, v=1", "os=mac, v=1"]
+        );
+        assert_eq!(
+            substitute("--target={os}-{v}{x}", &all[1]),
+            "--target=mac-1{x}"
+        );
+        assert_eq!(combinations(&[]), [vec![]]);
+        assert!(Var::parse("nothing").is_err());
+        assert!(Var::parse("=a,b").is_err());
+    }
+}
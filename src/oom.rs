@@ -0,0 +1,139 @@
+#[cfg(all(target_os = "linux", feature = "oom-hint"))]
+use std::fs;
+#[cfg(all(target_os = "linux", feature = "oom-hint"))]
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+/// Looks for signs that the kernel killed the command for lack of memory.
+///
+/// This is a heuristic: a command dying from `SIGKILL` that athens didn't
+/// send is blamed on the OOM killer if the memory cgroup recorded a kill
+/// during the run, or if the command's peak RSS came close to the memory
+/// available. Anything less conclusive says nothing.
+pub(crate) struct OomWatch {
+    #[cfg(all(target_os = "linux", feature = "oom-hint"))]
+    kills_before: Option<u64>,
+}
+
+#[cfg(all(target_os = "linux", feature = "oom-hint"))]
+impl OomWatch {
+    /// Starts watching, before the command is spawned.
+    pub(crate) fn start() -> Self {
+        Self {
+            kills_before: _cgroup_oom_kills(),
+        }
+    }
+
+    /// Whether the command that ended with `status` was likely OOM-killed.
+    pub(crate) fn likely(
+        &self,
+        status: &ExitStatus,
+        peak_rss: Option<u64>,
+    ) -> bool {
+        use std::os::unix::process::ExitStatusExt;
+
+        status.signal() == Some(libc::SIGKILL)
+            && _likely(
+                self.kills_before.zip(_cgroup_oom_kills()),
+                peak_rss.zip(_memory_limit()),
+            )
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "oom-hint")))]
+impl OomWatch {
+    pub(crate) fn start() -> Self {
+        Self {}
+    }
+
+    pub(crate) fn likely(
+        &self,
+        _status: &ExitStatus,
+        _peak_rss: Option<u64>,
+    ) -> bool {
+        false
+    }
+}
+
+/// Weighs the cgroup's OOM kill counts before and after the run, and the
+/// peak RSS against the memory limit.
+#[cfg_attr(
+    not(all(target_os = "linux", feature = "oom-hint")),
+    allow(dead_code)
+)]
+fn _likely(kills: Option<(u64, u64)>, rss: Option<(u64, u64)>) -> bool {
+    match (kills, rss) {
+        (Some((before, after)), _) if after > before => true,
+        // Allocations are refused a little before the limit is reached.
+        (_, Some((peak, limit))) => peak >= limit / 10 * 9,
+        _ => false,
+    }
+}
+
+/// The files of this process' memory cgroup counting its OOM kills and
+/// holding its limit, with cgroup v2 or v1.
+#[cfg(all(target_os = "linux", feature = "oom-hint"))]
+fn _cgroup_files() -> Option<(PathBuf, PathBuf)> {
+    let cgroups = fs::read_to_string("/proc/self/cgroup").ok()?;
+    let root = Path::new("/sys/fs/cgroup");
+    cgroups.lines().find_map(|line| {
+        let (_, line) = line.split_once(':')?;
+        let (controllers, path) = line.split_once(':')?;
+        let path = path.trim_start_matches('/');
+        let (dir, events, limit) = match controllers {
+            "" => (root.join(path), "memory.events", "memory.max"),
+            "memory" => (
+                root.join("memory").join(path),
+                "memory.oom_control",
+                "memory.limit_in_bytes",
+            ),
+            _ => return None,
+        };
+        let events = dir.join(events);
+        events.exists().then(|| (events, dir.join(limit)))
+    })
+}
+
+/// Processes of the cgroup killed by the OOM killer so far.
+#[cfg(all(target_os = "linux", feature = "oom-hint"))]
+fn _cgroup_oom_kills() -> Option<u64> {
+    let (events, _) = _cgroup_files()?;
+    fs::read_to_string(events)
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("oom_kill "))
+        .and_then(|count| count.trim().parse().ok())
+}
+
+/// The memory limit of the cgroup or the machine's memory, whichever is
+/// lower.
+#[cfg(all(target_os = "linux", feature = "oom-hint"))]
+fn _memory_limit() -> Option<u64> {
+    let cgroup = _cgroup_files()
+        .and_then(|(_, limit)| fs::read_to_string(limit).ok())
+        .and_then(|limit| limit.trim().parse().ok());
+    let total = fs::read_to_string("/proc/meminfo").ok().and_then(|info| {
+        let total = info
+            .lines()
+            .find_map(|line| line.strip_prefix("MemTotal:"))?;
+        let kib = total.trim().trim_end_matches("kB").trim().parse::<u64>();
+        kib.ok().map(|kib| kib * 1024)
+    });
+    match (cgroup, total) {
+        (Some(cgroup), Some(total)) => Some(cgroup.min(total)),
+        (limit, None) | (None, limit) => limit,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::oom::_likely;
+
+    #[test]
+    fn test_likely() {
+        assert!(_likely(Some((0, 1)), None));
+        assert!(_likely(None, Some((95, 100))));
+        assert!(!_likely(Some((2, 2)), Some((10, 100))));
+        assert!(!_likely(None, None));
+    }
+}
@@ -0,0 +1,159 @@
+/// Splits a shell pipeline into its stages, on the `|` outside quotes,
+/// comments, command substitutions and subshells.
+///
+/// Parsing is best-effort: `None` is returned for anything that isn't a
+/// plain pipeline of two or more non-empty stages (`||`, unbalanced
+/// quotes, a trailing `|`, a `|` inside `$(...)`, backticks or
+/// parentheses...), and the caller should then treat the script as a
+/// single command.
+pub fn split(script: &str) -> Option<Vec<String>> {
+    let mut stages = vec![String::new()];
+    // The quotes, backticks and parentheses `c` is in, innermost last.
+    let mut nesting = Vec::new();
+    let mut comment = false;
+    let mut chars = script.chars().peekable();
+    while let Some(c) = chars.next() {
+        let stage = stages.last_mut()?;
+        let word_start = stage.ends_with(char::is_whitespace)
+            || stage.ends_with([';', '&', '('])
+            || stage.trim().is_empty();
+        match (nesting.last(), c) {
+            _ if comment => comment = c != '\n',
+            (Some('\''), '\'') | (Some('"'), '"') | (Some('`'), '`') => {
+                nesting.pop();
+            }
+            (Some('\''), _) => (),
+            (_, '\\') => {
+                stage.push(c);
+                stage.push(chars.next()?);
+                continue;
+            }
+            (Some('"'), '$') if chars.peek() == Some(&'(') => {
+                stage.push(c);
+                stage.push(chars.next()?);
+                nesting.push('(');
+                continue;
+            }
+            (Some('"'), '`') => nesting.push(c),
+            (Some('"'), _) => (),
+            (_, '\'' | '"' | '`' | '(') => nesting.push(c),
+            (Some('('), ')') => {
+                nesting.pop();
+            }
+            (None, ')') => return None,
+            (_, '#') if word_start => comment = true,
+            (Some(_), '|') => return None,
+            (None, '|') if chars.peek() == Some(&'|') => return None,
+            (None, '|') => {
+                stages.push(String::new());
+                continue;
+            }
+            _ => (),
+        }
+        stage.push(c);
+    }
+    let stages = stages
+        .iter()
+        .map(|stage| stage.trim().to_string())
+        .collect::<Vec<_>>();
+    let valid = nesting.is_empty()
+        && stages.len() > 1
+        && stages.iter().all(|stage| !stage.is_empty());
+    valid.then_some(stages)
+}
+
+/// The command running `stages` as a pipeline in `sh`, with every line a
+/// stage prints on stderr prefixed by `[program]`. Stdout needs no tag: it
+/// all comes from the last stage. The exit status is the last stage's, as
+/// in the shell.
+pub fn annotate(stages: &[String]) -> Vec<String> {
+    let mut script = String::from(
+        "d=$(mktemp -d) || exit 1\n\
+         trap 'rm -rf \"$d\"' EXIT\n\
+         _tag() {\n\
+         \x20 while IFS= read -r line || [ -n \"$line\" ]; do\n\
+         \x20   printf '[%s] %s\\n' \"$1\" \"$line\"\n\
+         \x20 done <\"$2\" >&2\n\
+         }\n",
+    );
+    for i in 0..stages.len() {
+        let n = i + 1;
+        script.push_str(&format!(
+            "mkfifo \"$d/{n}\"\n_tag \"${n}\" \"$d/{n}\" &\n"
+        ));
+    }
+    let pipeline = stages
+        .iter()
+        .enumerate()
+        .map(|(i, stage)| format!("{{ {stage}\n}} 2>\"$d/{}\"", i + 1))
+        .collect::<Vec<_>>()
+        .join(" | ");
+    script.push_str(&pipeline);
+    script.push_str("\nstatus=$?\nwait\nexit $status\n");
+    let mut argv = vec!["sh".into(), "-c".into(), script, "sh".into()];
+    argv.extend(stages.iter().map(|stage| _program(stage)));
+    argv
+}
+
+/// The first word of a stage, skipping variable assignments and the
+/// punctuation of subshells and groups.
+fn _program(stage: &str) -> String {
+    stage
+        .split(|c: char| c.is_whitespace() || "(){};".contains(c))
+        .find(|word| !word.is_empty() && !word.contains('='))
+        .unwrap_or(stage)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use anyhow::Result;
+
+    use crate::pipeline::{annotate, split};
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            split("make 2>&1 | grep 'a|b' | sort -u").unwrap(),
+            ["make 2>&1", "grep 'a|b'", "sort -u"]
+        );
+        assert_eq!(
+            split(r#"echo "\"|" | wc"#).unwrap(),
+            [r#"echo "\"|""#, "wc"]
+        );
+        assert_eq!(split("make"), None);
+        assert_eq!(split("make || true"), None);
+        assert_eq!(split("make |"), None);
+        assert_eq!(split("echo 'a | b"), None);
+        assert_eq!(split("echo $(ls | wc -l) | cat"), None);
+        assert_eq!(split("echo \"$(ls | wc -l)\" | cat"), None);
+        assert_eq!(split("echo `ls | wc -l` | cat"), None);
+        assert_eq!(split("(make | tee log) | wc"), None);
+        assert_eq!(
+            split("echo $(date) ')' \"(\" | wc  # count | lines").unwrap(),
+            ["echo $(date) ')' \"(\"", "wc  # count | lines"]
+        );
+        assert_eq!(split("make # a|b\n | wc").unwrap(), ["make # a|b", "wc"]);
+        assert_eq!(split("a#b | c").unwrap(), ["a#b", "c"]);
+    }
+
+    #[test]
+    fn test_annotate() -> Result<()> {
+        let stages = split("echo hi; echo oops >&2 | LC_ALL=C tr a-z A-Z")
+            .expect("a pipeline");
+        let argv = annotate(&stages);
+        let output = Command::new(&argv[0]).args(&argv[1..]).output()?;
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"HI\n");
+        assert_eq!(output.stderr, b"[echo] oops\n");
+        assert_eq!(annotate(&["(cd x; make)".into()])[4], "cd");
+
+        let output = Command::new("sh")
+            .args(&annotate(&split("true | exit 3").expect("a pipeline"))[1..])
+            .output()?;
+        assert_eq!(output.status.code(), Some(3));
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use regex::Regex;
+
+/// How often a followed file is checked for new lines.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A line showing that what the command started is up, for commands that
+/// start a service in the background and exit.
+#[derive(Clone, Debug)]
+pub struct Ready {
+    /// Matched against the lines of the command and of `follow`.
+    pub regex: Regex,
+    /// How long after the start of the run the line can come.
+    pub timeout: Duration,
+    /// A file to read lines from as well, like the log of the service.
+    /// Only what is written to it once the run started is read.
+    pub follow: Option<PathBuf>,
+}
+
+/// The lines written to a file from now on, as `tail -f` shows them. At
+/// the end of the file, reading waits for more until `done` is set.
+pub(crate) struct Tail {
+    path: PathBuf,
+    file: Option<File>,
+    done: Arc<AtomicBool>,
+}
+
+impl Tail {
+    pub(crate) fn new(path: PathBuf, done: Arc<AtomicBool>) -> Self {
+        // A file created later is read from its start.
+        let file = File::open(&path).ok().and_then(|mut file| {
+            file.seek(SeekFrom::End(0)).ok()?;
+            Some(file)
+        });
+        Self { path, file, done }
+    }
+}
+
+impl Read for Tail {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !self.done.load(Ordering::Relaxed) {
+            if self.file.is_none() {
+                self.file = File::open(&self.path).ok();
+            }
+            if let Some(file) = &mut self.file {
+                let n = file.read(buf)?;
+                if n > 0 {
+                    return Ok(n);
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::{Read, Write};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use anyhow::Result;
+
+    use crate::ready::{Tail, POLL_INTERVAL};
+
+    #[test]
+    fn test_tail() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("service.log");
+        fs::write(&path, "old\n")?;
+        let done = Arc::new(AtomicBool::new(false));
+        let mut tail = Tail::new(path.clone(), done.clone());
+        let reader = thread::spawn(move || {
+            let mut read = String::new();
+            tail.read_to_string(&mut read).map(|_| read)
+        });
+        let mut log = OpenOptions::new().append(true).open(&path)?;
+        log.write_all(b"new\n")?;
+        thread::sleep(POLL_INTERVAL * 4);
+        done.store(true, Ordering::Relaxed);
+        assert_eq!(reader.join().expect("no panic")?, "new\n");
+        Ok(())
+    }
+}
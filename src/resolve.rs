@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nonempty::NonEmpty;
+use serde::{Serialize, Serializer};
+
+use crate::{printable_command, EnvFilter, Limits, RunOptions, NESTED_ENV};
+
+/// Everything a run will do, worked out from the command and the options
+/// before anything is spawned. The [`Command`] is built from it, and
+/// `--dry-run` prints it.
+#[derive(Clone, Debug, Serialize)]
+pub struct ResolvedRun {
+    /// The program and its arguments.
+    #[serde(serialize_with = "_lossy_all")]
+    pub argv: NonEmpty<OsString>,
+    /// What `argv` is run through, if anything.
+    #[serde(serialize_with = "_lossy_all")]
+    pub wrapper: Vec<OsString>,
+    /// Where the command runs: athens' own working directory.
+    pub cwd: Option<PathBuf>,
+    /// Variables set for the command on top of the inherited ones.
+    pub env_set: BTreeMap<String, String>,
+    /// Inherited variables removed by the environment filter.
+    #[serde(serialize_with = "_lossy_all")]
+    pub env_removed: Vec<OsString>,
+    /// The patterns of the environment filter. Values are never recorded.
+    pub env_filter: EnvFilter,
+    /// Directory where the output is saved.
+    pub dump_dir: PathBuf,
+    /// Exit codes that count as success.
+    pub success_codes: Vec<i32>,
+    pub encoding: &'static str,
+    /// The capture regex, if any.
+    pub capture: Option<String>,
+    pub max_buffer_lines: Option<usize>,
+    /// The input given to the command, if not athens' stdin.
+    pub stdin: Option<String>,
+    pub stdin_tee: bool,
+    pub limits: Limits,
+    /// The file mode creation mask of the command, if not athens' own.
+    #[serde(serialize_with = "_octal")]
+    pub umask: Option<u32>,
+}
+
+impl ResolvedRun {
+    pub fn resolve<S>(words: NonEmpty<S>, options: &RunOptions) -> Self
+    where
+        S: AsRef<OsStr>,
+    {
+        let mut env_removed = options.env.removed();
+        env_removed.sort();
+        let success_codes = if options.success_codes.is_empty() {
+            vec![0]
+        } else {
+            options.success_codes.clone()
+        };
+        Self {
+            argv: words.map(|word| word.as_ref().to_os_string()),
+            wrapper: options.wrapper.clone(),
+            cwd: env::current_dir().ok(),
+            env_set: BTreeMap::from([(NESTED_ENV.into(), "1".into())]),
+            env_removed,
+            env_filter: options.env.clone(),
+            dump_dir: options.dump_dir.clone().unwrap_or_else(env::temp_dir),
+            success_codes,
+            encoding: if options.detect_encoding {
+                "auto"
+            } else {
+                options.encoding().name()
+            },
+            capture: options
+                .capture
+                .as_ref()
+                .map(|capture| capture.regex().as_str().into()),
+            max_buffer_lines: options.max_buffer_lines,
+            stdin: options.stdin.as_ref().map(|stdin| {
+                match options.stdin_repeat.filter(|&n| n > 1) {
+                    Some(n) => format!("{}, {n} times", stdin.describe()),
+                    None => stdin.describe(),
+                }
+            }),
+            stdin_tee: options.stdin_tee && options.stdin.is_none(),
+            limits: options.limits.clone(),
+            umask: options.umask,
+        }
+    }
+
+    /// The command to spawn: the wrapper followed by `argv`.
+    pub fn command(&self) -> Command {
+        let mut words = self.wrapper.iter().chain(&self.argv);
+        let mut cmd = Command::new(words.next().expect("argv is not empty"));
+        cmd.args(words);
+        for name in &self.env_removed {
+            cmd.env_remove(name);
+        }
+        cmd.envs(&self.env_set);
+        self.limits.apply(&mut cmd);
+        #[cfg(unix)]
+        if let Some(mask) = self.umask {
+            use std::os::unix::process::CommandExt;
+
+            // SAFETY: `umask` can't fail, and is safe to call between `fork`
+            // and `exec`.
+            unsafe {
+                cmd.pre_exec(move || {
+                    libc::umask(mask as libc::mode_t);
+                    Ok(())
+                });
+            }
+        }
+        cmd
+    }
+
+    /// Lines recording how the run was made, for the top of the dump: the
+    /// command, where and when it ran, and the variables changed (only
+    /// names, for the removed ones).
+    pub fn header(&self, started: SystemTime) -> String {
+        let words = self.wrapper.iter().chain(&self.argv).collect::<Vec<_>>();
+        let words = NonEmpty::from_vec(words).expect("argv is not empty");
+        let mut lines = vec![
+            format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            format!("command: {}", printable_command(&words).to_string_lossy()),
+        ];
+        if let Some(cwd) = &self.cwd {
+            lines.push(format!("cwd: {}", cwd.display()));
+        }
+        lines.push(format!("started: {}", _utc(started)));
+        for (name, value) in &self.env_set {
+            lines.push(format!("env set: {name}={value}"));
+        }
+        for name in &self.env_removed {
+            lines.push(format!("env removed: {}", name.to_string_lossy()));
+        }
+        let mut header = String::new();
+        for line in lines {
+            header.push_str(&format!("# {line}\n"));
+        }
+        header.push_str("#\n");
+        header
+    }
+}
+
+/// `time` as in RFC 3339, in UTC and to the second.
+fn _utc(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // From days since the epoch to the civil date, as in
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+fn _octal<S>(mask: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match mask {
+        Some(mask) => serializer.serialize_str(&format!("{mask:04o}")),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn _lossy_all<'a, S, I>(values: I, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    I: IntoIterator<Item = &'a OsString>,
+{
+    serializer.collect_seq(values.into_iter().map(|v| v.to_string_lossy()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::ffi::OsStr;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use anyhow::Result;
+
+    use crate::resolve::{_utc, ResolvedRun};
+    use crate::{EnvFilter, RunOptions, NESTED_ENV};
+
+    #[test]
+    fn test_resolve_command() -> Result<()> {
+        let options = RunOptions {
+            env: EnvFilter {
+                allow: vec![],
+                deny: vec!["PATH".into()],
+            },
+            success_codes: vec![0, 1],
+            ..Default::default()
+        };
+        let run =
+            ResolvedRun::resolve(nonempty::nonempty!["ls", "-l"], &options);
+        let json = serde_json::to_value(&run)?;
+        assert_eq!(json["argv"], serde_json::json!(["ls", "-l"]));
+        assert_eq!(json["env_set"][NESTED_ENV], "1");
+        assert_eq!(json["success_codes"], serde_json::json!([0, 1]));
+        assert_eq!(json["wrapper"], serde_json::json!([]));
+        assert_eq!(
+            json["env_filter"],
+            serde_json::json!({"allow": [], "deny": ["PATH"]})
+        );
+
+        let cmd = run.command();
+        let envs = cmd.get_envs().collect::<Vec<_>>();
+        assert!(envs.contains(&(OsStr::new(NESTED_ENV), Some(OsStr::new("1")))));
+        if env::var_os("PATH").is_some() {
+            assert!(envs.contains(&(OsStr::new("PATH"), None)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_wrapper() {
+        let options = RunOptions {
+            wrapper: vec!["nice".into(), "-n".into(), "5".into()],
+            ..Default::default()
+        };
+        let cmd =
+            ResolvedRun::resolve(nonempty::nonempty!["ls", "-l"], &options)
+                .command();
+        assert_eq!(cmd.get_program(), "nice");
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(), ["-n", "5", "ls", "-l"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_umask() -> Result<()> {
+        let options = RunOptions {
+            umask: Some(0o027),
+            ..Default::default()
+        };
+        let run = ResolvedRun::resolve(
+            nonempty::nonempty!["sh", "-c", "umask"],
+            &options,
+        );
+        assert_eq!(serde_json::to_value(&run)?["umask"], "0027");
+        let output = run.command().output()?;
+        assert_eq!(String::from_utf8(output.stdout)?, "0027\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_header() {
+        assert_eq!(_utc(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(1_709_251_199);
+        assert_eq!(_utc(leap_day), "2024-02-29T23:59:59Z");
+
+        let options = RunOptions {
+            wrapper: vec!["nice".into()],
+            env: EnvFilter {
+                allow: vec![],
+                deny: vec!["PATH".into()],
+            },
+            ..Default::default()
+        };
+        let run = ResolvedRun::resolve(nonempty::nonempty!["make"], &options);
+        let header = run.header(UNIX_EPOCH);
+        let lines = header.lines().collect::<Vec<_>>();
+        assert_eq!(lines[1], "# command: nice make");
+        assert!(lines.contains(&"# started: 1970-01-01T00:00:00Z"));
+        assert!(lines.contains(&format!("# env set: {NESTED_ENV}=1").as_str()));
+        if env::var_os("PATH").is_some() {
+            assert!(lines.contains(&"# env removed: PATH"));
+        }
+        assert_eq!(lines.last(), Some(&"#"));
+    }
+}
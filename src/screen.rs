@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+
+use encoding_rs::{Decoder, Encoding};
+
+use crate::{Line, Stream};
+
+/// A tiny virtual terminal used by the raw preview.
+///
+/// Bytes from both streams are written at a shared cursor, as a terminal
+/// would do. Only `\n`, `\r` and backspace are interpreted, which is enough
+/// for the usual progress animations. Rows that scroll off the screen are
+/// handed back to the caller so they end up in the dump.
+pub(crate) struct Screen {
+    rows: VecDeque<Line>,
+    current: Vec<char>,
+    stream: Stream,
+    cursor: usize,
+    max_rows: usize,
+    decoders: [Decoder; 2],
+}
+
+impl Screen {
+    pub(crate) fn new(max_rows: usize, encoding: &'static Encoding) -> Self {
+        Self {
+            rows: VecDeque::new(),
+            current: Vec::new(),
+            stream: Stream::Stdout,
+            cursor: 0,
+            max_rows,
+            decoders: [
+                encoding.new_decoder_without_bom_handling(),
+                encoding.new_decoder_without_bom_handling(),
+            ],
+        }
+    }
+
+    pub(crate) fn set_max_rows(&mut self, max_rows: usize) {
+        self.max_rows = max_rows;
+        while self.rows.len() > self.max_rows {
+            self.rows.pop_front();
+        }
+    }
+
+    /// Applies a chunk of output and returns the lines completed by it.
+    pub(crate) fn feed(&mut self, stream: Stream, bytes: &[u8]) -> Vec<Line> {
+        let text = self.decode(&stream, bytes);
+        let mut done = Vec::new();
+        for c in text.chars() {
+            match c {
+                '\n' => done.push(self.newline()),
+                '\r' => self.cursor = 0,
+                '\x08' => self.cursor = self.cursor.saturating_sub(1),
+                _ => {
+                    self.stream = stream.clone();
+                    if self.cursor < self.current.len() {
+                        self.current[self.cursor] = c;
+                    } else {
+                        self.current.push(c);
+                    }
+                    self.cursor += 1;
+                }
+            }
+        }
+        done
+    }
+
+    /// Returns the unterminated last row, if any, once the output is over.
+    pub(crate) fn finish(&mut self) -> Option<Line> {
+        (!self.current.is_empty()).then(|| self.newline())
+    }
+
+    /// The visible rows, oldest first.
+    pub(crate) fn rows(&self) -> Vec<Line> {
+        let mut rows = self.rows.iter().cloned().collect::<Vec<_>>();
+        if !self.current.is_empty() {
+            rows.push(self.current_line());
+        }
+        let skip = rows.len().saturating_sub(self.max_rows);
+        rows.split_off(skip)
+    }
+
+    fn current_line(&self) -> Line {
+        Line {
+            line: self.current.iter().collect::<String>().into(),
+            stream: self.stream.clone(),
+            crlf: false,
+        }
+    }
+
+    fn newline(&mut self) -> Line {
+        let line = self.current_line();
+        self.rows.push_back(line.clone());
+        while self.rows.len() > self.max_rows {
+            self.rows.pop_front();
+        }
+        self.current.clear();
+        self.cursor = 0;
+        line
+    }
+
+    /// Decodes a chunk, keeping an incomplete trailing sequence for the
+    /// next chunk of the same stream.
+    fn decode(&mut self, stream: &Stream, bytes: &[u8]) -> String {
+        let decoder = match stream {
+            Stream::Stdout => &mut self.decoders[0],
+            Stream::Stderr => &mut self.decoders[1],
+        };
+        let len = decoder
+            .max_utf8_buffer_length(bytes.len())
+            .expect("chunks are small");
+        let mut text = String::with_capacity(len);
+        let _ = decoder.decode_to_string(bytes, &mut text, false);
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::UTF_8;
+
+    use crate::screen::Screen;
+    use crate::Stream;
+
+    fn texts(screen: &Screen) -> Vec<String> {
+        screen
+            .rows()
+            .into_iter()
+            .map(|l| l.line.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_carriage_return_and_backspace() {
+        let mut screen = Screen::new(2, UTF_8);
+        let done = screen.feed(Stream::Stdout, b"0%\r50%\r100%\nabc\x08\x08X");
+        assert_eq!(done.len(), 1);
+        assert_eq!(&*done[0].line, "100%");
+        assert_eq!(texts(&screen), ["100%", "aXc"]);
+        screen.feed(Stream::Stderr, "\në".as_bytes());
+        assert_eq!(texts(&screen), ["aXc", "ë"]);
+        assert_eq!(screen.finish().map(|l| l.line), Some("ë".into()));
+    }
+
+    #[test]
+    fn test_split_utf8_sequence() {
+        let mut screen = Screen::new(4, UTF_8);
+        let bytes = "ëf".as_bytes();
+        screen.feed(Stream::Stdout, &bytes[..1]);
+        screen.feed(Stream::Stdout, &bytes[1..]);
+        assert_eq!(texts(&screen), ["ëf"]);
+    }
+}
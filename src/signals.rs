@@ -0,0 +1,59 @@
+use std::sync::mpsc::Sender;
+
+use crate::{Control, Event};
+
+/// Forwards `SIGUSR1`/`SIGUSR2` to the main thread as [`Control`] events
+/// for as long as it is alive.
+#[cfg(unix)]
+pub(crate) struct Listener {
+    handle: signal_hook::iterator::Handle,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+#[cfg(unix)]
+impl Listener {
+    /// Starts listening. Adjusting the display is a convenience, so failing
+    /// to install the handlers just leaves the signals alone.
+    pub(crate) fn start(sender: Sender<Event>) -> Option<Self> {
+        use signal_hook::consts::{SIGUSR1, SIGUSR2};
+        use signal_hook::iterator::Signals;
+
+        let mut signals = Signals::new([SIGUSR1, SIGUSR2]).ok()?;
+        let handle = signals.handle();
+        let thread = std::thread::spawn(move || {
+            for signal in signals.forever() {
+                let control = match signal {
+                    SIGUSR1 => Control::GrowWindow,
+                    _ => Control::ToggleStderrOnly,
+                };
+                if sender.send(Event::Control(control)).is_err() {
+                    break;
+                }
+            }
+        });
+        Some(Self {
+            handle,
+            thread: Some(thread),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.handle.close();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) struct Listener;
+
+#[cfg(not(unix))]
+impl Listener {
+    pub(crate) fn start(_sender: Sender<Event>) -> Option<Self> {
+        None
+    }
+}
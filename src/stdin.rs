@@ -0,0 +1,186 @@
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Seek, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+/// Input given to the command instead of athens' stdin. The command's stdin
+/// is closed once all of it is written.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StdinInput {
+    Data(Vec<u8>),
+    File(PathBuf),
+}
+
+impl StdinInput {
+    /// A short description, like `12 bytes of data` or the file's path.
+    pub fn describe(&self) -> String {
+        match self {
+            StdinInput::Data(data) => format!("{} bytes of data", data.len()),
+            StdinInput::File(path) => path.to_string_lossy().into_owned(),
+        }
+    }
+}
+
+/// Where the command's stdin comes from.
+pub(crate) enum Input {
+    /// The command reads athens' stdin directly.
+    Inherit,
+    /// athens' stdin is copied to the command, and also to the file.
+    Tee(File),
+    /// Everything in the reader is written to the command, `repeat` times
+    /// with `delimiter` in between.
+    Feed {
+        from: Box<dyn Source>,
+        repeat: u64,
+        delimiter: Vec<u8>,
+    },
+}
+
+/// Input that can be read again from the start.
+pub(crate) trait Source: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> Source for T {}
+
+impl Input {
+    pub(crate) fn configure(&self, cmd: &mut Command) {
+        match self {
+            Input::Inherit => cmd.stdin(Stdio::inherit()),
+            Input::Tee(_) | Input::Feed { .. } => cmd.stdin(Stdio::piped()),
+        };
+    }
+
+    /// Starts feeding the child, from a thread so the child can fill its
+    /// output pipes before reading all of its input. The thread is never
+    /// joined: it may stay blocked reading our stdin after the child is
+    /// gone, and everything it forwarded is already written to the file by
+    /// then.
+    pub(crate) fn start(self, child: &mut Child) {
+        if let Some(to) = child.stdin.take() {
+            self.write_to(to);
+        }
+    }
+
+    /// Starts feeding `to`, the child's stdin, as [`Input::start`] does.
+    pub(crate) fn write_to<W: Write + Send + 'static>(self, to: W) {
+        match self {
+            Input::Inherit => (),
+            Input::Tee(file) => {
+                thread::spawn(move || pump(io::stdin().lock(), to, Some(file)));
+            }
+            Input::Feed {
+                from,
+                repeat,
+                delimiter,
+            } => {
+                thread::spawn(move || feed(from, to, repeat, &delimiter));
+            }
+        }
+    }
+}
+
+/// Writes all of `from` to `to` `repeat` times, with `delimiter` between
+/// the copies. Stops early when the child closes its stdin.
+fn feed<R, W>(mut from: R, mut to: W, repeat: u64, delimiter: &[u8])
+where
+    R: Read + Seek,
+    W: Write,
+{
+    for i in 0..repeat {
+        if i > 0 && (from.rewind().is_err() || to.write_all(delimiter).is_err())
+        {
+            return;
+        }
+        if !pump(&mut from, &mut to, None::<File>) {
+            return;
+        }
+    }
+}
+
+/// Copies `from` into `to`, recording every chunk in `tee` first. Stops
+/// quietly on end of input or when the child closes its stdin, returning
+/// whether all of the input was written; dropping `to` at the end closes
+/// the child's stdin.
+fn pump<R, W, T>(mut from: R, mut to: W, mut tee: Option<T>) -> bool
+where
+    R: Read,
+    W: Write,
+    T: Write,
+{
+    let mut buf = [0; 8192];
+    loop {
+        let n = match from.read(&mut buf) {
+            Ok(0) => return true,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return false,
+        };
+        // A failing copy must not get in the way of the command's input.
+        if tee
+            .as_mut()
+            .is_some_and(|t| t.write_all(&buf[..n]).is_err())
+        {
+            tee = None;
+        }
+        if to.write_all(&buf[..n]).and_then(|_| to.flush()).is_err() {
+            return false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor, Write};
+
+    use crate::stdin::{feed, pump};
+
+    /// Accepts `room` bytes, then behaves like a closed pipe.
+    struct Closing {
+        room: usize,
+    }
+
+    impl Write for Closing {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.room == 0 {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            let n = buf.len().min(self.room);
+            self.room -= n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pump_copies_and_records() {
+        let mut to = Vec::new();
+        let mut tee = Vec::new();
+        pump(Cursor::new(b"select 1;\n"), &mut to, Some(&mut tee));
+        assert_eq!(to, b"select 1;\n");
+        assert_eq!(tee, b"select 1;\n");
+    }
+
+    #[test]
+    fn test_feed_repeats() {
+        let mut to = Vec::new();
+        feed(Cursor::new(b"a\nb\n"), &mut to, 3, b"--\n");
+        assert_eq!(to, b"a\nb\n--\na\nb\n--\na\nb\n");
+        let mut to = Vec::new();
+        feed(Cursor::new(b"a"), &mut to, 0, b"");
+        assert!(to.is_empty());
+        // Stops at the first copy the child didn't take.
+        feed(Cursor::new(b"abc"), Closing { room: 4 }, u64::MAX, b"");
+    }
+
+    #[test]
+    fn test_pump_stops_when_child_closes_stdin() {
+        let input = vec![b'x'; 20000];
+        let mut tee = Vec::new();
+        pump(Cursor::new(&input), Closing { room: 10 }, Some(&mut tee));
+        // The first chunk was offered to the child, then reading stopped.
+        assert_eq!(tee.len(), 8192);
+    }
+}
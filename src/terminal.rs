@@ -0,0 +1,200 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// How long the terminal has to answer the probe. Terminals answer in a few
+/// milliseconds, and athens doesn't wait longer than this for one that
+/// doesn't.
+pub const PROBE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// What the terminal answered to the probe, cached per terminal type so it
+/// is only asked once. `None` is for questions it didn't answer.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Whether `╭` took a single column, so the terminal decodes UTF-8.
+    pub unicode: Option<bool>,
+    /// The answer to the primary device attributes query (DA1), like
+    /// `?62;22c`, without the leading escape.
+    pub device_attributes: Option<String>,
+}
+
+/// The name the results are cached under, from `TERM` and `TERM_PROGRAM`.
+pub fn cache_key() -> String {
+    let var = |name| env::var(name).unwrap_or_default();
+    let key = format!("{}+{}", var("TERM"), var("TERM_PROGRAM"));
+    key.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "+-.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn _path(cache: &Path, key: &str) -> PathBuf {
+    cache.join("terminal").join(format!("{key}.json"))
+}
+
+/// The results cached for `key` in the `cache` directory, if any. A cache
+/// that can't be read is treated as missing.
+pub fn cached(cache: &Path, key: &str) -> Option<Capabilities> {
+    let json = fs::read_to_string(_path(cache, key)).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+/// Caches `caps` for `key` in the `cache` directory, returning the file
+/// they were saved to.
+pub fn save(
+    cache: &Path,
+    key: &str,
+    caps: &Capabilities,
+) -> io::Result<PathBuf> {
+    let path = _path(cache, key);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(caps)?)?;
+    Ok(path)
+}
+
+/// Removes every cached result, returning how many there were.
+pub fn reset(cache: &Path) -> io::Result<usize> {
+    let dir = cache.join("terminal");
+    let count = match fs::read_dir(&dir) {
+        Ok(entries) => entries.count(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    fs::remove_dir_all(dir)?;
+    Ok(count)
+}
+
+/// Asks the controlling terminal what it supports, waiting at most
+/// `timeout` for the answers. Only queries terminals answer without side
+/// effects are sent: the cursor position after printing `╭` (erased right
+/// after), and the device attributes, which every terminal answers and so
+/// also marks the end of the answers.
+///
+/// Fails when there is no controlling terminal.
+#[cfg(unix)]
+pub fn probe(timeout: Duration) -> io::Result<Capabilities> {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+
+    let mut tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    let fd = tty.as_raw_fd();
+    // SAFETY: `termios` is plain data, filled in by `tcgetattr`.
+    let mut saved = unsafe { std::mem::zeroed::<libc::termios>() };
+    // SAFETY: `fd` is open for as long as `tty` lives.
+    if unsafe { libc::tcgetattr(fd, &mut saved) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut raw = saved;
+    // SAFETY: as above; the answers are read unbuffered and not echoed.
+    unsafe {
+        libc::cfmakeraw(&mut raw);
+        if libc::tcsetattr(fd, libc::TCSANOW, &raw) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    let answers = tty
+        .write_all("\r╭\x1b[6n\x1b[c".as_bytes())
+        .and_then(|_| _read_until(fd, b'c', timeout));
+    let _ = tty.write_all(b"\r\x1b[K");
+    // SAFETY: as above.
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &saved) };
+    Ok(_parse_answers(&answers?))
+}
+
+#[cfg(not(unix))]
+pub fn probe(_timeout: Duration) -> io::Result<Capabilities> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// What the terminal sent until `end`, or until `timeout` passed.
+#[cfg(unix)]
+fn _read_until(fd: i32, end: u8, timeout: Duration) -> io::Result<String> {
+    use std::time::Instant;
+
+    let deadline = Instant::now() + timeout;
+    let mut answers = Vec::new();
+    while !answers.ends_with(&[end]) {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let mut poll = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `poll` points to a single valid `pollfd`.
+        let ready =
+            unsafe { libc::poll(&mut poll, 1, left.as_millis() as i32) };
+        if ready <= 0 {
+            break;
+        }
+        let mut buf = [0u8; 64];
+        // SAFETY: `buf` is valid for `buf.len()` bytes.
+        let n = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+        if n <= 0 {
+            break;
+        }
+        answers.extend_from_slice(&buf[..n as usize]);
+    }
+    Ok(String::from_utf8_lossy(&answers).into_owned())
+}
+
+/// Reads the cursor position report (`ESC[row;colR`) and the device
+/// attributes (`ESC[?...c`) out of what the terminal answered.
+fn _parse_answers(answers: &str) -> Capabilities {
+    let mut caps = Capabilities::default();
+    for answer in answers.split('\x1b').filter_map(|a| a.strip_prefix('[')) {
+        if let Some(position) = answer.strip_suffix('R') {
+            let column = position.split(';').nth(1);
+            caps.unicode =
+                column.and_then(|c| c.parse::<u32>().ok()).map(|c| c == 2);
+        } else if answer.starts_with('?') && answer.ends_with('c') {
+            caps.device_attributes = Some(answer.to_string());
+        }
+    }
+    caps
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::terminal::{_parse_answers, cached, reset, save, Capabilities};
+
+    #[test]
+    fn test_parse_answers() {
+        let caps = _parse_answers("\x1b[12;2R\x1b[?62;22c");
+        assert_eq!(caps.unicode, Some(true));
+        assert_eq!(caps.device_attributes.as_deref(), Some("?62;22c"));
+        // `╭` counted as its three bytes.
+        assert_eq!(_parse_answers("\x1b[1;4R").unicode, Some(false));
+        assert_eq!(_parse_answers(""), Capabilities::default());
+    }
+
+    #[test]
+    fn test_cache() -> Result<()> {
+        let cache = tempfile::tempdir()?;
+        assert_eq!(cached(cache.path(), "xterm+"), None);
+        let caps = Capabilities {
+            unicode: Some(true),
+            device_attributes: None,
+        };
+        save(cache.path(), "xterm+", &caps)?;
+        assert_eq!(cached(cache.path(), "xterm+"), Some(caps));
+        assert_eq!(cached(cache.path(), "dumb+"), None);
+        assert_eq!(reset(cache.path())?, 1);
+        assert_eq!(cached(cache.path(), "xterm+"), None);
+        assert_eq!(reset(cache.path())?, 0);
+        Ok(())
+    }
+}
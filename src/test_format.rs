@@ -0,0 +1,176 @@
+/// Test runner output that `--test-format` understands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestFormat {
+    /// The Test Anything Protocol: `ok 1 - name`, `not ok 2 - name`.
+    Tap,
+    /// `cargo test`: `test name ... ok`, `test name ... FAILED`.
+    Cargo,
+    /// `pytest -v`: `file.py::name PASSED`, and the `FAILED file.py::name`
+    /// lines of the short summary.
+    Pytest,
+}
+
+/// The tests seen passing and failing in the output so far.
+#[derive(Clone, Debug)]
+pub struct TestResults {
+    pub format: TestFormat,
+    pub passed: usize,
+    pub failed: usize,
+    /// Names of the failed tests, in the order they were reported.
+    pub failures: Vec<String>,
+}
+
+impl TestResults {
+    pub(crate) fn new(format: TestFormat) -> Self {
+        Self {
+            format,
+            passed: 0,
+            failed: 0,
+            failures: vec![],
+        }
+    }
+
+    /// Counts the test reported by `line`, if any.
+    pub(crate) fn scan(&mut self, line: &str) {
+        let result = match self.format {
+            TestFormat::Tap => _tap(line),
+            TestFormat::Cargo => _cargo(line),
+            TestFormat::Pytest => _pytest(line),
+        };
+        match result {
+            Some((true, _)) => self.passed += 1,
+            // The summary of pytest repeats failures already reported.
+            Some((false, name))
+                if self.format == TestFormat::Pytest
+                    && self.failures.iter().any(|failure| failure == name) => {}
+            Some((false, name)) => {
+                self.failed += 1;
+                self.failures.push(name.into());
+            }
+            None => (),
+        }
+    }
+
+    /// The tally shown in the header, like `✓ 214  ✗ 3`.
+    pub(crate) fn label(&self) -> String {
+        format!("✓ {}  ✗ {}", self.passed, self.failed)
+    }
+}
+
+/// Whether `line` is a passing or failing TAP test point, and its name.
+/// Indented lines are subtests, counted through their parent.
+fn _tap(line: &str) -> Option<(bool, &str)> {
+    let (ok, rest) = match line.strip_prefix("not ok") {
+        Some(rest) => (false, rest),
+        None => (true, line.strip_prefix("ok")?),
+    };
+    if !(rest.is_empty() || rest.starts_with(' ')) {
+        return None;
+    }
+    let (description, directive) = rest.split_once(" # ").unwrap_or((rest, ""));
+    // Failures marked TODO are expected.
+    let todo = directive
+        .trim_start()
+        .to_ascii_uppercase()
+        .starts_with("TODO");
+    let description = description.trim();
+    let name = description
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .trim_start()
+        .trim_start_matches("- ")
+        .trim();
+    let name = if name.is_empty() { description } else { name };
+    Some((ok || todo, name))
+}
+
+fn _cargo(line: &str) -> Option<(bool, &str)> {
+    let (name, result) = line.strip_prefix("test ")?.rsplit_once(" ... ")?;
+    match result.trim_end() {
+        "ok" => Some((true, name)),
+        "FAILED" => Some((false, name)),
+        _ => None,
+    }
+}
+
+fn _pytest(line: &str) -> Option<(bool, &str)> {
+    let mut words = line.split_whitespace();
+    let (first, second) = (words.next()?, words.next()?);
+    match (first, second) {
+        (name, "PASSED") if name.contains("::") => Some((true, name)),
+        (name, "FAILED" | "ERROR") if name.contains("::") => {
+            Some((false, name))
+        }
+        ("FAILED" | "ERROR", name) if name.contains("::") => {
+            Some((false, name))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_format::{TestFormat, TestResults};
+
+    const TAP: &str = "\
+TAP version 13
+1..5
+ok 1 - parses input
+not ok 2 - handles empty file
+  ---
+  message: 'expected 0, got 1'
+  ...
+ok 3 # SKIP no network
+not ok 4 - unicode # TODO not yet
+    not ok 1 - subtest
+not ok 5
+# tests 5
+";
+
+    const CARGO: &str = "\
+running 3 tests
+test buffer::tests::test_push ... ok
+test lib::tests::test_cancel ... FAILED
+test lib::tests::test_slow ... ignored
+test result: FAILED. 1 passed; 1 failed; 1 ignored; 0 measured
+";
+
+    const PYTEST: &str = "\
+tests/test_io.py::test_read PASSED                              [ 33%]
+tests/test_io.py::test_write FAILED                             [ 66%]
+tests/test_io.py::test_seek[big] PASSED                         [100%]
+=========================== short test summary info ============================
+FAILED tests/test_io.py::test_write - AssertionError: assert 1 == 2
+ERROR tests/test_db.py::test_connect - ConnectionError
+========================= 1 failed, 2 passed in 0.12s ==========================
+";
+
+    fn scan(format: TestFormat, output: &str) -> TestResults {
+        let mut results = TestResults::new(format);
+        for line in output.lines() {
+            results.scan(line);
+        }
+        results
+    }
+
+    #[test]
+    fn test_formats() {
+        let tap = scan(TestFormat::Tap, TAP);
+        assert_eq!((tap.passed, tap.failed), (3, 2));
+        assert_eq!(tap.failures, ["handles empty file", "5"]);
+
+        let cargo = scan(TestFormat::Cargo, CARGO);
+        assert_eq!((cargo.passed, cargo.failed), (1, 1));
+        assert_eq!(cargo.failures, ["lib::tests::test_cancel"]);
+
+        let pytest = scan(TestFormat::Pytest, PYTEST);
+        assert_eq!((pytest.passed, pytest.failed), (2, 2));
+        assert_eq!(
+            pytest.failures,
+            [
+                "tests/test_io.py::test_write",
+                "tests/test_db.py::test_connect"
+            ]
+        );
+        assert_eq!(pytest.label(), "✓ 2  ✗ 2");
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use indicatif::HumanBytes;
+
+/// How far back the rate looks, so it follows recent speed rather than the
+/// average of the whole run.
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// What `--throughput` counts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThroughputUnit {
+    Lines,
+    /// Bytes of output, as decoded, counting line endings.
+    Bytes,
+}
+
+/// Output counted over the last [`RATE_WINDOW`].
+pub(crate) struct Throughput {
+    unit: ThroughputUnit,
+    started: Instant,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl Throughput {
+    pub(crate) fn new(unit: ThroughputUnit, now: Instant) -> Self {
+        Self {
+            unit,
+            started: now,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Counts `line`, printed at `now`.
+    pub(crate) fn add(&mut self, line: &str, now: Instant) {
+        let amount = match self.unit {
+            ThroughputUnit::Lines => 1,
+            ThroughputUnit::Bytes => line.len() as u64 + 1,
+        };
+        while self
+            .samples
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > RATE_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, amount));
+    }
+
+    /// Units per second over the window ending at `now`.
+    pub(crate) fn rate(&self, now: Instant) -> f64 {
+        let total = self
+            .samples
+            .iter()
+            .filter(|(at, _)| now.duration_since(*at) <= RATE_WINDOW)
+            .map(|(_, amount)| amount)
+            .sum::<u64>();
+        // Early on, the window is only as long as the run.
+        let span = now.duration_since(self.started).min(RATE_WINDOW);
+        total as f64 / span.as_secs_f64().max(0.001)
+    }
+
+    /// The rate at `now`, like `1.2k lines/s` or `3.40 MiB/s`.
+    pub(crate) fn label(&self, now: Instant) -> String {
+        let rate = self.rate(now);
+        match self.unit {
+            ThroughputUnit::Bytes => format!("{}/s", HumanBytes(rate as u64)),
+            ThroughputUnit::Lines if rate < 1e3 => format!("{rate:.0} lines/s"),
+            ThroughputUnit::Lines if rate < 1e6 => {
+                format!("{:.1}k lines/s", rate / 1e3)
+            }
+            ThroughputUnit::Lines => format!("{:.1}M lines/s", rate / 1e6),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::throughput::{Throughput, ThroughputUnit};
+
+    #[test]
+    fn test_rate_follows_recent_output() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut lines = Throughput::new(ThroughputUnit::Lines, start);
+        for _ in 0..2000 {
+            lines.add("x", at(1));
+        }
+        assert_eq!(lines.label(at(2)), "1.0k lines/s");
+        // Once the burst is out of the window, the rate drops.
+        lines.add("x", at(10));
+        assert_eq!(lines.label(at(10)), "0 lines/s");
+
+        let mut bytes = Throughput::new(ThroughputUnit::Bytes, start);
+        bytes.add(&"x".repeat(2047), at(1));
+        assert_eq!(bytes.label(at(2)), "1.00 KiB/s");
+    }
+}
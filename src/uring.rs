@@ -0,0 +1,129 @@
+use std::io::Read;
+
+/// The output pipe of the command, as read by its reader thread.
+///
+/// With the `io-uring` feature on Linux, each read goes through an io_uring
+/// submission queue instead of a `read` system call. A kernel without
+/// io_uring (or with it disabled) gets the plain pipe.
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub(crate) fn pipe<P>(pipe: P) -> Box<dyn Read + Send>
+where
+    P: Read + std::os::unix::io::AsRawFd + Send + 'static,
+{
+    match UringPipe::new(pipe) {
+        Ok(pipe) => Box::new(pipe),
+        Err((pipe, _)) => Box::new(pipe),
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+pub(crate) fn pipe<P>(pipe: P) -> Box<dyn Read + Send>
+where
+    P: Read + Send + 'static,
+{
+    Box::new(pipe)
+}
+
+/// A pipe read one [`io_uring::opcode::Read`] at a time, each filling a
+/// buffer as large as the pipe so that it serves several reads of the line
+/// reader. Reads aren't queued ahead: those in flight on the same pipe may
+/// complete out of order, and linking them breaks the chain on every short
+/// read, which is most of them on a pipe.
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub(crate) struct UringPipe<P> {
+    ring: io_uring::IoUring,
+    /// Kept open for as long as reads are submitted on it.
+    pipe: P,
+    buf: Box<[u8]>,
+    /// What is left to hand out of `buf`.
+    start: usize,
+    end: usize,
+}
+
+/// The default capacity of a Linux pipe, the most one read returns.
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+impl<P: std::os::unix::io::AsRawFd> UringPipe<P> {
+    /// Fails, giving `pipe` back, when the kernel has no io_uring.
+    pub(crate) fn new(pipe: P) -> Result<Self, (P, std::io::Error)> {
+        // Reads are submitted one at a time.
+        match io_uring::IoUring::new(2) {
+            Ok(ring) => Ok(Self {
+                ring,
+                pipe,
+                buf: vec![0; PIPE_CAPACITY].into_boxed_slice(),
+                start: 0,
+                end: 0,
+            }),
+            Err(e) => Err((pipe, e)),
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+impl<P: std::os::unix::io::AsRawFd> Read for UringPipe<P> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use std::io;
+
+        use io_uring::{opcode, types};
+
+        if self.start == self.end && !buf.is_empty() {
+            let fd = types::Fd(self.pipe.as_raw_fd());
+            let len = self.buf.len() as u32;
+            // Pipes have no offset: 0 reads from where they are.
+            let read =
+                opcode::Read::new(fd, self.buf.as_mut_ptr(), len).build();
+            // SAFETY: `self.buf` and the pipe outlive the read, which
+            // completes before this returns.
+            unsafe { self.ring.submission().push(&read) }.map_err(|_| {
+                io::Error::other("io_uring submission queue full")
+            })?;
+            self.ring.submit_and_wait(1)?;
+            let completion = self.ring.completion().next();
+            let result = completion.map_or(-libc::EIO, |c| c.result());
+            if result < 0 {
+                return Err(io::Error::from_raw_os_error(-result));
+            }
+            (self.start, self.end) = (0, result as usize);
+        }
+        let n = buf.len().min(self.end - self.start);
+        buf[..n].copy_from_slice(&self.buf[self.start..self.start + n]);
+        self.start += n;
+        Ok(n)
+    }
+}
+
+#[cfg(all(test, target_os = "linux", feature = "io-uring"))]
+mod tests {
+    use std::io::Read;
+    use std::process::{Command, Stdio};
+
+    use anyhow::Result;
+
+    use crate::uring::UringPipe;
+
+    #[test]
+    fn test_uring_pipe() -> Result<()> {
+        let mut child = Command::new("sh")
+            .args(["-c", "echo one; sleep 0.1; seq 10000; echo done"])
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().expect("piped");
+        let Ok(pipe) = UringPipe::new(stdout) else {
+            // Nothing to test without io_uring.
+            return Ok(());
+        };
+        // Smaller reads than the pipe's, served from its buffer.
+        let mut read = String::new();
+        std::io::BufReader::with_capacity(100, pipe)
+            .read_to_string(&mut read)?;
+        child.wait()?;
+        let lines: Vec<_> = read.lines().collect();
+        assert_eq!(lines.len(), 10_002);
+        assert_eq!((lines[0], lines[10_000]), ("one", "10000"));
+        assert_eq!(lines[10_001], "done");
+        Ok(())
+    }
+}
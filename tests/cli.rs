@@ -0,0 +1,34 @@
+use std::process::Command;
+
+use anyhow::Result;
+
+#[test]
+fn test_invalid_options_run_nothing() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let marker = dir.path().join("ran");
+    let output = Command::new(env!("CARGO_BIN_EXE_runner"))
+        .args(["--plain", "--stdin-file"])
+        .arg(dir.path().join("missing"))
+        .args(["--", "sh", "-c", "touch \"$0\""])
+        .arg(&marker)
+        .output()?;
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--stdin-file"), "{stderr}");
+    assert!(!marker.exists());
+    Ok(())
+}
+
+#[test]
+fn test_exit_status() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    for (script, code) in [("exit 3", 3), ("kill -9 $$", 137)] {
+        let output = Command::new(env!("CARGO_BIN_EXE_runner"))
+            .args(["--plain", "--output-dir"])
+            .arg(dir.path())
+            .args(["--", "sh", "-c", script])
+            .output()?;
+        assert_eq!(output.status.code(), Some(code), "{script}");
+    }
+    Ok(())
+}